@@ -0,0 +1,6 @@
+//! Frames with valid CRCs, through fragment reassembly and the decoding of what they carry.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| turret_device_fuzz::receive_frames(data));
@@ -0,0 +1,6 @@
+//! Arbitrary bytes off the bus, through deframing and the CRC check.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| turret_device_fuzz::receive_stream(data));
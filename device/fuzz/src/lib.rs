@@ -0,0 +1,156 @@
+//! Harnesses feeding arbitrary input through the device's receive path, as the fuzz targets and
+//! this crate's tests do. Each one panics only if the receive path does.
+//!
+//! The first byte of the input picks the settings to receive with, see [`Settings`], the rest is
+//! what arrives over the bus.
+
+pub use turret_device::codec::Codec;
+use turret_device::codec::PREFERENCE;
+use turret_device::crc::SoftwareCrc;
+use turret_device::rx::{Received, Receiver};
+use turret_device::LinkConfig;
+use turret_protocol::crc::crc32;
+use turret_protocol::hello::{Hello, FULL_CRC};
+
+/// The firmware's defaults for `TURRET_BUF_SIZE` and `TURRET_MAX_MESSAGE_SIZE`.
+pub const BUF_SIZE: usize = 64;
+pub const MAX_MESSAGE_SIZE: usize = 256;
+
+/// The firmware's receiver, at its default sizes.
+pub type DeviceReceiver = Receiver<{ BUF_SIZE - 1 }, MAX_MESSAGE_SIZE>;
+
+/// This board's address, frames for any other are ignored.
+pub const ADDRESS: u8 = 1;
+
+/// What the device receives with, picked by the input's first byte:
+/// bit 0 selects postcard over CBOR, bit 2 JSON over both, bit 1 full CRCs.
+#[derive(Debug, Clone, Copy)]
+pub struct Settings {
+    pub codec: Codec,
+    pub link: LinkConfig,
+}
+
+impl Settings {
+    pub fn from_byte(byte: u8) -> Self {
+        let mut link = LinkConfig::new(ADDRESS, BUF_SIZE);
+        link.full_crc = byte & 0b10 != 0;
+        Self {
+            codec: if byte & 0b100 != 0 {
+                Codec::Json
+            } else if byte & 0b001 != 0 {
+                Codec::Postcard
+            } else {
+                Codec::Cbor
+            },
+            link,
+        }
+    }
+}
+
+/// The device's side of the link: the receiver, and the settings a hello or a node address
+/// request may change.
+pub struct Device {
+    receiver: DeviceReceiver,
+    settings: Settings,
+    crc: SoftwareCrc,
+}
+
+impl Device {
+    pub fn new(settings: Settings) -> Self {
+        Self {
+            receiver: DeviceReceiver::new(),
+            settings,
+            crc: SoftwareCrc::new(),
+        }
+    }
+
+    /// Receives `bytes`, handling what they hold like the firmware does, short of replying.
+    /// Returns how many hellos, node addresses and requests they completed.
+    pub fn receive(&mut self, bytes: &[u8]) -> usize {
+        let mut handled = 0;
+        for &byte in bytes {
+            let received = self.receiver.push(
+                byte,
+                &self.settings.codec,
+                &self.settings.link,
+                &mut self.crc,
+            );
+            match received {
+                Some(Ok(Received::Hello { offer, .. })) => {
+                    self.agree(&offer);
+                    handled += 1;
+                }
+                Some(Ok(Received::NodeAddress { requested, .. })) => {
+                    if let Some(moved) = requested.address {
+                        self.settings.link.address = moved;
+                    }
+                    handled += 1;
+                }
+                Some(Ok(Received::Request { request, fresh, .. })) => {
+                    self.receiver.acknowledge(&request, fresh);
+                    handled += 1;
+                }
+                _ => {}
+            }
+        }
+        handled
+    }
+
+    /// Whether CRCs currently cover the last partial word, see `LinkConfig::full_crc`.
+    pub fn full_crc(&self) -> bool {
+        self.settings.link.full_crc
+    }
+
+    fn agree(&mut self, offer: &Hello) {
+        let supported = Hello {
+            min_version: turret_protocol::envelope::MIN_PROTOCOL_VERSION,
+            max_version: turret_protocol::envelope::PROTOCOL_VERSION,
+            codecs: PREFERENCE.iter().fold(0, |codecs, codec| codecs | codec),
+            flags: FULL_CRC,
+            max_frame: BUF_SIZE as u16,
+        };
+        if let Some(agreed) = supported.negotiate(offer, PREFERENCE) {
+            // NOTE(unwrap): negotiating picks one of the codecs preferred.
+            self.settings.codec = Codec::from_hello_bit(agreed.codecs).unwrap();
+            self.settings.link.apply(&agreed);
+        }
+    }
+}
+
+/// Feeds `data` to the device as raw bytes off the bus. Almost nothing random passes the CRC,
+/// so this exercises deframing and the CRC check.
+pub fn receive_stream(data: &[u8]) {
+    let (&settings, bytes) = match data.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    Device::new(Settings::from_byte(settings)).receive(bytes);
+}
+
+/// Feeds `data` to the device as frames carrying valid CRCs, so fragment reassembly and the
+/// decoding of what they carry are exercised. After the settings byte, each frame is a length
+/// byte and that many bytes of contents, to which the CRC is appended before COBS encoding.
+pub fn receive_frames(data: &[u8]) {
+    let (&settings, mut rest) = match data.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    let mut device = Device::new(Settings::from_byte(settings));
+    while let Some((&len, tail)) = rest.split_first() {
+        let (contents, tail) = tail.split_at((len as usize).min(tail.len()));
+        rest = tail;
+        let full_crc = device.full_crc();
+        device.receive(&frame(contents, full_crc));
+    }
+}
+
+/// Appends the CRC to `contents`, COBS encodes the result and terminates it with a sentinel.
+pub fn frame(contents: &[u8], full_crc: bool) -> Vec<u8> {
+    let mut decoded = contents.to_vec();
+    decoded.extend_from_slice(&crc32(contents, full_crc).to_be_bytes());
+    let mut encoded = vec![0; postcard_cobs::max_encoding_length(decoded.len())];
+    let len = postcard_cobs::encode(&decoded, &mut encoded);
+    encoded.truncate(len);
+    encoded.push(0x00);
+    encoded
+}
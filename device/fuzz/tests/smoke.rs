@@ -0,0 +1,101 @@
+//! Runs the fuzz harnesses over pseudo-random and mutated input, so a plain `cargo test` catches
+//! regressions without libFuzzer.
+
+use turret_device::LinkConfig;
+use turret_device_fuzz::{
+    frame, receive_frames, receive_stream, Codec, Device, Settings, ADDRESS, BUF_SIZE,
+};
+use turret_protocol::datamodel::log_level::LogLevel;
+use turret_protocol::datamodel::request::{Request, RequestKind};
+use turret_protocol::datamodel::serial_config::SerialConfig;
+use turret_protocol::envelope::{encode, Message};
+use turret_protocol::fragment::{fragment, max_chunk_len};
+
+/// xorshift32, so every run sees the same input.
+struct Rng(u32);
+
+impl Rng {
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+
+    fn bytes(&mut self, max_len: usize) -> Vec<u8> {
+        let len = self.next() as usize % max_len;
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+}
+
+/// The contents of the frames carrying a request, CRCs not yet appended.
+fn request_frames(codec: Codec) -> Vec<Vec<u8>> {
+    let request = Request {
+        kind: RequestKind::SerialConfig,
+        log_level: Some(LogLevel::Warn),
+        serial: Some(SerialConfig::DEFAULT),
+        seq: Some(9),
+    };
+    let mut buffer = [0u8; 256];
+    let len = encode(&codec, &Message::Request(request), Some(4), &mut buffer).unwrap();
+    fragment(&buffer[..len], 0, max_chunk_len(BUF_SIZE))
+        .unwrap()
+        .map(|(header, chunk)| {
+            let mut contents = vec![ADDRESS];
+            contents.extend_from_slice(&header.to_bytes());
+            contents.extend_from_slice(chunk);
+            contents
+        })
+        .collect()
+}
+
+fn receive_all(device: &mut Device, frames: &[Vec<u8>]) -> usize {
+    frames
+        .iter()
+        .map(|contents| device.receive(&frame(contents, false)))
+        .sum()
+}
+
+fn settings(codec: Codec) -> Settings {
+    Settings {
+        codec,
+        link: LinkConfig::new(ADDRESS, BUF_SIZE),
+    }
+}
+
+#[test]
+fn valid_requests_are_handled() {
+    for codec in Codec::ALL {
+        let mut device = Device::new(settings(codec));
+        assert_eq!(receive_all(&mut device, &request_frames(codec)), 1);
+    }
+}
+
+#[test]
+fn random_input_never_panics() {
+    let mut rng = Rng(0x2545_f491);
+    for _ in 0..20_000 {
+        let data = rng.bytes(256);
+        receive_stream(&data);
+        receive_frames(&data);
+    }
+}
+
+#[test]
+fn mutated_requests_never_panic() {
+    let mut rng = Rng(0x9e37_79b9);
+    for codec in Codec::ALL {
+        let valid = request_frames(codec);
+        for _ in 0..5_000 {
+            let mut frames = valid.clone();
+            for _ in 0..=rng.next() % 4 {
+                let contents = &mut frames[rng.next() as usize % valid.len()];
+                let i = rng.next() as usize % contents.len();
+                contents[i] = rng.next() as u8;
+            }
+            let last = frames.last_mut().unwrap();
+            last.truncate(rng.next() as usize % (last.len() + 1));
+            receive_all(&mut Device::new(settings(codec)), &frames);
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! Buffers the turret's position, sampled at a fixed rate, into batches sent as one message.
+//!
+//! The sampler pushes a sample every [`SAMPLE_PERIOD_US`], and the publisher takes a batch every
+//! period of the `TelemetryBatch` topic. A sample that finds the buffer full is dropped, and the
+//! next batch counts it in [`dropped`](TelemetryBatch::dropped).
+
+use core::convert::TryFrom;
+use core::mem;
+
+use heapless::Vec;
+use turret_protocol::datamodel::telemetry_batch::TelemetryBatch;
+
+/// Microseconds between samples, 1 kHz.
+pub const SAMPLE_PERIOD_US: u32 = 1_000;
+
+/// The samples taken since the last batch was sent.
+pub struct Batcher {
+    /// Whether samples are buffered at all, only while the topic is subscribed to.
+    running: bool,
+    /// The batch being filled.
+    filling: Option<TelemetryBatch>,
+    /// A batch that was full, or ended by a move too big for a delta, waiting to be sent.
+    sealed: Option<TelemetryBatch>,
+    /// Count of the last sample buffered.
+    last_pos: u32,
+    /// Samples dropped since the last batch started.
+    dropped: u32,
+}
+
+impl Batcher {
+    pub const fn new() -> Self {
+        Self {
+            running: false,
+            filling: None,
+            sealed: None,
+            last_pos: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Starts buffering samples from scratch.
+    pub fn start(&mut self) {
+        *self = Self {
+            running: true,
+            ..Self::new()
+        };
+    }
+
+    /// Stops buffering samples, and forgets those buffered.
+    pub fn stop(&mut self) {
+        *self = Self::new();
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Buffers the sample taken at `now_ms`, at encoder count `pos`.
+    pub fn push(&mut self, now_ms: u32, pos: u32) {
+        if !self.running {
+            return;
+        }
+        if let Some(batch) = &mut self.filling {
+            // counts wrap around, so a small move across the wrap is a small delta.
+            let delta = i8::try_from(pos.wrapping_sub(self.last_pos) as i32);
+            if let Ok(delta) = delta {
+                if batch.deltas.push(delta).is_ok() {
+                    self.last_pos = pos;
+                    return;
+                }
+            }
+            // this sample starts the next batch, if there's room to set this one aside.
+            if self.sealed.is_some() {
+                self.dropped = self.dropped.saturating_add(1);
+                return;
+            }
+            self.sealed = self.filling.take();
+        }
+        self.filling = Some(TelemetryBatch {
+            start_ms: now_ms,
+            period_us: SAMPLE_PERIOD_US,
+            first_pos: pos,
+            deltas: Vec::new(),
+            dropped: mem::take(&mut self.dropped),
+        });
+        self.last_pos = pos;
+    }
+
+    /// The next batch to send, the one set aside if any, otherwise the samples taken since the
+    /// last batch.
+    pub fn take(&mut self) -> Option<TelemetryBatch> {
+        self.sealed.take().or_else(|| self.filling.take())
+    }
+}
+
+impl Default for Batcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use turret_protocol::datamodel::telemetry_batch::BATCH_LEN;
+
+    fn running() -> Batcher {
+        let mut batcher = Batcher::new();
+        batcher.start();
+        batcher
+    }
+
+    fn samples(batch: &TelemetryBatch) -> std::vec::Vec<(u32, u32)> {
+        batch.samples().collect()
+    }
+
+    #[test]
+    fn samples_are_delta_encoded() {
+        let mut batcher = running();
+        for (now, pos) in [(10, u32::MAX - 1), (11, u32::MAX), (12, 2), (13, 1)] {
+            batcher.push(now, pos);
+        }
+        let batch = batcher.take().unwrap();
+        assert_eq!(batch.first_pos, u32::MAX - 1);
+        assert_eq!(batch.deltas, [1, 3, -1]);
+        assert_eq!(
+            samples(&batch),
+            [(10, u32::MAX - 1), (11, u32::MAX), (12, 2), (13, 1)]
+        );
+        assert_eq!(batcher.take(), None);
+    }
+
+    #[test]
+    fn a_move_too_big_for_a_delta_starts_a_batch() {
+        let mut batcher = running();
+        for (now, pos) in [(0, 1_000), (1, 1_001), (2, 0), (3, 1)] {
+            batcher.push(now, pos);
+        }
+        assert_eq!(samples(&batcher.take().unwrap()), [(0, 1_000), (1, 1_001)]);
+        let batch = batcher.take().unwrap();
+        assert_eq!(samples(&batch), [(2, 0), (3, 1)]);
+        assert_eq!(batch.dropped, 0);
+    }
+
+    #[test]
+    fn samples_finding_the_buffer_full_are_counted() {
+        let mut batcher = running();
+        // two full batches, then five more samples.
+        for now in 0..2 * BATCH_LEN as u32 + 5 {
+            batcher.push(now, now);
+        }
+        let first = batcher.take().unwrap();
+        assert_eq!(first.deltas.len(), BATCH_LEN - 1);
+        assert_eq!(first.dropped, 0);
+
+        let now = 2 * BATCH_LEN as u32 + 5;
+        batcher.push(now, now);
+        let second = batcher.take().unwrap();
+        assert_eq!(second.start_ms, BATCH_LEN as u32);
+        assert_eq!(second.dropped, 0);
+        let third = batcher.take().unwrap();
+        assert_eq!(samples(&third), [(now, now)]);
+        assert_eq!(third.dropped, 5);
+    }
+
+    #[test]
+    fn samples_are_only_buffered_while_running() {
+        let mut batcher = Batcher::new();
+        batcher.push(0, 0);
+        assert_eq!(batcher.take(), None);
+        batcher.start();
+        batcher.push(1, 1);
+        batcher.stop();
+        assert!(!batcher.is_running());
+        assert_eq!(batcher.take(), None);
+    }
+}
@@ -0,0 +1,123 @@
+//! The firmware's payload encodings, shared with the fuzz targets so they decode with the same
+//! crates the board does.
+//!
+//! Each codec is compiled in by its cargo feature, `codec-cbor`, `codec-postcard` and
+//! `codec-json`, and this module only with at least one of them.
+
+use serde::{Deserialize, Serialize};
+use turret_protocol::envelope::WireCodec;
+use turret_protocol::hello::{CODEC_CBOR, CODEC_JSON, CODEC_POSTCARD};
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Codec {
+    Cbor = 0,
+    Postcard = 1,
+    Json = 2,
+}
+
+#[derive(Debug)]
+pub enum CodecError {
+    /// The codec isn't compiled in.
+    Unsupported(Codec),
+    FailedSerialize,
+    FailedDeserialize,
+}
+
+/// Hello bits of the enabled codecs, in order of preference.
+pub const PREFERENCE: &[u8] = &[
+    #[cfg(feature = "codec-cbor")]
+    CODEC_CBOR,
+    #[cfg(feature = "codec-postcard")]
+    CODEC_POSTCARD,
+    #[cfg(feature = "codec-json")]
+    CODEC_JSON,
+];
+
+impl Codec {
+    pub const ALL: [Codec; 3] = [Codec::Cbor, Codec::Postcard, Codec::Json];
+
+    /// The codec's bit in a hello.
+    pub fn hello_bit(self) -> u8 {
+        match self {
+            Codec::Cbor => CODEC_CBOR,
+            Codec::Postcard => CODEC_POSTCARD,
+            Codec::Json => CODEC_JSON,
+        }
+    }
+
+    /// The codec a hello's bit stands for.
+    pub fn from_hello_bit(bit: u8) -> Option<Self> {
+        match bit {
+            CODEC_CBOR => Some(Codec::Cbor),
+            CODEC_POSTCARD => Some(Codec::Postcard),
+            CODEC_JSON => Some(Codec::Json),
+            _ => None,
+        }
+    }
+
+    /// Encodes `value` into `buffer`, returning the number of bytes written.
+    pub fn serialize<T: Serialize>(
+        self,
+        value: &T,
+        buffer: &mut [u8],
+    ) -> Result<usize, CodecError> {
+        match self {
+            #[cfg(feature = "codec-cbor")]
+            Codec::Cbor => {
+                use serde_cbor::ser::{Serializer, SliceWrite};
+                let mut serializer = Serializer::new(SliceWrite::new(buffer));
+                value
+                    .serialize(&mut serializer)
+                    .map_err(|_| CodecError::FailedSerialize)?;
+                Ok(serializer.into_inner().bytes_written())
+            }
+            #[cfg(feature = "codec-postcard")]
+            Codec::Postcard => postcard::to_slice(value, buffer)
+                .map(|used| used.len())
+                .map_err(|_| CodecError::FailedSerialize),
+            #[cfg(feature = "codec-json")]
+            Codec::Json => {
+                serde_json_core::to_slice(value, buffer).map_err(|_| CodecError::FailedSerialize)
+            }
+            #[allow(unreachable_patterns)]
+            unsupported => Err(CodecError::Unsupported(unsupported)),
+        }
+    }
+
+    /// Decodes a `T` from `buffer`.
+    /// The buffer needs to be mutable as an implementation detail of CBOR.
+    pub fn deserialize<'a, T: Deserialize<'a>>(
+        self,
+        buffer: &'a mut [u8],
+    ) -> Result<T, CodecError> {
+        match self {
+            #[cfg(feature = "codec-cbor")]
+            Codec::Cbor => {
+                serde_cbor::de::from_mut_slice(buffer).map_err(|_| CodecError::FailedDeserialize)
+            }
+            #[cfg(feature = "codec-postcard")]
+            Codec::Postcard => {
+                postcard::from_bytes(buffer).map_err(|_| CodecError::FailedDeserialize)
+            }
+            #[cfg(feature = "codec-json")]
+            Codec::Json => serde_json_core::from_slice(buffer)
+                .map(|(value, _)| value)
+                .map_err(|_| CodecError::FailedDeserialize),
+            #[allow(unreachable_patterns)]
+            unsupported => Err(CodecError::Unsupported(unsupported)),
+        }
+    }
+}
+
+impl WireCodec for Codec {
+    type Error = CodecError;
+
+    fn serialize<T: Serialize>(&self, value: &T, buffer: &mut [u8]) -> Result<usize, CodecError> {
+        Codec::serialize(*self, value, buffer)
+    }
+
+    fn deserialize<'a, T: Deserialize<'a>>(&self, buffer: &'a mut [u8]) -> Result<T, CodecError> {
+        Codec::deserialize(*self, buffer)
+    }
+}
@@ -0,0 +1,82 @@
+//! Reports the crash record preserved across the last reset.
+
+use turret_protocol::datamodel::crash_record::CrashRecordPacket;
+use turret_protocol::envelope::{Message, WireCodec};
+
+use crate::tx::encode;
+
+/// Shortens `packet`'s message until the reply, encoded with `codec`, fits `buffer`, which is
+/// sized to the largest message sent. The crash record's message alone may take more than that,
+/// once escaped by text codecs.
+///
+/// If even an empty message doesn't fit, the packet is returned without one, and sending it
+/// fails as it would have anyway.
+pub fn fit<'a, C: WireCodec>(
+    codec: &C,
+    mut packet: CrashRecordPacket<'a>,
+    request_id: Option<u8>,
+    buffer: &mut [u8],
+) -> CrashRecordPacket<'a> {
+    let full = packet.msg;
+    let mut end = full.len();
+    loop {
+        packet.msg = &full[..end];
+        if end == 0 || encode(codec, &Message::CrashRecord(packet), request_id, buffer).is_ok() {
+            return packet;
+        }
+        // drop the last character, the message only ever shortens by whole characters.
+        end = full[..end]
+            .char_indices()
+            .last()
+            .map_or(0, |(start, _)| start);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::Json;
+    use turret_protocol::datamodel::crash_record::CrashKind;
+
+    fn panic(msg: &str) -> CrashRecordPacket<'_> {
+        CrashRecordPacket {
+            kind: CrashKind::Panic,
+            file: "src/tasks/usart1_rx.rs",
+            line: 212,
+            pc: 0,
+            lr: 0,
+            cfsr: 0,
+            hfsr: 0,
+            mmfar: 0,
+            bfar: 0,
+            msg,
+        }
+    }
+
+    #[test]
+    fn records_that_fit_are_sent_whole() {
+        let msg = "called `Option::unwrap()` on a `None` value";
+        assert_eq!(fit(&Json, panic(msg), Some(1), &mut [0; 256]).msg, msg);
+    }
+
+    #[test]
+    fn messages_are_shortened_to_fit() {
+        // every quote is escaped, so the message takes twice its length.
+        let msg = "\"\u{e9}".repeat(48);
+        let mut buffer = [0; 256];
+        let fitted = fit(&Json, panic(&msg), Some(1), &mut buffer);
+        assert!(msg.starts_with(fitted.msg));
+        assert!(fitted.msg.len() < msg.len());
+        let len = encode(&Json, &Message::CrashRecord(fitted), Some(1), &mut buffer)
+            .unwrap()
+            .len();
+        // each character takes two bytes, one more wouldn't have fit.
+        assert!(len + 2 > buffer.len());
+    }
+
+    #[test]
+    fn records_that_never_fit_lose_their_whole_message() {
+        let fitted = fit(&Json, panic("boom"), None, &mut [0; 16]);
+        assert_eq!(fitted.msg, "");
+    }
+}
@@ -0,0 +1,82 @@
+//! Checksums frames with a [`Crc`] unit.
+
+use core::convert::TryInto;
+
+use turret_protocol::crc::{update, INITIAL};
+
+use crate::hal::Crc;
+
+/// Computes the CRC-32/MPEG-2 of `data`.
+///
+/// The unit only takes whole words, so by default `data` is truncated to the nearest word
+/// boundary. Once a host agreed on full CRCs, the last partial word is fed padded with zeros
+/// instead.
+pub fn checksum(crc: &mut impl Crc, data: &[u8], full: bool) -> u32 {
+    crc.reset();
+    let (words, tail) = data.split_at(data.len() / 4 * 4);
+    let mut result = INITIAL;
+    for word in words.chunks_exact(4) {
+        // NOTE(unwrap): chunks_exact only yields whole words.
+        result = crc.update(u32::from_be_bytes(word.try_into().unwrap()));
+    }
+    if full && !tail.is_empty() {
+        let mut last = [0u8; 4];
+        last[..tail.len()].copy_from_slice(tail);
+        result = crc.update(u32::from_be_bytes(last));
+    }
+    result
+}
+
+/// A [`Crc`] unit in software, for running the task logic without the peripheral.
+#[derive(Debug, Clone, Copy)]
+pub struct SoftwareCrc {
+    crc: u32,
+}
+
+impl SoftwareCrc {
+    pub const fn new() -> Self {
+        Self { crc: INITIAL }
+    }
+}
+
+impl Default for SoftwareCrc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Crc for SoftwareCrc {
+    fn reset(&mut self) {
+        self.crc = INITIAL;
+    }
+
+    fn update(&mut self, word: u32) -> u32 {
+        // the peripheral takes the word's most significant byte first.
+        self.crc = word
+            .to_be_bytes()
+            .iter()
+            .fold(self.crc, |crc, &byte| update(crc, byte));
+        self.crc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use turret_protocol::crc::crc32;
+
+    #[test]
+    fn matches_the_protocol_crate() {
+        let mut crc = SoftwareCrc::new();
+        for data in [
+            &b""[..],
+            b"1234",
+            b"12345678",
+            b"123456789",
+            b"\x01\x00\x00\x01\x01",
+        ] {
+            assert_eq!(checksum(&mut crc, data, false), crc32(data, false));
+            assert_eq!(checksum(&mut crc, data, true), crc32(data, true));
+        }
+    }
+}
@@ -0,0 +1,33 @@
+//! What the task logic needs from the hardware.
+
+use turret_protocol::datamodel::telemetry_packet::TurretDirection;
+
+/// The quadrature encoder following the turret.
+pub trait Encoder {
+    /// The turret's position, in encoder counts.
+    fn count(&self) -> u32;
+    /// Which way the turret last turned.
+    fn direction(&self) -> TurretDirection;
+}
+
+/// A CRC-32/MPEG-2 unit fed 32 bit words, like the STM32's CRC peripheral.
+pub trait Crc {
+    /// Starts over from [`INITIAL`](turret_protocol::crc::INITIAL).
+    fn reset(&mut self);
+    /// Feeds a word, returning the CRC of every word fed since the reset.
+    fn update(&mut self, word: u32) -> u32;
+}
+
+/// A millisecond clock.
+pub trait Clock {
+    /// Milliseconds since some fixed point in the past, wrapping around.
+    fn now_ms(&self) -> u32;
+}
+
+/// The serial port frames are sent on, one at a time.
+pub trait SerialTx {
+    /// Whether a frame is still being sent.
+    fn is_busy(&self) -> bool;
+    /// Starts sending `frame`, sentinel included. Only called while not busy.
+    fn start(&mut self, frame: &[u8]);
+}
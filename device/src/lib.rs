@@ -0,0 +1,149 @@
+//! The turret monitor firmware's task logic, free of the hardware it runs on.
+//!
+//! Queueing, framing and checksumming what the device sends, and deframing, reassembling and
+//! deduplicating what it receives, only touch the hardware through the traits in [`hal`].
+//! The firmware implements them with the STM32's peripherals, and the simulator in `host/sim`
+//! with a pseudo-terminal and a scripted encoder, so both run the same logic.
+#![cfg_attr(not(test), no_std)]
+
+use turret_protocol::hello::{Hello, FULL_CRC};
+
+pub mod batch;
+#[cfg(any(
+    feature = "codec-cbor",
+    feature = "codec-postcard",
+    feature = "codec-json"
+))]
+pub mod codec;
+pub mod crash;
+pub mod crc;
+pub mod hal;
+pub mod rx;
+pub mod schedule;
+pub mod telemetry;
+pub mod tx;
+
+/// How this end frames and checksums, which a hello may change.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinkConfig {
+    /// This board's address on the bus, see `turret_protocol::address`.
+    pub address: u8,
+    /// Size of the frames sent, COBS overhead and sentinel included.
+    pub frame_size: usize,
+    /// Whether CRCs cover the last partial word of a frame, see `turret_protocol::hello`.
+    pub full_crc: bool,
+}
+
+impl LinkConfig {
+    /// The settings until a host agrees on others.
+    pub const fn new(address: u8, frame_size: usize) -> Self {
+        Self {
+            address,
+            frame_size,
+            full_crc: false,
+        }
+    }
+
+    /// Switches to the frame size and CRCs a hello agreed on.
+    pub fn apply(&mut self, agreed: &Hello) {
+        self.frame_size = agreed.max_frame as usize;
+        self.full_crc = agreed.flags & FULL_CRC != 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use turret_protocol::crc::crc32;
+    use turret_protocol::datamodel::request::{Request, RequestKind};
+    use turret_protocol::envelope::{Message, WireCodec};
+    use turret_protocol::fragment::{fragment, max_chunk_len};
+
+    use crate::crc::SoftwareCrc;
+    use crate::hal::SerialTx;
+    use crate::tx::{encode, Priority, TxQueue};
+    use crate::LinkConfig;
+
+    /// The bench codec, with std's serde_json.
+    pub struct Json;
+
+    impl WireCodec for Json {
+        type Error = serde_json::Error;
+
+        fn serialize<T: Serialize>(
+            &self,
+            value: &T,
+            buffer: &mut [u8],
+        ) -> Result<usize, Self::Error> {
+            let json = serde_json::to_vec(value)?;
+            buffer
+                .get_mut(..json.len())
+                .ok_or_else(|| serde::ser::Error::custom("buffer too small"))?
+                .copy_from_slice(&json);
+            Ok(json.len())
+        }
+
+        fn deserialize<'a, T: Deserialize<'a>>(
+            &self,
+            buffer: &'a mut [u8],
+        ) -> Result<T, Self::Error> {
+            serde_json::from_slice(buffer)
+        }
+    }
+
+    /// A serial port that sends frames instantly.
+    #[derive(Default)]
+    pub struct Wire {
+        pub sent: Vec<u8>,
+    }
+
+    impl SerialTx for Wire {
+        fn is_busy(&self) -> bool {
+            false
+        }
+
+        fn start(&mut self, frame: &[u8]) {
+            self.sent.extend_from_slice(frame);
+        }
+    }
+
+    pub fn request(kind: RequestKind, seq: Option<u8>) -> Message<'static> {
+        Message::Request(Request {
+            kind,
+            log_level: None,
+            serial: None,
+            seq,
+        })
+    }
+
+    /// The frames a host sends `message` to the board at `address` as.
+    pub fn requests(message: &Message, request_id: Option<u8>, address: u8) -> Vec<u8> {
+        let mut buffer = [0u8; 256];
+        let message = encode(&Json, message, request_id, &mut buffer).unwrap();
+        let mut sent = Vec::new();
+        for (header, chunk) in fragment(message, 0, max_chunk_len(128)).unwrap() {
+            let mut contents = vec![address];
+            contents.extend_from_slice(&header.to_bytes());
+            contents.extend_from_slice(chunk);
+            contents.extend_from_slice(&crc32(&contents, false).to_be_bytes());
+            let mut frame = [0u8; 128];
+            let len = postcard_cobs::encode(&contents, &mut frame);
+            sent.extend_from_slice(&frame[..len]);
+            sent.push(0);
+        }
+        sent
+    }
+
+    /// The frames `message` is sent as from `link`.
+    pub fn frames(message: &Message, request_id: Option<u8>, link: &LinkConfig) -> Vec<u8> {
+        let mut buffer = [0u8; 256];
+        let message = encode(&Json, message, request_id, &mut buffer).unwrap();
+        let mut queue: TxQueue<128, 8> = TxQueue::new();
+        queue
+            .push(message, Priority::Reply, link, &mut SoftwareCrc::new())
+            .unwrap();
+        let mut wire = Wire::default();
+        while queue.start_next(&mut wire).is_some() {}
+        wire.sent
+    }
+}
@@ -0,0 +1,376 @@
+//! Turns the bytes received into the requests they carry.
+
+use core::convert::TryInto;
+
+use turret_protocol::address::{self, ADDRESS_LEN};
+use turret_protocol::datamodel::ack::AckPacket;
+use turret_protocol::datamodel::error::ErrorCode;
+use turret_protocol::datamodel::node_address::NodeAddress;
+use turret_protocol::datamodel::request::Request;
+use turret_protocol::datamodel::subscription::Subscription;
+use turret_protocol::deframer::{DeframeError, Deframer};
+use turret_protocol::envelope::{
+    self, EnvelopeHeader, Message, WireCodec, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+use turret_protocol::fragment::{FragmentError, FragmentHeader, Reassembler, CRC_LEN, HEADER_LEN};
+use turret_protocol::hello::Hello;
+use turret_protocol::reliable::{Delivery, DuplicateFilter};
+
+use crate::crc::checksum;
+use crate::hal::Crc;
+use crate::LinkConfig;
+
+#[derive(Debug)]
+pub enum RxError {
+    CobsDecoderError,
+    FrameTooShort,
+    InvalidSenderCrc,
+    FailedTelemetrySpawn,
+    FailedReplySpawn,
+    FailedDeserialize,
+    /// The message wasn't a request, hello, subscription or node address, or of a protocol
+    /// version this firmware doesn't speak.
+    Unsupported,
+    BufferOverflow,
+    /// A node address request asked for an address no board can have.
+    InvalidAddress,
+    /// The frame's fragment couldn't be added to a message.
+    Fragment(FragmentError),
+}
+
+/// What a complete frame held.
+#[derive(Debug)]
+pub enum Received {
+    /// A frame for another board on the bus, or a reply from any board, this one included.
+    Ignored,
+    /// A fragment of a message whose other fragments are still to come.
+    Fragment,
+    /// A hello, understood at any protocol version.
+    Hello {
+        request_id: Option<u8>,
+        offer: Hello,
+    },
+    /// A request to answer. Retransmissions of reliable requests that were already handled
+    /// aren't `fresh`: they are answered all the same, but their side effects are skipped.
+    Request {
+        request_id: Option<u8>,
+        request: Request,
+        fresh: bool,
+    },
+    /// A subscription to start, change or stop streaming a topic.
+    Subscription {
+        request_id: Option<u8>,
+        requested: Subscription,
+    },
+    /// A request for this board's address, or to move it to `requested.address`, which is a
+    /// valid node address.
+    NodeAddress {
+        request_id: Option<u8>,
+        requested: NodeAddress,
+    },
+    /// A message that can't be handled, which the host is told about with `code`.
+    Rejected {
+        request_id: Option<u8>,
+        code: ErrorCode,
+        error: RxError,
+    },
+}
+
+/// Deframes the bytes received into frames of at most `N` bytes, and reassembles their
+/// fragments into messages of at most `M` bytes.
+pub struct Receiver<const N: usize, const M: usize> {
+    deframer: Deframer<N>,
+    reassembler: Reassembler<M>,
+    duplicates: DuplicateFilter,
+}
+
+impl<const N: usize, const M: usize> Receiver<N, M> {
+    pub const fn new() -> Self {
+        Self {
+            deframer: Deframer::new(),
+            reassembler: Reassembler::new(),
+            duplicates: DuplicateFilter::new(),
+        }
+    }
+
+    /// Feeds a received byte. Once it completes a frame, checks the frame's CRC and adds the
+    /// fragment it carries to the message being reassembled, returning what that frame held.
+    /// Partial frames are kept until the rest arrives.
+    pub fn push<C: WireCodec>(
+        &mut self,
+        byte: u8,
+        codec: &C,
+        link: &LinkConfig,
+        crc: &mut impl Crc,
+    ) -> Option<Result<Received, RxError>> {
+        let frame = match self.deframer.push(byte)? {
+            Ok(frame) => frame,
+            Err(DeframeError::Overflow) => return Some(Err(RxError::BufferOverflow)),
+            Err(DeframeError::Cobs) => return Some(Err(RxError::CobsDecoderError)),
+        };
+        let fragment = match verify(frame, link, crc) {
+            Ok(Some(fragment)) => fragment,
+            Ok(None) => return Some(Ok(Received::Ignored)),
+            Err(e) => return Some(Err(e)),
+        };
+        let (header, chunk) = match FragmentHeader::split(fragment) {
+            Ok(split) => split,
+            Err(e) => return Some(Err(RxError::Fragment(e))),
+        };
+        let received = match self.reassembler.push(header, chunk) {
+            Ok(Some(message)) => unwrap(message, codec, &self.duplicates),
+            // wait for the rest of the message.
+            Ok(None) => Ok(Received::Fragment),
+            Err(e) => Err(RxError::Fragment(e)),
+        };
+        Some(received)
+    }
+
+    /// Records that a reliable request was handled, so its retransmissions aren't executed
+    /// again, and returns its acknowledgement. Requests that failed aren't recorded, so the
+    /// host retransmits them.
+    pub fn acknowledge(&mut self, request: &Request, fresh: bool) -> Option<AckPacket> {
+        let seq = request.seq?;
+        self.duplicates.record(seq);
+        Some(AckPacket {
+            seq,
+            duplicate: !fresh,
+        })
+    }
+
+    /// Forgets partially received frames and messages, and the requests already handled.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Forgets partially received frames and messages, but not the requests already handled.
+    /// Used when the line's settings change, since bytes received at the old ones are garbage.
+    pub fn discard_partial(&mut self) {
+        self.deframer = Deframer::new();
+        self.reassembler = Reassembler::new();
+    }
+}
+
+impl<const N: usize, const M: usize> Default for Receiver<N, M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Checks the CRC of a decoded frame, and returns the fragment it carries if it is for this
+/// board.
+fn verify<'a>(
+    frame: &'a mut [u8],
+    link: &LinkConfig,
+    crc: &mut impl Crc,
+) -> Result<Option<&'a mut [u8]>, RxError> {
+    let n = frame.len();
+    // need at least an address and a fragment header besides the CRC.
+    if n < ADDRESS_LEN + HEADER_LEN + CRC_LEN {
+        return Err(RxError::FrameTooShort);
+    }
+    let (data, crc_bytes) = frame.split_at_mut(n - CRC_LEN);
+    // NOTE(unwrap): the split left exactly CRC_LEN bytes.
+    let sender_crc = u32::from_be_bytes((&*crc_bytes).try_into().unwrap());
+    if sender_crc != checksum(crc, data, link.full_crc) {
+        return Err(RxError::InvalidSenderCrc);
+    }
+    // NOTE(unwrap): the length was checked above.
+    let (destination, _) = address::split(data).unwrap();
+    if !address::accepts(link.address, destination) {
+        return Ok(None);
+    }
+    Ok(Some(&mut data[ADDRESS_LEN..]))
+}
+
+/// Unwraps a complete message from its envelope, and tells whether a reliable request is a
+/// retransmission.
+fn unwrap<C: WireCodec>(
+    message: &mut [u8],
+    codec: &C,
+    duplicates: &DuplicateFilter,
+) -> Result<Received, RxError> {
+    let request_id = EnvelopeHeader::split(message).and_then(|(header, _)| header.request_id);
+    let speaks = |version| (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version);
+    let request = match envelope::decode(codec, message) {
+        Ok((_, Message::Hello(offer))) => return Ok(Received::Hello { request_id, offer }),
+        Ok((header, Message::Request(request))) if speaks(header.version) => request,
+        Ok((header, Message::Subscription(requested))) if speaks(header.version) => {
+            return Ok(Received::Subscription {
+                request_id,
+                requested,
+            })
+        }
+        Ok((header, Message::NodeAddress(requested))) if speaks(header.version) => {
+            if matches!(requested.address, Some(moved) if !address::is_node(moved)) {
+                return Ok(Received::Rejected {
+                    request_id,
+                    code: ErrorCode::Malformed,
+                    error: RxError::InvalidAddress,
+                });
+            }
+            return Ok(Received::NodeAddress {
+                request_id,
+                requested,
+            });
+        }
+        Ok(_) => {
+            return Ok(Received::Rejected {
+                request_id,
+                code: ErrorCode::Unsupported,
+                error: RxError::Unsupported,
+            })
+        }
+        Err(_) => {
+            return Ok(Received::Rejected {
+                request_id,
+                code: ErrorCode::Malformed,
+                error: RxError::FailedDeserialize,
+            })
+        }
+    };
+    let delivery = match request.seq {
+        Some(seq) => duplicates.check(seq),
+        None => Delivery::New,
+    };
+    Ok(Received::Request {
+        request_id,
+        request,
+        fresh: delivery == Delivery::New,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crc::SoftwareCrc;
+    use crate::tests::{frames, request, requests, Json};
+    use turret_protocol::datamodel::request::RequestKind;
+
+    const LINK: LinkConfig = LinkConfig::new(1, 128);
+
+    fn receive(receiver: &mut Receiver<128, 256>, bytes: &[u8]) -> Vec<Received> {
+        let mut crc = SoftwareCrc::new();
+        bytes
+            .iter()
+            .filter_map(|&byte| receiver.push(byte, &Json, &LINK, &mut crc))
+            .map(|received| received.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn requests_are_deduplicated_once_acknowledged() {
+        let mut receiver = Receiver::new();
+        let sent = requests(&request(RequestKind::Zero, Some(7)), Some(3), 1);
+        let received = receive(&mut receiver, &sent);
+        let (request, fresh) = match received.as_slice() {
+            [Received::Request {
+                request_id: Some(3),
+                request,
+                fresh,
+            }] => (request, *fresh),
+            other => panic!("unexpected {:?}", other),
+        };
+        assert!(fresh);
+        let ack = receiver.acknowledge(request, fresh).unwrap();
+        assert_eq!((ack.seq, ack.duplicate), (7, false));
+
+        let received = receive(&mut receiver, &sent);
+        assert!(matches!(
+            received.as_slice(),
+            [Received::Request { fresh: false, .. }]
+        ));
+    }
+
+    #[test]
+    fn partial_frames_can_be_discarded() {
+        let mut receiver: Receiver<128, 256> = Receiver::new();
+        let sent = requests(&request(RequestKind::Zero, Some(7)), Some(3), 1);
+        let (first, rest) = sent.split_at(sent.len() / 2);
+        assert!(receive(&mut receiver, first).is_empty());
+        receiver.discard_partial();
+        // what's left of the frame doesn't make a request on its own.
+        let mut crc = SoftwareCrc::new();
+        let results: Vec<_> = rest
+            .iter()
+            .filter_map(|&byte| receiver.push(byte, &Json, &LINK, &mut crc))
+            .collect();
+        assert!(results.iter().all(Result::is_err));
+
+        let received = receive(&mut receiver, &sent);
+        match received.as_slice() {
+            [Received::Request { request, fresh, .. }] => {
+                receiver.acknowledge(request, *fresh);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        receiver.discard_partial();
+        // requests already handled are still recognized.
+        let received = receive(&mut receiver, &sent);
+        assert!(matches!(
+            received.as_slice(),
+            [Received::Request { fresh: false, .. }]
+        ));
+    }
+
+    #[test]
+    fn other_boards_frames_are_ignored() {
+        let sent = requests(&request(RequestKind::Telemetry, None), None, 2);
+        let received = receive(&mut Receiver::new(), &sent);
+        assert!(matches!(received.as_slice(), [Received::Ignored]));
+    }
+
+    #[test]
+    fn replies_are_ignored() {
+        // another board's, or our own echoed back by a half-duplex transceiver.
+        for address in [1, 2] {
+            let sent = frames(
+                &request(RequestKind::Telemetry, None),
+                None,
+                &LinkConfig::new(address, 128),
+            );
+            let received = receive(&mut Receiver::new(), &sent);
+            assert!(matches!(received.as_slice(), [Received::Ignored]));
+        }
+    }
+
+    #[test]
+    fn node_addresses_are_checked() {
+        let mut receiver = Receiver::new();
+        let valid = NodeAddress { address: Some(5) };
+        let sent = requests(&Message::NodeAddress(valid), Some(2), address::BROADCAST);
+        assert!(matches!(
+            receive(&mut receiver, &sent).as_slice(),
+            [Received::NodeAddress { request_id: Some(2), requested }] if *requested == valid
+        ));
+        let invalid = NodeAddress {
+            address: Some(address::BROADCAST),
+        };
+        let sent = requests(&Message::NodeAddress(invalid), Some(3), 1);
+        assert!(matches!(
+            receive(&mut receiver, &sent).as_slice(),
+            [Received::Rejected {
+                code: ErrorCode::Malformed,
+                error: RxError::InvalidAddress,
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn corrupted_frames_are_reported() {
+        let mut sent = requests(&request(RequestKind::Telemetry, None), None, 1);
+        // a byte of the payload, which COBS left as it was.
+        sent[10] ^= 0x01;
+        let mut receiver: Receiver<128, 256> = Receiver::new();
+        let mut crc = SoftwareCrc::new();
+        let results: Vec<_> = sent
+            .iter()
+            .filter_map(|&byte| receiver.push(byte, &Json, &LINK, &mut crc))
+            .collect();
+        assert!(matches!(
+            results.as_slice(),
+            [Err(RxError::InvalidSenderCrc)]
+        ));
+    }
+}
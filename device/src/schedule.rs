@@ -0,0 +1,355 @@
+//! Decides when to send each topic the host subscribed to, within the link's bandwidth.
+//!
+//! Each topic is charged for the frames carrying its largest packet, every period. Subscriptions
+//! may take up to [`BANDWIDTH_SHARE_PERCENT`] of what the line carries, the rest is left to
+//! replies and log frames, and a period that would exceed it is lengthened until it fits.
+
+use turret_protocol::datamodel::link_errors::LinkErrorsPacket;
+use turret_protocol::datamodel::serial_config::{Parity, SerialConfig, StopBits};
+use turret_protocol::datamodel::subscription::{Subscription, Topic};
+use turret_protocol::datamodel::telemetry_batch::{TelemetryBatch, BATCH_LEN};
+use turret_protocol::datamodel::telemetry_packet::{TurretDirection, TurretTelemetryPacket};
+use turret_protocol::envelope::{Message, WireCodec};
+use turret_protocol::fragment::max_chunk_len;
+
+use crate::batch::SAMPLE_PERIOD_US;
+use crate::tx::encode;
+use crate::LinkConfig;
+
+/// Shortest period a topic is sent at, and how often the scheduler should be polled.
+pub const MIN_PERIOD_MS: u32 = 10;
+/// Longest period a topic is sent at, a subscription that only fits less often is refused.
+pub const MAX_PERIOD_MS: u32 = 60_000;
+/// Longest period batches are sent at: the time a batch takes to fill up. The batcher holds a
+/// second batch, so one sent late doesn't lose samples either.
+pub const MAX_BATCH_PERIOD_MS: u32 = BATCH_LEN as u32 * SAMPLE_PERIOD_US / 1000;
+/// Share of the line's bandwidth all subscriptions together may take.
+pub const BANDWIDTH_SHARE_PERCENT: u32 = 50;
+
+/// A topic being sent.
+#[derive(Debug, Clone, Copy)]
+struct Stream {
+    period_ms: u32,
+    /// When the topic is next sent.
+    due_ms: u32,
+    /// Bytes each message of the topic takes on the wire, at most.
+    wire_bytes: u32,
+}
+
+impl Stream {
+    /// Bytes per second the topic takes on the wire, rounded up.
+    fn load(&self) -> u32 {
+        div_ceil(u64::from(self.wire_bytes) * 1000, u64::from(self.period_ms))
+    }
+}
+
+/// The topics subscribed to, and when each is due.
+pub struct Scheduler {
+    streams: [Option<Stream>; Topic::ALL.len()],
+}
+
+impl Scheduler {
+    pub const fn new() -> Self {
+        Self {
+            streams: [None; Topic::ALL.len()],
+        }
+    }
+
+    /// Starts, changes or stops sending a topic, and returns the subscription granted.
+    ///
+    /// `wire_bytes` is what each of the topic's messages takes on the wire, see [`wire_bytes`],
+    /// and `line_rate` the bytes per second the line carries, see [`line_rate`]. A topic that is
+    /// subscribed to is sent right away, then every period. Periods longer than the topic can
+    /// wait without losing data are shortened, see [`max_period_ms`].
+    pub fn subscribe(
+        &mut self,
+        requested: Subscription,
+        wire_bytes: u32,
+        line_rate: u32,
+        now_ms: u32,
+    ) -> Subscription {
+        let topic = requested.topic;
+        self.streams[topic as usize] = None;
+        let refused = Subscription {
+            topic,
+            period_ms: 0,
+        };
+        if requested.period_ms == 0 {
+            return refused;
+        }
+
+        let budget = (u64::from(line_rate) * u64::from(BANDWIDTH_SHARE_PERCENT) / 100) as u32;
+        let used: u32 = self.streams.iter().flatten().map(Stream::load).sum();
+        let available = budget.saturating_sub(used);
+        if available == 0 {
+            return refused;
+        }
+        let fastest = div_ceil(u64::from(wire_bytes) * 1000, u64::from(available));
+        let longest = max_period_ms(topic);
+        let period_ms = requested
+            .period_ms
+            .min(longest)
+            .max(fastest)
+            .max(MIN_PERIOD_MS);
+        if period_ms > longest {
+            return refused;
+        }
+
+        self.streams[topic as usize] = Some(Stream {
+            period_ms,
+            due_ms: now_ms,
+            wire_bytes,
+        });
+        Subscription { topic, period_ms }
+    }
+
+    /// The topic to send now, if any is due. Several may be due at once, the most overdue is
+    /// returned first, so poll until none is left.
+    ///
+    /// A topic that fell more than a period behind skips the periods it missed, rather than
+    /// being sent back to back to catch up.
+    pub fn next_due(&mut self, now_ms: u32) -> Option<Topic> {
+        let (index, stream) = self
+            .streams
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(index, stream)| Some((index, stream.as_mut()?)))
+            .filter(|(_, stream)| is_reached(stream.due_ms, now_ms))
+            .max_by_key(|(_, stream)| now_ms.wrapping_sub(stream.due_ms))?;
+        stream.due_ms = stream.due_ms.wrapping_add(stream.period_ms);
+        if is_reached(stream.due_ms, now_ms) {
+            stream.due_ms = now_ms.wrapping_add(stream.period_ms);
+        }
+        Some(Topic::ALL[index])
+    }
+
+    /// The period `topic` is sent at, if it is subscribed to.
+    pub fn period_ms(&self, topic: Topic) -> Option<u32> {
+        self.streams[topic as usize].map(|stream| stream.period_ms)
+    }
+
+    /// Stops sending every topic.
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Longest period `topic` can be sent at: batches must be sent before the samples overflow the
+/// batcher, other topics are only bounded by [`MAX_PERIOD_MS`].
+pub fn max_period_ms(topic: Topic) -> u32 {
+    match topic {
+        Topic::TelemetryBatch => MAX_BATCH_PERIOD_MS,
+        Topic::Telemetry | Topic::LinkErrors => MAX_PERIOD_MS,
+    }
+}
+
+/// Whether the clock reached `due_ms`, across wrap-arounds.
+fn is_reached(due_ms: u32, now_ms: u32) -> bool {
+    now_ms.wrapping_sub(due_ms) < u32::MAX / 2
+}
+
+/// `dividend / divisor` rounded up, saturating at `u32::MAX`.
+fn div_ceil(dividend: u64, divisor: u64) -> u32 {
+    dividend.div_ceil(divisor).min(u64::from(u32::MAX)) as u32
+}
+
+/// Bytes per second a line carries at `config`: each byte takes a start bit, 8 data bits, the
+/// parity bit if there is one, and the stop bits.
+pub fn line_rate(config: &SerialConfig) -> u32 {
+    let parity = match config.parity {
+        Parity::None => 0,
+        Parity::Even | Parity::Odd => 1,
+    };
+    let stop = match config.stop_bits {
+        StopBits::One => 1,
+        StopBits::Two => 2,
+    };
+    config.baud / (1 + 8 + parity + stop)
+}
+
+/// Bytes each of `topic`'s messages takes on the wire at most, with `codec` and `link`: as many
+/// whole frames as its largest packet is split into. `buffer` is sized to the largest message
+/// sent, and `None` is returned if the packet can't be encoded into it.
+pub fn wire_bytes<C: WireCodec>(
+    codec: &C,
+    topic: Topic,
+    link: &LinkConfig,
+    buffer: &mut [u8],
+) -> Option<u32> {
+    let message = encode(codec, &largest(topic), None, buffer).ok()?;
+    let chunk_len = max_chunk_len(link.frame_size);
+    let frames = message.len().div_ceil(chunk_len);
+    Some((frames * link.frame_size) as u32)
+}
+
+/// `topic`'s packet with every field at its longest.
+fn largest(topic: Topic) -> Message<'static> {
+    match topic {
+        Topic::Telemetry => Message::Telemetry(TurretTelemetryPacket {
+            turret_pos: u32::MAX,
+            turret_rot: TurretDirection::Backward,
+        }),
+        Topic::LinkErrors => Message::LinkErrors(LinkErrorsPacket {
+            overrun: u32::MAX,
+            framing: u32::MAX,
+            noise: u32::MAX,
+            parity: u32::MAX,
+            dma_transfer: u32::MAX,
+            dma_fifo: u32::MAX,
+            dma_direct_mode: u32::MAX,
+            rx_restarts: u32::MAX,
+            tx_timeouts: u32::MAX,
+        }),
+        Topic::TelemetryBatch => Message::TelemetryBatch(TelemetryBatch {
+            start_ms: u32::MAX,
+            period_us: u32::MAX,
+            first_pos: u32::MAX,
+            deltas: [i8::MIN; BATCH_LEN - 1].iter().copied().collect(),
+            dropped: u32::MAX,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::Json;
+
+    /// 115200 8N1.
+    const LINE_RATE: u32 = 11_520;
+
+    fn subscription(topic: Topic, period_ms: u32) -> Subscription {
+        Subscription { topic, period_ms }
+    }
+
+    #[test]
+    fn line_rate_counts_every_bit() {
+        assert_eq!(line_rate(&SerialConfig::DEFAULT), LINE_RATE);
+        let config = SerialConfig {
+            baud: 9_600,
+            parity: Parity::Even,
+            stop_bits: StopBits::Two,
+        };
+        assert_eq!(line_rate(&config), 800);
+    }
+
+    #[test]
+    fn periods_are_lengthened_to_fit_the_bandwidth() {
+        let mut scheduler = Scheduler::new();
+        // 64 bytes every 10ms would be 6400 B/s, more than half the line.
+        let granted = scheduler.subscribe(subscription(Topic::Telemetry, 10), 64, LINE_RATE, 0);
+        assert_eq!(granted, subscription(Topic::Telemetry, 12));
+        // what's left of the share only carries the other topic every 301ms.
+        let granted = scheduler.subscribe(subscription(Topic::LinkErrors, 100), 128, LINE_RATE, 0);
+        assert_eq!(granted, subscription(Topic::LinkErrors, 301));
+    }
+
+    #[test]
+    fn subscriptions_that_dont_fit_are_refused() {
+        let mut scheduler = Scheduler::new();
+        scheduler.subscribe(subscription(Topic::Telemetry, 10), 64, 800, 0);
+        let granted = scheduler.subscribe(subscription(Topic::LinkErrors, 10), 64, 800, 0);
+        assert_eq!(granted.period_ms, 0);
+        assert_eq!(scheduler.period_ms(Topic::LinkErrors), None);
+    }
+
+    #[test]
+    fn batches_are_sent_before_the_batcher_overflows() {
+        let mut scheduler = Scheduler::new();
+        let granted = scheduler.subscribe(
+            subscription(Topic::TelemetryBatch, 1_000),
+            192,
+            LINE_RATE,
+            0,
+        );
+        assert_eq!(granted, subscription(Topic::TelemetryBatch, 64));
+        // other topics may still wait longer.
+        let granted = scheduler.subscribe(subscription(Topic::Telemetry, 1_000), 64, LINE_RATE, 0);
+        assert_eq!(granted, subscription(Topic::Telemetry, 1_000));
+        // a line that can't carry a batch every 64ms would lose samples, so it's refused.
+        let granted = scheduler.subscribe(subscription(Topic::TelemetryBatch, 50), 192, 4_800, 0);
+        assert_eq!(granted.period_ms, 0);
+        assert_eq!(scheduler.period_ms(Topic::TelemetryBatch), None);
+    }
+
+    #[test]
+    fn a_period_of_zero_unsubscribes() {
+        let mut scheduler = Scheduler::new();
+        scheduler.subscribe(subscription(Topic::Telemetry, 100), 64, LINE_RATE, 0);
+        let granted = scheduler.subscribe(subscription(Topic::Telemetry, 0), 64, LINE_RATE, 0);
+        assert_eq!(granted.period_ms, 0);
+        assert_eq!(scheduler.next_due(1_000), None);
+    }
+
+    #[test]
+    fn topics_are_multiplexed_most_overdue_first() {
+        let mut scheduler = Scheduler::new();
+        scheduler.subscribe(subscription(Topic::Telemetry, 20), 32, LINE_RATE, 0);
+        scheduler.subscribe(subscription(Topic::LinkErrors, 50), 32, LINE_RATE, 5);
+        let mut sent = Vec::new();
+        for now in (0..=100).step_by(10) {
+            while let Some(topic) = scheduler.next_due(now) {
+                sent.push((now, topic));
+            }
+        }
+        use Topic::{LinkErrors, Telemetry};
+        assert_eq!(
+            sent,
+            [
+                (0, Telemetry),
+                (10, LinkErrors),
+                (20, Telemetry),
+                (40, Telemetry),
+                (60, LinkErrors),
+                (60, Telemetry),
+                (80, Telemetry),
+                (100, Telemetry),
+            ]
+        );
+    }
+
+    #[test]
+    fn missed_periods_are_skipped() {
+        let mut scheduler = Scheduler::new();
+        scheduler.subscribe(subscription(Topic::Telemetry, 10), 32, LINE_RATE, 0);
+        assert_eq!(scheduler.next_due(0), Some(Topic::Telemetry));
+        assert_eq!(scheduler.next_due(55), Some(Topic::Telemetry));
+        assert_eq!(scheduler.next_due(55), None);
+        assert_eq!(scheduler.next_due(65), Some(Topic::Telemetry));
+    }
+
+    #[test]
+    fn topics_are_charged_whole_frames() {
+        let link = LinkConfig::new(1, 32);
+        let buffer = &mut [0; 256];
+        assert_eq!(wire_bytes(&Json, Topic::Telemetry, &link, buffer), Some(96));
+        // nine counters of ten digits each, with their names.
+        assert_eq!(
+            wire_bytes(&Json, Topic::LinkErrors, &link, buffer),
+            Some(320)
+        );
+        // a full batch doesn't fit a message with JSON.
+        assert_eq!(
+            wire_bytes(&Json, Topic::TelemetryBatch, &link, buffer),
+            None
+        );
+    }
+
+    #[test]
+    fn topics_are_sized_for_the_largest_message() {
+        let link = LinkConfig::new(1, 32);
+        // too small for the link errors' counters, which would then never be sent.
+        let buffer = &mut [0; 128];
+        assert_eq!(wire_bytes(&Json, Topic::Telemetry, &link, buffer), Some(96));
+        assert_eq!(wire_bytes(&Json, Topic::LinkErrors, &link, buffer), None);
+        // large enough for a full batch.
+        let buffer = &mut [0; 1024];
+        assert!(wire_bytes(&Json, Topic::TelemetryBatch, &link, buffer).is_some());
+    }
+}
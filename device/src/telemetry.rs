@@ -0,0 +1,13 @@
+//! Samples the turret's position.
+
+use turret_protocol::datamodel::telemetry_packet::TurretTelemetryPacket;
+
+use crate::hal::Encoder;
+
+/// Reads the turret's current position and direction off the encoder.
+pub fn sample(encoder: &impl Encoder) -> TurretTelemetryPacket {
+    TurretTelemetryPacket {
+        turret_pos: encoder.count(),
+        turret_rot: encoder.direction(),
+    }
+}
@@ -0,0 +1,317 @@
+//! Queues, frames and checksums what the device sends.
+
+use turret_protocol::address::{self, ADDRESS_LEN};
+use turret_protocol::envelope::{self, Message, WireCodec};
+use turret_protocol::fragment::{fragment, max_chunk_len, FragmentHeader, CRC_LEN, HEADER_LEN};
+
+use heapless::spsc::Queue;
+
+use crate::crc::checksum;
+use crate::hal::{Crc, SerialTx};
+use crate::LinkConfig;
+
+#[derive(Debug)]
+pub enum TxError {
+    FailedSerialize,
+    PayloadTooLarge(usize),
+    /// The queue for this priority is full, the frame was dropped.
+    QueueFull(Priority),
+}
+
+/// Order in which queued frames are sent, most important first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Priority {
+    /// Replies to host requests, error replies included.
+    Reply,
+    /// Streaming telemetry. When its queue is full the oldest frame is dropped, it's stale anyway.
+    Telemetry,
+    /// Forwarded log records.
+    Log,
+}
+
+/// A COBS encoded frame of at most `N` bytes, ready to be sent.
+#[derive(Clone, Copy)]
+pub struct Frame<const N: usize> {
+    bytes: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> Frame<N> {
+    pub const fn new() -> Self {
+        Self {
+            bytes: [0; N],
+            len: 0,
+        }
+    }
+
+    /// The frame as sent, sentinel included.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+impl<const N: usize> Default for Frame<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Wraps `message` in an envelope, echoing `request_id` if it replies to a request.
+/// Returns the encoded message, anything bigger than `buffer` fails.
+pub fn encode<'a, C: WireCodec>(
+    codec: &C,
+    message: &Message,
+    request_id: Option<u8>,
+    buffer: &'a mut [u8],
+) -> Result<&'a [u8], TxError> {
+    match envelope::encode(codec, message, request_id, buffer) {
+        Ok(size) => Ok(&buffer[..size]),
+        Err(_) => Err(TxError::FailedSerialize),
+    }
+}
+
+/// Frames of at most `N` bytes waiting to be sent, one queue of capacity `DEPTH` per
+/// [`Priority`].
+pub struct TxQueue<const N: usize, const DEPTH: usize> {
+    reply: Queue<Frame<N>, DEPTH>,
+    telemetry: Queue<Frame<N>, DEPTH>,
+    log: Queue<Frame<N>, DEPTH>,
+    /// ID of the next message, so the host can tell consecutive messages' fragments apart.
+    next_message_id: u8,
+    /// Transfers started so far, tells a timeout which transfer it was scheduled for.
+    started: u32,
+}
+
+impl<const N: usize, const DEPTH: usize> TxQueue<N, DEPTH> {
+    pub const fn new() -> Self {
+        Self {
+            reply: Queue::new(),
+            telemetry: Queue::new(),
+            log: Queue::new(),
+            next_message_id: 0,
+            started: 0,
+        }
+    }
+
+    /// Splits an encoded message into fragments, then queues a frame per fragment at
+    /// `priority`. Each frame holds `link`'s address, the fragment's header and chunk, followed
+    /// by their CRC, COBS encoded.
+    ///
+    /// The message is queued whole or not at all.
+    pub fn push(
+        &mut self,
+        message: &[u8],
+        priority: Priority,
+        link: &LinkConfig,
+        crc: &mut impl Crc,
+    ) -> Result<(), TxError> {
+        let message_id = self.next_message_id;
+        self.next_message_id = self.next_message_id.wrapping_add(1);
+        let chunk_len = max_chunk_len(link.frame_size.min(N));
+        let fragments = fragment(message, message_id, chunk_len)
+            .map_err(|_| TxError::PayloadTooLarge(message.len()))?;
+        self.reserve(priority, fragments.len())?;
+        for (header, chunk) in fragments {
+            let frame = encode_frame(header, chunk, link, crc);
+            self.queue(priority)
+                .enqueue(frame)
+                .map_err(|_| TxError::QueueFull(priority))?;
+        }
+        Ok(())
+    }
+
+    fn queue(&mut self, priority: Priority) -> &mut Queue<Frame<N>, DEPTH> {
+        match priority {
+            Priority::Reply => &mut self.reply,
+            Priority::Telemetry => &mut self.telemetry,
+            Priority::Log => &mut self.log,
+        }
+    }
+
+    /// Makes room for `frames` frames at `priority`.
+    fn reserve(&mut self, priority: Priority, frames: usize) -> Result<(), TxError> {
+        if priority == Priority::Telemetry {
+            // the host discards the rest of a message whose first fragments are dropped.
+            while self.telemetry.capacity() - self.telemetry.len() < frames
+                && self.telemetry.dequeue().is_some()
+            {}
+        }
+        let queue = self.queue(priority);
+        if queue.capacity() - queue.len() < frames {
+            return Err(TxError::QueueFull(priority));
+        }
+        Ok(())
+    }
+
+    /// Drops the frames waiting at `priority`, returning how many there were.
+    pub fn discard(&mut self, priority: Priority) -> usize {
+        let queue = self.queue(priority);
+        let mut discarded = 0;
+        while queue.dequeue().is_some() {
+            discarded += 1;
+        }
+        discarded
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.reply.is_empty() && self.telemetry.is_empty() && self.log.is_empty()
+    }
+
+    /// Starts sending the most important queued frame, unless `serial` is still busy.
+    ///
+    /// Returns the number of the transfer started, for [`stalled`](Self::stalled) to tell
+    /// whether it ever completed.
+    pub fn start_next(&mut self, serial: &mut impl SerialTx) -> Option<u32> {
+        if serial.is_busy() {
+            // the serial port comes back for the queue once this transfer completes.
+            return None;
+        }
+        let frame = self
+            .reply
+            .dequeue()
+            .or_else(|| self.telemetry.dequeue())
+            .or_else(|| self.log.dequeue())?;
+        self.started = self.started.wrapping_add(1);
+        serial.start(frame.as_bytes());
+        Some(self.started)
+    }
+
+    /// Whether `transfer` is still being sent. Asked once it has taken too long, a transfer
+    /// that is stalled had its completion missed.
+    pub fn stalled(&self, serial: &impl SerialTx, transfer: u32) -> bool {
+        self.started == transfer && serial.is_busy()
+    }
+}
+
+impl<const N: usize, const DEPTH: usize> Default for TxQueue<N, DEPTH> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Builds the frame carrying one fragment of a message.
+fn encode_frame<const N: usize>(
+    header: FragmentHeader,
+    chunk: &[u8],
+    link: &LinkConfig,
+    crc: &mut impl Crc,
+) -> Frame<N> {
+    let mut fragment_buffer = [0u8; N];
+    let chunk_start = ADDRESS_LEN + HEADER_LEN;
+    let fragment_size = chunk_start + chunk.len();
+    fragment_buffer[0] = address::reply(link.address);
+    fragment_buffer[ADDRESS_LEN..chunk_start].copy_from_slice(&header.to_bytes());
+    fragment_buffer[chunk_start..fragment_size].copy_from_slice(chunk);
+
+    // append the CRC32 to the end.
+    let checksum = checksum(crc, &fragment_buffer[..fragment_size], link.full_crc);
+    fragment_buffer[fragment_size..fragment_size + CRC_LEN]
+        .copy_from_slice(&checksum.to_be_bytes());
+
+    // the chunk length leaves room for COBS' overhead and the sentinel.
+    let mut frame = Frame::new();
+    let encoded_size = postcard_cobs::encode(
+        &fragment_buffer[..fragment_size + CRC_LEN],
+        &mut frame.bytes,
+    );
+    // the sentinel is already in place, since the frame starts zeroed.
+    frame.len = encoded_size + 1;
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crc::SoftwareCrc;
+    use crate::tests::{frames, request, Json, Wire};
+    use turret_protocol::datamodel::request::RequestKind;
+    use turret_protocol::hello::MIN_FRAME_SIZE;
+
+    /// A serial port that never finishes sending.
+    struct Stuck(usize);
+
+    impl SerialTx for Stuck {
+        fn is_busy(&self) -> bool {
+            self.0 > 0
+        }
+
+        fn start(&mut self, _frame: &[u8]) {
+            self.0 += 1;
+        }
+    }
+
+    fn queued(
+        queue: &mut TxQueue<128, 4>,
+        priority: Priority,
+        link: &LinkConfig,
+    ) -> Result<(), TxError> {
+        let mut buffer = [0u8; 256];
+        let message = encode(
+            &Json,
+            &request(RequestKind::Telemetry, None),
+            None,
+            &mut buffer,
+        )?;
+        queue.push(message, priority, link, &mut SoftwareCrc::new())
+    }
+
+    #[test]
+    fn frames_fit_the_agreed_size() {
+        let mut link = LinkConfig::new(1, MIN_FRAME_SIZE);
+        let sent = frames(&request(RequestKind::Telemetry, Some(1)), None, &link);
+        let lens: Vec<_> = sent.split_inclusive(|&b| b == 0).map(<[u8]>::len).collect();
+        assert!(lens.len() > 1);
+        assert!(lens.iter().all(|&len| len <= MIN_FRAME_SIZE));
+
+        link.frame_size = 128;
+        let sent = frames(&request(RequestKind::Telemetry, Some(1)), None, &link);
+        assert_eq!(sent.iter().filter(|&&b| b == 0).count(), 1);
+    }
+
+    #[test]
+    fn replies_go_first_and_stale_telemetry_makes_room() {
+        let link = LinkConfig::new(1, 128);
+        let mut queue = TxQueue::new();
+        // each queue holds DEPTH - 1 frames.
+        for _ in 0..3 {
+            queued(&mut queue, Priority::Telemetry, &link).unwrap();
+        }
+        queued(&mut queue, Priority::Telemetry, &link).unwrap();
+        queued(&mut queue, Priority::Log, &link).unwrap();
+        queued(&mut queue, Priority::Log, &link).unwrap();
+        queued(&mut queue, Priority::Log, &link).unwrap();
+        assert!(matches!(
+            queued(&mut queue, Priority::Log, &link),
+            Err(TxError::QueueFull(Priority::Log))
+        ));
+        queued(&mut queue, Priority::Reply, &link).unwrap();
+
+        let mut wire = Wire::default();
+        queue.start_next(&mut wire).unwrap();
+        // the reply is message 8, after four telemetry messages and four logs, one refused.
+        assert_eq!(wire.sent[2], 8);
+        assert_eq!(queue.discard(Priority::Telemetry), 3);
+        assert_eq!(queue.discard(Priority::Log), 3);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn only_the_last_transfer_can_stall() {
+        let link = LinkConfig::new(1, 128);
+        let mut queue = TxQueue::new();
+        queued(&mut queue, Priority::Reply, &link).unwrap();
+        queued(&mut queue, Priority::Reply, &link).unwrap();
+        let mut serial = Stuck(0);
+        let first = queue.start_next(&mut serial).unwrap();
+        assert_eq!(queue.start_next(&mut serial), None);
+        assert!(queue.stalled(&serial, first));
+
+        // the first transfer completed, the timeout scheduled for it comes too late.
+        serial.0 = 0;
+        let second = queue.start_next(&mut serial).unwrap();
+        assert!(!queue.stalled(&serial, first));
+        assert!(queue.stalled(&serial, second));
+        serial.0 = 0;
+        assert!(!queue.stalled(&serial, second));
+    }
+}
@@ -0,0 +1,134 @@
+//! The device's framing, against the golden vectors in `protocol/vectors`.
+
+use serde::{Deserialize, Serialize};
+use turret_device::crc::SoftwareCrc;
+use turret_device::hal::SerialTx;
+use turret_device::rx::{Received, Receiver};
+use turret_device::tx::{encode, Priority, TxQueue};
+use turret_device::LinkConfig;
+use turret_protocol::address::REPLY;
+use turret_protocol::envelope::{Message, WireCodec};
+use turret_protocol::hello::{CODEC_CBOR, CODEC_POSTCARD};
+use turret_protocol::vectors::{self, to_hex, Vector};
+
+/// The codec a vector's hello bit stands for, with std's versions of the firmware's crates.
+struct Codec(u8);
+
+impl WireCodec for Codec {
+    type Error = ();
+
+    fn serialize<T: Serialize>(&self, value: &T, buffer: &mut [u8]) -> Result<usize, ()> {
+        let encoded = match self.0 {
+            CODEC_CBOR => serde_cbor::to_vec(value).map_err(|_| ())?,
+            CODEC_POSTCARD => {
+                return postcard::to_slice(value, buffer)
+                    .map(|used| used.len())
+                    .map_err(|_| ())
+            }
+            _ => serde_json::to_vec(value).map_err(|_| ())?,
+        };
+        buffer
+            .get_mut(..encoded.len())
+            .ok_or(())?
+            .copy_from_slice(&encoded);
+        Ok(encoded.len())
+    }
+
+    fn deserialize<'a, T: Deserialize<'a>>(&self, buffer: &'a mut [u8]) -> Result<T, ()> {
+        match self.0 {
+            CODEC_CBOR => serde_cbor::de::from_mut_slice(buffer).map_err(|_| ()),
+            CODEC_POSTCARD => postcard::from_bytes(buffer).map_err(|_| ()),
+            _ => serde_json::from_slice(buffer).map_err(|_| ()),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Wire {
+    sent: Vec<u8>,
+}
+
+impl SerialTx for Wire {
+    fn is_busy(&self) -> bool {
+        false
+    }
+
+    fn start(&mut self, frame: &[u8]) {
+        self.sent.extend_from_slice(frame);
+    }
+}
+
+fn link(vector: &Vector) -> LinkConfig {
+    let mut link = LinkConfig::new(vector.address & !REPLY, vector.frame_size);
+    link.full_crc = vector.full_crc;
+    link
+}
+
+#[test]
+fn messages_are_sent_as_the_vectors() {
+    for vector in vectors::all().into_iter().filter(Vector::from_device) {
+        let mut buffer = [0u8; 256];
+        let message = encode(
+            &Codec(vector.codec),
+            &vector.message(),
+            vector.request_id,
+            &mut buffer,
+        )
+        .unwrap();
+        let mut queue: TxQueue<64, 8> = TxQueue::new();
+        queue
+            .push(
+                message,
+                Priority::Reply,
+                &link(&vector),
+                &mut SoftwareCrc::new(),
+            )
+            .unwrap();
+        let mut wire = Wire::default();
+        while queue.start_next(&mut wire).is_some() {}
+        assert_eq!(to_hex(&wire.sent), to_hex(&vector.frame), "{}", vector.name);
+    }
+}
+
+#[test]
+fn host_messages_are_received() {
+    let mut received = 0;
+    for vector in vectors::all() {
+        if vector.from_device() {
+            continue;
+        }
+        // the device at address 1 also takes broadcasts.
+        let mut link = link(&vector);
+        link.address = 1;
+        let mut receiver: Receiver<63, 256> = Receiver::new();
+        let mut crc = SoftwareCrc::new();
+        let mut complete = None;
+        for &byte in &vector.frame {
+            match receiver.push(byte, &Codec(vector.codec), &link, &mut crc) {
+                Some(Ok(Received::Fragment)) | None => {}
+                Some(result) => complete = Some(result.unwrap()),
+            }
+        }
+        let (request_id, message) = match complete {
+            Some(Received::Request {
+                request_id,
+                request,
+                fresh: true,
+            }) => (request_id, Message::Request(request)),
+            Some(Received::Hello { request_id, offer }) => (request_id, Message::Hello(offer)),
+            Some(Received::Subscription {
+                request_id,
+                requested,
+            }) => (request_id, Message::Subscription(requested)),
+            Some(Received::NodeAddress {
+                request_id,
+                requested,
+            }) => (request_id, Message::NodeAddress(requested)),
+            other => panic!("{}: received {:?}", vector.name, other),
+        };
+        assert_eq!(request_id, vector.request_id, "{}", vector.name);
+        assert!(vector.matches(&message), "{}", vector.name);
+        received += 1;
+    }
+    assert!(received > 0);
+}
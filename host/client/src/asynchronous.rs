@@ -0,0 +1,280 @@
+use std::io;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time;
+use tokio_serial::{SerialPortBuilderExt, SerialStream};
+use turret_protocol::datamodel::link_errors::LinkErrorsPacket;
+use turret_protocol::datamodel::log_level::{LogLevel, LogLevelPacket};
+use turret_protocol::datamodel::node_address::NodeAddress;
+use turret_protocol::datamodel::request::{Request, RequestKind};
+use turret_protocol::datamodel::reset_cause::ResetCausePacket;
+use turret_protocol::datamodel::serial_config::{SerialConfig, SerialConfigPacket};
+use turret_protocol::datamodel::subscription::{Subscription, Topic};
+use turret_protocol::datamodel::telemetry_packet::TurretTelemetryPacket;
+use turret_protocol::envelope::Message;
+use turret_protocol::hello::Hello;
+
+use crate::blocking::{configure, SWITCH_SETTLE};
+use crate::codec::Codec;
+use crate::error::Error;
+use crate::received::{CrashRecord, FromReply, Received};
+use crate::session::{request, subscription, Config, Session, Stats, Step};
+
+/// The async counterpart of [`Client`](crate::Client), running on tokio.
+pub struct AsyncClient<P = SerialStream> {
+    port: P,
+    session: Session,
+    buffer: Vec<u8>,
+}
+
+impl AsyncClient {
+    /// Opens the serial port at `path`, 8N1 like the firmware, for a device built with its
+    /// defaults. Must be called within a tokio runtime.
+    pub fn open(path: &str, baud: u32) -> Result<Self, Error> {
+        Self::open_with(path, baud, Config::default())
+    }
+
+    /// Opens the serial port at `path` like [`open`](Self::open), for a device built otherwise.
+    pub fn open_with(path: &str, baud: u32, config: Config) -> Result<Self, Error> {
+        let port = tokio_serial::new(path, baud).open_native_async()?;
+        Ok(Self::new(port, config))
+    }
+
+    /// Switches the device's serial settings, then the port's, and confirms the switch before
+    /// the device falls back. If the device rejects the settings, its reply holds those in use.
+    pub async fn switch_serial(
+        &mut self,
+        config: SerialConfig,
+    ) -> Result<SerialConfigPacket, Error> {
+        let reply = self.set_serial_config(config).await?;
+        if reply.switching {
+            time::sleep(SWITCH_SETTLE).await;
+            configure(&mut self.port, &config)?;
+            // any valid frame confirms the new settings.
+            self.telemetry().await?;
+        }
+        Ok(reply)
+    }
+}
+
+impl<P: AsyncRead + AsyncWrite + Unpin> AsyncClient<P> {
+    pub fn new(port: P, config: Config) -> Self {
+        Self {
+            port,
+            session: Session::new(config),
+            buffer: vec![0; 256],
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        self.session.config()
+    }
+
+    pub fn port(&self) -> &P {
+        &self.port
+    }
+
+    pub fn port_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// The codec in use, which a hello may have changed.
+    pub fn codec(&self) -> Codec {
+        self.session.codec()
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.session.stats()
+    }
+
+    /// Agrees on the protocol version, codec, CRC and frame size with the device, and switches
+    /// to them. The device forgets them when it resets, so say hello again after one.
+    ///
+    /// A device still at the full CRCs agreed with an earlier client drops frames checked the
+    /// default way, so an unanswered hello is retried with full CRCs.
+    pub async fn hello(&mut self) -> Result<Hello, Error> {
+        match self.say_hello().await {
+            Err(Error::Timeout) if !self.session.full_crc() => {
+                self.session.set_full_crc(true);
+                let result = self.say_hello().await;
+                if result.is_err() {
+                    self.session.set_full_crc(false);
+                }
+                result
+            }
+            result => result,
+        }
+    }
+
+    async fn say_hello(&mut self) -> Result<Hello, Error> {
+        let frames = self
+            .session
+            .start_message(&Message::Hello(Session::offer()), Instant::now())?;
+        let reply = self.exchange(frames).await?;
+        self.session.agree(reply)
+    }
+
+    /// Sends a fire-and-forget request, and waits for its reply.
+    pub async fn request(&mut self, request: Request) -> Result<Received, Error> {
+        let frames = self.session.start_request(request, false, Instant::now())?;
+        self.exchange(frames).await
+    }
+
+    /// Sends a request that is executed exactly once, retransmitting it until the device
+    /// acknowledges it, and waits for its reply.
+    pub async fn request_reliable(&mut self, request: Request) -> Result<Received, Error> {
+        let frames = self.session.start_request(request, true, Instant::now())?;
+        self.exchange(frames).await
+    }
+
+    pub async fn telemetry(&mut self) -> Result<TurretTelemetryPacket, Error> {
+        self.query(request(RequestKind::Telemetry), false).await
+    }
+
+    pub async fn reset_cause(&mut self) -> Result<ResetCausePacket, Error> {
+        self.query(request(RequestKind::ResetCause), false).await
+    }
+
+    pub async fn crash_record(&mut self) -> Result<CrashRecord, Error> {
+        self.query(request(RequestKind::CrashRecord), false).await
+    }
+
+    pub async fn log_level(&mut self) -> Result<LogLevelPacket, Error> {
+        self.query(request(RequestKind::LogLevel), false).await
+    }
+
+    pub async fn set_log_level(&mut self, level: LogLevel) -> Result<LogLevelPacket, Error> {
+        let request = Request {
+            log_level: Some(level),
+            ..request(RequestKind::LogLevel)
+        };
+        self.query(request, true).await
+    }
+
+    pub async fn link_errors(&mut self) -> Result<LinkErrorsPacket, Error> {
+        self.query(request(RequestKind::LinkErrors), false).await
+    }
+
+    pub async fn serial_config(&mut self) -> Result<SerialConfigPacket, Error> {
+        self.query(request(RequestKind::SerialConfig), false).await
+    }
+
+    /// Asks the device to switch its serial settings. The port must then be switched too, and
+    /// used within the device's fallback window, see the book's serial settings chapter.
+    pub async fn set_serial_config(
+        &mut self,
+        config: SerialConfig,
+    ) -> Result<SerialConfigPacket, Error> {
+        let request = Request {
+            serial: Some(config),
+            ..request(RequestKind::SerialConfig)
+        };
+        self.query(request, true).await
+    }
+
+    /// Makes the turret's current position the new zero, and returns the telemetry that follows.
+    pub async fn zero(&mut self) -> Result<TurretTelemetryPacket, Error> {
+        self.query(request(RequestKind::Zero), true).await
+    }
+
+    /// Reboots the device, which replies with the reset cause it will report afterwards.
+    /// Not retransmitted, since a retransmission reaching the rebooted device reboots it again.
+    pub async fn reboot(&mut self) -> Result<ResetCausePacket, Error> {
+        self.query(request(RequestKind::Reboot), false).await
+    }
+
+    /// Saves the settings changed at runtime, so the device keeps them across resets.
+    pub async fn save_config(&mut self) -> Result<LogLevelPacket, Error> {
+        self.query(request(RequestKind::SaveConfig), true).await
+    }
+
+    /// Streams `topic` every `period`, or stops it with a zero period. The device replies with
+    /// the period it granted, which is longer if its link can't carry the topic that often, and
+    /// zero if it can't carry it at all. The topic's messages arrive as events, see
+    /// [`next_event`](Self::next_event). A hello ends every subscription.
+    pub async fn subscribe(
+        &mut self,
+        topic: Topic,
+        period: Duration,
+    ) -> Result<Subscription, Error> {
+        let requested = Message::Subscription(subscription(topic, period));
+        let frames = self.session.start_message(&requested, Instant::now())?;
+        let reply = self.exchange(frames).await?;
+        Subscription::from_reply(reply).map_err(Error::UnexpectedReply)
+    }
+
+    /// Asks the device which address it is at, e.g. through the broadcast address when it is
+    /// alone on the bus.
+    pub async fn node_address(&mut self) -> Result<NodeAddress, Error> {
+        self.move_node(None).await
+    }
+
+    /// Moves the device to `address`, any below the broadcast address, and talks to it there
+    /// from now on. The device keeps its new address across resets.
+    ///
+    /// Not retransmitted: the device answers at its new address once it replied, so a
+    /// retransmission would go unanswered. If the reply is lost, ask the new address.
+    pub async fn set_node_address(&mut self, address: u8) -> Result<NodeAddress, Error> {
+        let moved = self.move_node(Some(address)).await?;
+        self.session.set_address(address);
+        Ok(moved)
+    }
+
+    async fn move_node(&mut self, address: Option<u8>) -> Result<NodeAddress, Error> {
+        let requested = Message::NodeAddress(NodeAddress { address });
+        let frames = self.session.start_message(&requested, Instant::now())?;
+        let reply = self.exchange(frames).await?;
+        NodeAddress::from_reply(reply).map_err(Error::UnexpectedReply)
+    }
+
+    /// Waits up to `timeout` for a message the device sent unprompted, such as telemetry or a
+    /// log record. Those that arrived while waiting for replies are returned first.
+    pub async fn next_event(&mut self, timeout: Duration) -> Result<Option<Received>, Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(event) = self.session.take_event() {
+                return Ok(Some(event));
+            }
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+            self.fill(deadline).await?;
+        }
+    }
+
+    async fn query<T: FromReply>(&mut self, request: Request, reliable: bool) -> Result<T, Error> {
+        let frames = self
+            .session
+            .start_request(request, reliable, Instant::now())?;
+        let reply = self.exchange(frames).await?;
+        T::from_reply(reply).map_err(Error::UnexpectedReply)
+    }
+
+    async fn exchange(&mut self, frames: Vec<u8>) -> Result<Received, Error> {
+        self.port.write_all(&frames).await?;
+        self.port.flush().await?;
+        loop {
+            match self.session.step(Instant::now()) {
+                Step::Done(result) => return result,
+                Step::Send(frames) => self.port.write_all(&frames).await?,
+                Step::Wait(until) => self.fill(until).await?,
+            }
+        }
+    }
+
+    /// Reads whatever arrives before `until`.
+    async fn fill(&mut self, until: Instant) -> Result<(), Error> {
+        let read = self.port.read(&mut self.buffer);
+        match time::timeout_at(until.into(), read).await {
+            Ok(Ok(0)) => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            Ok(Ok(n)) => {
+                self.session.receive(&self.buffer[..n]);
+                Ok(())
+            }
+            Ok(Err(e)) => Err(e.into()),
+            // nothing arrived in time.
+            Err(_) => Ok(()),
+        }
+    }
+}
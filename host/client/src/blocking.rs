@@ -0,0 +1,298 @@
+use std::io::{self, Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serialport::SerialPort;
+use turret_protocol::datamodel::link_errors::LinkErrorsPacket;
+use turret_protocol::datamodel::log_level::{LogLevel, LogLevelPacket};
+use turret_protocol::datamodel::node_address::NodeAddress;
+use turret_protocol::datamodel::request::{Request, RequestKind};
+use turret_protocol::datamodel::reset_cause::ResetCausePacket;
+use turret_protocol::datamodel::serial_config::{
+    Parity, SerialConfig, SerialConfigPacket, StopBits,
+};
+use turret_protocol::datamodel::subscription::{Subscription, Topic};
+use turret_protocol::datamodel::telemetry_packet::TurretTelemetryPacket;
+use turret_protocol::envelope::Message;
+use turret_protocol::hello::Hello;
+
+use crate::codec::Codec;
+use crate::error::Error;
+use crate::received::{CrashRecord, FromReply, Received};
+use crate::session::{request, subscription, Config, Session, Stats, Step};
+
+/// How long a serial port's reads block, which bounds how late a timeout is noticed.
+pub(crate) const READ_TIMEOUT: Duration = Duration::from_millis(10);
+/// Time for the device to finish replying and reconfigure USART1, before the host switches too.
+pub(crate) const SWITCH_SETTLE: Duration = Duration::from_millis(20);
+
+/// A blocking client, over a serial port or any other byte stream.
+pub struct Client<P = Box<dyn SerialPort>> {
+    port: P,
+    session: Session,
+    buffer: Vec<u8>,
+}
+
+impl Client {
+    /// Opens the serial port at `path`, 8N1 like the firmware, for a device built with its
+    /// defaults.
+    pub fn open(path: &str, baud: u32) -> Result<Self, Error> {
+        Self::open_with(path, baud, Config::default())
+    }
+
+    /// Opens the serial port at `path` like [`open`](Self::open), for a device built otherwise.
+    pub fn open_with(path: &str, baud: u32, config: Config) -> Result<Self, Error> {
+        let port = serialport::new(path, baud).timeout(READ_TIMEOUT).open()?;
+        Ok(Self::new(port, config))
+    }
+
+    /// Switches the device's serial settings, then the port's, and confirms the switch before
+    /// the device falls back. If the device rejects the settings, its reply holds those in use.
+    pub fn switch_serial(&mut self, config: SerialConfig) -> Result<SerialConfigPacket, Error> {
+        let reply = self.set_serial_config(config)?;
+        if reply.switching {
+            thread::sleep(SWITCH_SETTLE);
+            configure(self.port.as_mut(), &config)?;
+            // any valid frame confirms the new settings.
+            self.telemetry()?;
+        }
+        Ok(reply)
+    }
+}
+
+impl<P: Read + Write> Client<P> {
+    /// A client over `port`, whose reads should time out every so often as a serial port's do,
+    /// otherwise timeouts are only noticed once something arrives.
+    pub fn new(port: P, config: Config) -> Self {
+        Self {
+            port,
+            session: Session::new(config),
+            buffer: vec![0; 256],
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        self.session.config()
+    }
+
+    pub fn port(&self) -> &P {
+        &self.port
+    }
+
+    pub fn port_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// The codec in use, which a hello may have changed.
+    pub fn codec(&self) -> Codec {
+        self.session.codec()
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.session.stats()
+    }
+
+    /// Agrees on the protocol version, codec, CRC and frame size with the device, and switches
+    /// to them. The device forgets them when it resets, so say hello again after one.
+    ///
+    /// A device still at the full CRCs agreed with an earlier client drops frames checked the
+    /// default way, so an unanswered hello is retried with full CRCs.
+    pub fn hello(&mut self) -> Result<Hello, Error> {
+        match self.say_hello() {
+            Err(Error::Timeout) if !self.session.full_crc() => {
+                self.session.set_full_crc(true);
+                let result = self.say_hello();
+                if result.is_err() {
+                    self.session.set_full_crc(false);
+                }
+                result
+            }
+            result => result,
+        }
+    }
+
+    fn say_hello(&mut self) -> Result<Hello, Error> {
+        let frames = self
+            .session
+            .start_message(&Message::Hello(Session::offer()), Instant::now())?;
+        let reply = self.exchange(frames)?;
+        self.session.agree(reply)
+    }
+
+    /// Sends a fire-and-forget request, and waits for its reply.
+    pub fn request(&mut self, request: Request) -> Result<Received, Error> {
+        let frames = self.session.start_request(request, false, Instant::now())?;
+        self.exchange(frames)
+    }
+
+    /// Sends a request that is executed exactly once, retransmitting it until the device
+    /// acknowledges it, and waits for its reply.
+    pub fn request_reliable(&mut self, request: Request) -> Result<Received, Error> {
+        let frames = self.session.start_request(request, true, Instant::now())?;
+        self.exchange(frames)
+    }
+
+    pub fn telemetry(&mut self) -> Result<TurretTelemetryPacket, Error> {
+        self.query(request(RequestKind::Telemetry), false)
+    }
+
+    pub fn reset_cause(&mut self) -> Result<ResetCausePacket, Error> {
+        self.query(request(RequestKind::ResetCause), false)
+    }
+
+    pub fn crash_record(&mut self) -> Result<CrashRecord, Error> {
+        self.query(request(RequestKind::CrashRecord), false)
+    }
+
+    pub fn log_level(&mut self) -> Result<LogLevelPacket, Error> {
+        self.query(request(RequestKind::LogLevel), false)
+    }
+
+    pub fn set_log_level(&mut self, level: LogLevel) -> Result<LogLevelPacket, Error> {
+        let request = Request {
+            log_level: Some(level),
+            ..request(RequestKind::LogLevel)
+        };
+        self.query(request, true)
+    }
+
+    pub fn link_errors(&mut self) -> Result<LinkErrorsPacket, Error> {
+        self.query(request(RequestKind::LinkErrors), false)
+    }
+
+    pub fn serial_config(&mut self) -> Result<SerialConfigPacket, Error> {
+        self.query(request(RequestKind::SerialConfig), false)
+    }
+
+    /// Asks the device to switch its serial settings. The port must then be switched too, and
+    /// used within the device's fallback window, see the book's serial settings chapter.
+    pub fn set_serial_config(&mut self, config: SerialConfig) -> Result<SerialConfigPacket, Error> {
+        let request = Request {
+            serial: Some(config),
+            ..request(RequestKind::SerialConfig)
+        };
+        self.query(request, true)
+    }
+
+    /// Makes the turret's current position the new zero, and returns the telemetry that follows.
+    pub fn zero(&mut self) -> Result<TurretTelemetryPacket, Error> {
+        self.query(request(RequestKind::Zero), true)
+    }
+
+    /// Reboots the device, which replies with the reset cause it will report afterwards.
+    /// Not retransmitted, since a retransmission reaching the rebooted device reboots it again.
+    pub fn reboot(&mut self) -> Result<ResetCausePacket, Error> {
+        self.query(request(RequestKind::Reboot), false)
+    }
+
+    /// Saves the settings changed at runtime, so the device keeps them across resets.
+    pub fn save_config(&mut self) -> Result<LogLevelPacket, Error> {
+        self.query(request(RequestKind::SaveConfig), true)
+    }
+
+    /// Streams `topic` every `period`, or stops it with a zero period. The device replies with
+    /// the period it granted, which is longer if its link can't carry the topic that often, and
+    /// zero if it can't carry it at all. The topic's messages arrive as events, see
+    /// [`next_event`](Self::next_event). A hello ends every subscription.
+    pub fn subscribe(&mut self, topic: Topic, period: Duration) -> Result<Subscription, Error> {
+        let requested = Message::Subscription(subscription(topic, period));
+        let frames = self.session.start_message(&requested, Instant::now())?;
+        let reply = self.exchange(frames)?;
+        Subscription::from_reply(reply).map_err(Error::UnexpectedReply)
+    }
+
+    /// Asks the device which address it is at, e.g. through the broadcast address when it is
+    /// alone on the bus.
+    pub fn node_address(&mut self) -> Result<NodeAddress, Error> {
+        self.move_node(None)
+    }
+
+    /// Moves the device to `address`, any below the broadcast address, and talks to it there
+    /// from now on. The device keeps its new address across resets.
+    ///
+    /// Not retransmitted: the device answers at its new address once it replied, so a
+    /// retransmission would go unanswered. If the reply is lost, ask the new address.
+    pub fn set_node_address(&mut self, address: u8) -> Result<NodeAddress, Error> {
+        let moved = self.move_node(Some(address))?;
+        self.session.set_address(address);
+        Ok(moved)
+    }
+
+    fn move_node(&mut self, address: Option<u8>) -> Result<NodeAddress, Error> {
+        let requested = Message::NodeAddress(NodeAddress { address });
+        let frames = self.session.start_message(&requested, Instant::now())?;
+        let reply = self.exchange(frames)?;
+        NodeAddress::from_reply(reply).map_err(Error::UnexpectedReply)
+    }
+
+    /// Waits up to `timeout` for a message the device sent unprompted, such as telemetry or a
+    /// log record. Those that arrived while waiting for replies are returned first.
+    pub fn next_event(&mut self, timeout: Duration) -> Result<Option<Received>, Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(event) = self.session.take_event() {
+                return Ok(Some(event));
+            }
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+            self.fill()?;
+        }
+    }
+
+    fn query<T: FromReply>(&mut self, request: Request, reliable: bool) -> Result<T, Error> {
+        let frames = self
+            .session
+            .start_request(request, reliable, Instant::now())?;
+        let reply = self.exchange(frames)?;
+        T::from_reply(reply).map_err(Error::UnexpectedReply)
+    }
+
+    fn exchange(&mut self, frames: Vec<u8>) -> Result<Received, Error> {
+        self.port.write_all(&frames)?;
+        self.port.flush()?;
+        loop {
+            match self.session.step(Instant::now()) {
+                Step::Done(result) => return result,
+                Step::Send(frames) => self.port.write_all(&frames)?,
+                // the port's read timeout bounds the wait instead.
+                Step::Wait(_) => self.fill()?,
+            }
+        }
+    }
+
+    /// Reads whatever arrives before the port's read times out.
+    fn fill(&mut self) -> Result<(), Error> {
+        match self.port.read(&mut self.buffer) {
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            Ok(n) => self.session.receive(&self.buffer[..n]),
+            Err(e) if is_timeout(&e) => {}
+            Err(e) => return Err(e.into()),
+        }
+        Ok(())
+    }
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+    )
+}
+
+/// Applies the device's serial settings to a port.
+pub(crate) fn configure(
+    port: &mut dyn SerialPort,
+    config: &SerialConfig,
+) -> Result<(), serialport::Error> {
+    port.set_baud_rate(config.baud)?;
+    port.set_parity(match config.parity {
+        Parity::None => serialport::Parity::None,
+        Parity::Even => serialport::Parity::Even,
+        Parity::Odd => serialport::Parity::Odd,
+    })?;
+    port.set_stop_bits(match config.stop_bits {
+        StopBits::One => serialport::StopBits::One,
+        StopBits::Two => serialport::StopBits::Two,
+    })
+}
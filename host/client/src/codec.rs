@@ -0,0 +1,95 @@
+//! Payload encodings, all of which a host supports.
+//!
+//! These mirror the firmware's codecs, with the std versions of the same crates.
+//! A firmware build speaks one of them until a hello agrees on another it was built with.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use turret_protocol::envelope::WireCodec;
+use turret_protocol::hello::{CODEC_CBOR, CODEC_JSON, CODEC_POSTCARD};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Codec {
+    Cbor,
+    Postcard,
+    Json,
+}
+
+#[derive(Debug)]
+pub enum CodecError {
+    Cbor(serde_cbor::Error),
+    Postcard(postcard::Error),
+    Json(serde_json::Error),
+    /// The encoded payload doesn't fit in the buffer.
+    BufferTooSmall,
+}
+
+impl Codec {
+    /// Every codec, in the order firmware builds prefer them.
+    pub const ALL: [Codec; 3] = [Codec::Cbor, Codec::Postcard, Codec::Json];
+
+    /// The codec's bit in a hello.
+    pub fn hello_bit(self) -> u8 {
+        match self {
+            Codec::Cbor => CODEC_CBOR,
+            Codec::Postcard => CODEC_POSTCARD,
+            Codec::Json => CODEC_JSON,
+        }
+    }
+
+    /// The codec a hello's bit stands for.
+    pub fn from_hello_bit(bit: u8) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|codec| codec.hello_bit() == bit)
+    }
+}
+
+impl WireCodec for Codec {
+    type Error = CodecError;
+
+    fn serialize<T: Serialize>(&self, value: &T, buffer: &mut [u8]) -> Result<usize, CodecError> {
+        match self {
+            Codec::Cbor => {
+                use serde_cbor::ser::{Serializer, SliceWrite};
+                let mut serializer = Serializer::new(SliceWrite::new(buffer));
+                value.serialize(&mut serializer).map_err(CodecError::Cbor)?;
+                Ok(serializer.into_inner().bytes_written())
+            }
+            Codec::Postcard => postcard::to_slice(value, buffer)
+                .map(|used| used.len())
+                .map_err(CodecError::Postcard),
+            Codec::Json => {
+                let json = serde_json::to_vec(value).map_err(CodecError::Json)?;
+                buffer
+                    .get_mut(..json.len())
+                    .ok_or(CodecError::BufferTooSmall)?
+                    .copy_from_slice(&json);
+                Ok(json.len())
+            }
+        }
+    }
+
+    fn deserialize<'a, T: Deserialize<'a>>(&self, buffer: &'a mut [u8]) -> Result<T, CodecError> {
+        match self {
+            Codec::Cbor => serde_cbor::de::from_mut_slice(buffer).map_err(CodecError::Cbor),
+            Codec::Postcard => postcard::from_bytes(buffer).map_err(CodecError::Postcard),
+            Codec::Json => serde_json::from_slice(buffer).map_err(CodecError::Json),
+        }
+    }
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CodecError::Cbor(e) => write!(f, "CBOR: {}", e),
+            CodecError::Postcard(e) => write!(f, "postcard: {}", e),
+            CodecError::Json(e) => write!(f, "JSON: {}", e),
+            CodecError::BufferTooSmall => write!(f, "payload too large"),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
@@ -0,0 +1,74 @@
+use std::{fmt, io};
+
+use turret_protocol::datamodel::error::ErrorCode;
+use turret_protocol::envelope::EnvelopeError;
+use turret_protocol::fragment::FragmentError;
+use turret_protocol::hello::Hello;
+
+use crate::codec::CodecError;
+use crate::received::Received;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The serial port couldn't be opened or configured.
+    Serial(serialport::Error),
+    /// No reply arrived in time, retransmissions included.
+    Timeout,
+    /// The device couldn't handle the request.
+    Device(ErrorCode),
+    /// The device replied with another message than the request calls for, or agreed on
+    /// settings this client didn't offer.
+    UnexpectedReply(Received),
+    /// The device has nothing in common with this client, and replied with what it supports.
+    NoAgreement(Hello),
+    /// The message couldn't be encoded.
+    Encode(EnvelopeError<CodecError>),
+    /// The message needs more fragments than a frame header can count.
+    TooLarge(FragmentError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::Serial(e) => write!(f, "serial port error: {}", e),
+            Error::Timeout => write!(f, "timed out waiting for the device"),
+            Error::Device(code) => write!(f, "the device replied with error {:?}", code),
+            Error::UnexpectedReply(reply) => {
+                write!(f, "unexpected reply {:?}", reply.message_type())
+            }
+            Error::NoAgreement(supported) => {
+                write!(
+                    f,
+                    "nothing in common with the device, which supports {:?}",
+                    supported
+                )
+            }
+            Error::Encode(e) => write!(f, "failed to encode message: {:?}", e),
+            Error::TooLarge(e) => write!(f, "message too large: {:?}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Serial(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<serialport::Error> for Error {
+    fn from(e: serialport::Error) -> Self {
+        Error::Serial(e)
+    }
+}
@@ -0,0 +1,39 @@
+//! Host client for the turret monitor firmware.
+//!
+//! Speaks the protocol described in the book's communications interface chapter over a serial
+//! port: it frames, checksums, fragments and encodes requests, and recovers the replies,
+//! telemetry and log records the device sends back.
+//!
+//! [`Client`] is blocking, and [`AsyncClient`] (the default `async` feature) runs on tokio.
+//! Both work over any byte stream, not only serial ports, and share the same protocol state,
+//! which does no I/O itself.
+//!
+//! ```no_run
+//! # fn main() -> Result<(), turret_client::Error> {
+//! let mut client = turret_client::Client::open("/dev/ttyACM0", 115_200)?;
+//! client.hello()?;
+//! let telemetry = client.telemetry()?;
+//! println!("turret at {}", telemetry.turret_pos);
+//! # Ok(())
+//! # }
+//! ```
+
+#[cfg(feature = "async")]
+mod asynchronous;
+mod blocking;
+pub mod codec;
+mod error;
+pub mod link;
+mod received;
+mod session;
+
+#[cfg(feature = "async")]
+pub use asynchronous::AsyncClient;
+pub use blocking::Client;
+pub use codec::Codec;
+pub use error::Error;
+pub use received::{CrashRecord, FromReply, LogRecord, Received};
+pub use session::{Config, Stats};
+
+/// The protocol crate, for the packets requested and received.
+pub use turret_protocol as protocol;
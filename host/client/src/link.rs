@@ -0,0 +1,282 @@
+//! Frames messages for the wire, and recovers messages from received bytes, without any I/O.
+//!
+//! This is the host's end of the link: it sends requests, and only takes replies. The device's
+//! end is `turret_device`'s, which the simulator runs.
+
+use std::convert::TryInto;
+
+use turret_protocol::address::{self, ADDRESS_LEN};
+use turret_protocol::crc::crc32;
+use turret_protocol::deframer::{DeframeError, Deframer};
+use turret_protocol::fragment::{
+    fragment, max_chunk_len, FragmentError, FragmentHeader, Reassembler, CRC_LEN, HEADER_LEN,
+};
+use turret_protocol::hello::{Hello, MIN_FRAME_SIZE};
+
+/// Frame size of a firmware built without `TURRET_BUF_SIZE`, used until a hello agrees on one.
+pub const DEFAULT_FRAME_SIZE: usize = 64;
+/// Largest frame a host receives, offered in its hello.
+pub const MAX_FRAME_SIZE: usize = 1024;
+/// Largest message a host reassembles.
+pub const MAX_MESSAGE_SIZE: usize = 16 * 1024;
+
+/// Received frames, and why those that didn't make it into a message were dropped.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct LinkStats {
+    /// Frames with a valid CRC.
+    pub frames: u32,
+    /// Frames that weren't well-formed COBS, or too large.
+    pub malformed: u32,
+    /// Frames whose CRC didn't match.
+    pub bad_crc: u32,
+    /// Frames that couldn't be added to a message.
+    pub fragment: u32,
+    /// Replies from other devices on the bus, and requests from other hosts.
+    pub ignored: u32,
+}
+
+pub struct Link {
+    /// The device every frame is sent to, and whose replies are expected.
+    address: u8,
+    frame_size: usize,
+    full_crc: bool,
+    next_message_id: u8,
+    deframer: Box<Deframer<MAX_FRAME_SIZE>>,
+    reassembler: Box<Reassembler<MAX_MESSAGE_SIZE>>,
+    stats: LinkStats,
+}
+
+impl Link {
+    /// A link to the device at `address`, with the firmware's default settings.
+    ///
+    /// Frames received are kept if they are replies from `address`, or from any device if it is
+    /// [`BROADCAST`](address::BROADCAST), so a host linked to the broadcast address hears every device on the bus.
+    pub fn new(address: u8) -> Self {
+        Self {
+            address,
+            frame_size: DEFAULT_FRAME_SIZE,
+            full_crc: false,
+            next_message_id: 0,
+            deframer: Box::new(Deframer::new()),
+            reassembler: Box::new(Reassembler::new()),
+            stats: LinkStats::default(),
+        }
+    }
+
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    /// Talks to the device at `address` from now on, e.g. once it was moved there.
+    pub fn set_address(&mut self, address: u8) {
+        self.address = address;
+    }
+
+    /// Sets the size of the frames sent, which the receiver must be able to hold, within
+    /// `MIN_FRAME_SIZE..=MAX_FRAME_SIZE`.
+    pub fn set_frame_size(&mut self, frame_size: usize) {
+        self.frame_size = frame_size.clamp(MIN_FRAME_SIZE, MAX_FRAME_SIZE);
+    }
+
+    /// Switches between CRCs covering the whole frame, and only its whole words.
+    pub fn set_full_crc(&mut self, full: bool) {
+        self.full_crc = full;
+    }
+
+    pub fn full_crc(&self) -> bool {
+        self.full_crc
+    }
+
+    /// Switches to the settings a hello agreed on.
+    pub fn apply(&mut self, agreed: &Hello) {
+        self.set_frame_size(agreed.max_frame as usize);
+        self.set_full_crc(agreed.flags & turret_protocol::hello::FULL_CRC != 0);
+    }
+
+    pub fn stats(&self) -> LinkStats {
+        self.stats
+    }
+
+    /// Splits `message` into frames, appending them to `out` ready to be written.
+    pub fn encode(&mut self, message: &[u8], out: &mut Vec<u8>) -> Result<(), FragmentError> {
+        let message_id = self.next_message_id;
+        self.next_message_id = self.next_message_id.wrapping_add(1);
+        let mut buffer = Vec::with_capacity(self.frame_size);
+        for (header, chunk) in fragment(message, message_id, max_chunk_len(self.frame_size))? {
+            buffer.clear();
+            buffer.push(self.address);
+            buffer.extend_from_slice(&header.to_bytes());
+            buffer.extend_from_slice(chunk);
+            let checksum = crc32(&buffer, self.full_crc);
+            buffer.extend_from_slice(&checksum.to_be_bytes());
+
+            let start = out.len();
+            out.resize(start + buffer.len() + buffer.len() / 254 + 2, 0);
+            let encoded = postcard_cobs::encode(&buffer, &mut out[start..]);
+            // followed by the sentinel, already in place.
+            out.truncate(start + encoded + 1);
+        }
+        Ok(())
+    }
+
+    /// Feeds received bytes, calling `on_message` with every message they complete.
+    /// Bytes may be chunked any way, frames that don't make it into a message are counted.
+    pub fn receive(&mut self, bytes: &[u8], mut on_message: impl FnMut(&mut [u8])) {
+        let Self {
+            address: own,
+            full_crc,
+            deframer,
+            reassembler,
+            stats,
+            ..
+        } = self;
+        for &byte in bytes {
+            let frame = match deframer.push(byte) {
+                None => continue,
+                Some(Ok(frame)) => frame,
+                Some(Err(DeframeError::Cobs)) | Some(Err(DeframeError::Overflow)) => {
+                    stats.malformed += 1;
+                    continue;
+                }
+            };
+            let n = frame.len();
+            if n < ADDRESS_LEN + HEADER_LEN + CRC_LEN {
+                stats.malformed += 1;
+                continue;
+            }
+            let (data, crc) = frame.split_at(n - CRC_LEN);
+            // NOTE(unwrap): the split leaves exactly CRC_LEN bytes.
+            if u32::from_be_bytes(crc.try_into().unwrap()) != crc32(data, *full_crc) {
+                stats.bad_crc += 1;
+                continue;
+            }
+            stats.frames += 1;
+            // NOTE(unwrap): the length was checked above.
+            let (address, fragment) = address::split(data).unwrap();
+            if !address::from_device(*own, address) {
+                stats.ignored += 1;
+                continue;
+            }
+            let result = FragmentHeader::split(fragment)
+                .and_then(|(header, chunk)| reassembler.push(header, chunk));
+            match result {
+                Ok(Some(message)) => on_message(message),
+                Ok(None) => {}
+                Err(_) => stats.fragment += 1,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use turret_protocol::address::BROADCAST;
+
+    /// The frames `wire` holds, as the device they were sent to would send them back.
+    fn as_device(wire: &[u8], full_crc: bool) -> Vec<u8> {
+        let mut deframer = Deframer::<MAX_FRAME_SIZE>::new();
+        let mut replies = Vec::new();
+        for &byte in wire {
+            let frame = match deframer.push(byte) {
+                Some(frame) => frame.unwrap(),
+                None => continue,
+            };
+            let mut data = frame[..frame.len() - CRC_LEN].to_vec();
+            data[0] = address::reply(data[0]);
+            data.extend_from_slice(&crc32(&data, full_crc).to_be_bytes());
+            let mut encoded = vec![0; data.len() + data.len() / 254 + 2];
+            let len = postcard_cobs::encode(&data, &mut encoded);
+            replies.extend_from_slice(&encoded[..len]);
+            replies.push(0);
+        }
+        replies
+    }
+
+    /// Sends `message` from the device `sender` is linked to, to `receiver`.
+    fn round_trip(sender: &mut Link, receiver: &mut Link, message: &[u8]) -> Vec<Vec<u8>> {
+        let mut wire = Vec::new();
+        sender.encode(message, &mut wire).unwrap();
+        let wire = as_device(&wire, sender.full_crc());
+        let mut received = Vec::new();
+        // one byte at a time, the worst a serial port does.
+        for byte in wire {
+            receiver.receive(&[byte], |message| received.push(message.to_vec()));
+        }
+        received
+    }
+
+    #[test]
+    fn large_messages_are_fragmented() {
+        let message: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        let mut device = Link::new(1);
+        let mut host = Link::new(1);
+        for full in [false, true] {
+            device.set_full_crc(full);
+            host.set_full_crc(full);
+            assert_eq!(round_trip(&mut device, &mut host, &message), [&message[..]]);
+        }
+        device.set_frame_size(32);
+        assert_eq!(round_trip(&mut device, &mut host, &message), [message]);
+    }
+
+    #[test]
+    fn frame_sizes_are_clamped() {
+        let message = [0x55; 100];
+        let mut device = Link::new(1);
+        let mut host = Link::new(1);
+        // too small to carry any of the message.
+        device.set_frame_size(0);
+        assert_eq!(round_trip(&mut device, &mut host, &message), [&message[..]]);
+        device.set_frame_size(usize::MAX);
+        let mut wire = Vec::new();
+        device
+            .encode(&[0x55; 4 * MAX_FRAME_SIZE], &mut wire)
+            .unwrap();
+        assert!(wire
+            .split(|&byte| byte == 0)
+            .all(|frame| frame.len() <= MAX_FRAME_SIZE));
+    }
+
+    #[test]
+    fn other_devices_are_ignored() {
+        let mut other = Link::new(2);
+        let mut host = Link::new(1);
+        assert!(round_trip(&mut other, &mut host, b"hi").is_empty());
+        assert_eq!(host.stats().ignored, 1);
+        let mut everyone = Link::new(BROADCAST);
+        assert_eq!(
+            round_trip(&mut other, &mut everyone, b"hi"),
+            [b"hi".to_vec()]
+        );
+    }
+
+    #[test]
+    fn requests_are_ignored() {
+        // another host's, on the same bus.
+        let mut other = Link::new(1);
+        let mut wire = Vec::new();
+        other.encode(b"hi", &mut wire).unwrap();
+        for address in [1, BROADCAST] {
+            let mut host = Link::new(address);
+            let mut received = 0;
+            host.receive(&wire, |_| received += 1);
+            assert_eq!(received, 0);
+            assert_eq!(host.stats().ignored, 1);
+        }
+    }
+
+    #[test]
+    fn corrupted_frames_are_dropped() {
+        let mut device = Link::new(1);
+        let mut wire = Vec::new();
+        device.encode(b"hello host", &mut wire).unwrap();
+        let mut wire = as_device(&wire, false);
+        wire[6] ^= 0x10;
+        let mut host = Link::new(1);
+        let mut received = 0;
+        host.receive(&wire, |_| received += 1);
+        assert_eq!(received, 0);
+        assert_eq!(host.stats().bad_crc, 1);
+    }
+}
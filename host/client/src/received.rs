@@ -0,0 +1,153 @@
+use turret_protocol::datamodel::ack::AckPacket;
+use turret_protocol::datamodel::crash_record::CrashKind;
+use turret_protocol::datamodel::error::ErrorPacket;
+use turret_protocol::datamodel::link_errors::LinkErrorsPacket;
+use turret_protocol::datamodel::log_level::{LogLevel, LogLevelPacket};
+use turret_protocol::datamodel::node_address::NodeAddress;
+use turret_protocol::datamodel::request::Request;
+use turret_protocol::datamodel::reset_cause::ResetCausePacket;
+use turret_protocol::datamodel::serial_config::SerialConfigPacket;
+use turret_protocol::datamodel::subscription::Subscription;
+use turret_protocol::datamodel::telemetry_batch::TelemetryBatch;
+use turret_protocol::datamodel::telemetry_packet::TurretTelemetryPacket;
+use turret_protocol::envelope::{Message, MessageType};
+use turret_protocol::hello::Hello;
+
+/// A message received from the device, owning its payload.
+#[derive(Debug)]
+pub enum Received {
+    Request(Request),
+    Telemetry(TurretTelemetryPacket),
+    ResetCause(ResetCausePacket),
+    CrashRecord(CrashRecord),
+    LogLevel(LogLevelPacket),
+    LinkErrors(LinkErrorsPacket),
+    SerialConfig(SerialConfigPacket),
+    Ack(AckPacket),
+    Log(LogRecord),
+    Error(ErrorPacket),
+    Hello(Hello),
+    Subscription(Subscription),
+    TelemetryBatch(TelemetryBatch),
+    NodeAddress(NodeAddress),
+    /// A message type this client doesn't know, from newer firmware.
+    Unknown(u8),
+}
+
+/// An owned `CrashRecordPacket`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrashRecord {
+    pub kind: CrashKind,
+    pub file: String,
+    pub line: u32,
+    pub pc: u32,
+    pub lr: u32,
+    pub cfsr: u32,
+    pub hfsr: u32,
+    pub mmfar: u32,
+    pub bfar: u32,
+    pub msg: String,
+}
+
+/// An owned `LogRecordPacket`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogRecord {
+    pub level: LogLevel,
+    pub ts: u32,
+    pub dropped: u32,
+    pub msg: String,
+}
+
+impl Received {
+    /// The type sent in the envelope, or the unknown type's number.
+    pub fn message_type(&self) -> Result<MessageType, u8> {
+        Ok(match self {
+            Received::Request(_) => MessageType::Request,
+            Received::Telemetry(_) => MessageType::Telemetry,
+            Received::ResetCause(_) => MessageType::ResetCause,
+            Received::CrashRecord(_) => MessageType::CrashRecord,
+            Received::LogLevel(_) => MessageType::LogLevel,
+            Received::LinkErrors(_) => MessageType::LinkErrors,
+            Received::SerialConfig(_) => MessageType::SerialConfig,
+            Received::Ack(_) => MessageType::Ack,
+            Received::Log(_) => MessageType::Log,
+            Received::Error(_) => MessageType::Error,
+            Received::Hello(_) => MessageType::Hello,
+            Received::Subscription(_) => MessageType::Subscription,
+            Received::TelemetryBatch(_) => MessageType::TelemetryBatch,
+            Received::NodeAddress(_) => MessageType::NodeAddress,
+            Received::Unknown(message_type) => return Err(*message_type),
+        })
+    }
+}
+
+impl From<Message<'_>> for Received {
+    fn from(message: Message) -> Self {
+        match message {
+            Message::Request(m) => Received::Request(m),
+            Message::Telemetry(m) => Received::Telemetry(m),
+            Message::ResetCause(m) => Received::ResetCause(m),
+            Message::CrashRecord(m) => Received::CrashRecord(CrashRecord {
+                kind: m.kind,
+                file: m.file.to_owned(),
+                line: m.line,
+                pc: m.pc,
+                lr: m.lr,
+                cfsr: m.cfsr,
+                hfsr: m.hfsr,
+                mmfar: m.mmfar,
+                bfar: m.bfar,
+                msg: m.msg.to_owned(),
+            }),
+            Message::LogLevel(m) => Received::LogLevel(m),
+            Message::LinkErrors(m) => Received::LinkErrors(m),
+            Message::SerialConfig(m) => Received::SerialConfig(m),
+            Message::Ack(m) => Received::Ack(m),
+            Message::Log(m) => Received::Log(LogRecord {
+                level: m.level,
+                ts: m.ts,
+                dropped: m.dropped,
+                msg: m.msg.to_owned(),
+            }),
+            Message::Error(m) => Received::Error(m),
+            Message::Hello(m) => Received::Hello(m),
+            Message::Subscription(m) => Received::Subscription(m),
+            Message::TelemetryBatch(m) => Received::TelemetryBatch(m),
+            Message::NodeAddress(m) => Received::NodeAddress(m),
+            Message::Unknown(message_type) => Received::Unknown(message_type),
+        }
+    }
+}
+
+/// A reply payload, picked out of whatever was received.
+pub trait FromReply: Sized {
+    /// Returns the payload if `reply` carries one, or gives `reply` back.
+    fn from_reply(reply: Received) -> Result<Self, Received>;
+}
+
+macro_rules! from_reply {
+    ($($payload:ty => $variant:ident,)*) => {
+        $(
+            impl FromReply for $payload {
+                fn from_reply(reply: Received) -> Result<Self, Received> {
+                    match reply {
+                        Received::$variant(payload) => Ok(payload),
+                        other => Err(other),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+from_reply! {
+    TurretTelemetryPacket => Telemetry,
+    ResetCausePacket => ResetCause,
+    CrashRecord => CrashRecord,
+    LogLevelPacket => LogLevel,
+    LinkErrorsPacket => LinkErrors,
+    SerialConfigPacket => SerialConfig,
+    Hello => Hello,
+    Subscription => Subscription,
+    NodeAddress => NodeAddress,
+}
@@ -0,0 +1,390 @@
+//! Protocol state shared by the blocking and async clients, which only add the I/O.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use turret_protocol::datamodel::request::{Request, RequestKind};
+use turret_protocol::datamodel::subscription::{Subscription, Topic};
+use turret_protocol::envelope::{self, Message, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use turret_protocol::hello::{Hello, FULL_CRC, MIN_FRAME_SIZE};
+use turret_protocol::reliable::{Action, RetransmitPolicy, Sender};
+
+use crate::codec::Codec;
+use crate::error::Error;
+use crate::link::{Link, LinkStats, MAX_FRAME_SIZE, MAX_MESSAGE_SIZE};
+use crate::received::Received;
+
+/// How often reliable requests check whether they're due for a retransmission.
+const RELIABLE_TICK: Duration = Duration::from_millis(5);
+/// Unprompted messages kept until read, beyond which the oldest are dropped.
+const EVENT_CAPACITY: usize = 256;
+
+/// How a client talks to the device.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Config {
+    /// The device's address, see `turret_protocol::address`.
+    /// The broadcast address hears every device, so only one should be on the bus.
+    pub address: u8,
+    /// The codec the device speaks until a hello agrees on another.
+    pub codec: Codec,
+    /// How long to wait for the reply to a fire-and-forget request.
+    pub timeout: Duration,
+    /// When reliable requests are retransmitted, and given up on.
+    pub retransmit: RetransmitPolicy,
+}
+
+impl Default for Config {
+    /// Suits a firmware built with its defaults.
+    fn default() -> Self {
+        Self {
+            address: 1,
+            codec: Codec::Cbor,
+            timeout: Duration::from_millis(500),
+            retransmit: RetransmitPolicy::DEFAULT,
+        }
+    }
+}
+
+/// What was received, and what was dropped on the way.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Stats {
+    pub link: LinkStats,
+    /// Messages whose envelope or payload couldn't be decoded.
+    pub undecodable: u32,
+    /// Replies to requests no longer waited for, such as retransmissions' replies.
+    pub stale_replies: u32,
+    /// Unprompted messages dropped because they weren't read in time.
+    pub dropped_events: u32,
+}
+
+/// What a client should do next about its request.
+pub(crate) enum Step {
+    Done(Result<Received, Error>),
+    /// Write these frames again.
+    Send(Vec<u8>),
+    /// Read until this instant, or until anything arrives.
+    Wait(Instant),
+}
+
+/// A request waiting for its reply.
+struct Exchange {
+    request_id: u8,
+    frames: Vec<u8>,
+    /// Set for reliable requests, which are also waiting for their acknowledgement.
+    seq: Option<u8>,
+    deadline: Instant,
+    reply: Option<Received>,
+    acked: bool,
+}
+
+pub(crate) struct Session {
+    config: Config,
+    codec: Codec,
+    link: Link,
+    epoch: Instant,
+    next_request_id: u8,
+    sender: Sender,
+    exchange: Option<Exchange>,
+    events: VecDeque<Received>,
+    stats: Stats,
+}
+
+impl Session {
+    pub(crate) fn new(config: Config) -> Self {
+        Self {
+            config,
+            codec: config.codec,
+            link: Link::new(config.address),
+            epoch: Instant::now(),
+            next_request_id: 1,
+            sender: Sender::new(config.retransmit),
+            exchange: None,
+            events: VecDeque::new(),
+            stats: Stats::default(),
+        }
+    }
+
+    pub(crate) fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Talks to the device at `address` from now on.
+    pub(crate) fn set_address(&mut self, address: u8) {
+        self.config.address = address;
+        self.link.set_address(address);
+    }
+
+    /// The codec in use, which a hello may have changed.
+    pub(crate) fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    pub(crate) fn stats(&self) -> Stats {
+        Stats {
+            link: self.link.stats(),
+            ..self.stats
+        }
+    }
+
+    /// What this client supports.
+    pub(crate) fn offer() -> Hello {
+        Hello {
+            min_version: MIN_PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+            codecs: Codec::ALL
+                .iter()
+                .fold(0, |codecs, codec| codecs | codec.hello_bit()),
+            flags: FULL_CRC,
+            max_frame: MAX_FRAME_SIZE as u16,
+        }
+    }
+
+    /// Switches to what the device's reply to a hello agreed on.
+    pub(crate) fn agree(&mut self, reply: Received) -> Result<Hello, Error> {
+        match reply {
+            Received::Hello(agreed) if agreed.is_agreed() => {
+                // a buggy or foreign device may agree on what was never offered.
+                let offered = Self::offer();
+                let codec = Codec::from_hello_bit(agreed.codecs)
+                    .filter(|codec| offered.codecs & codec.hello_bit() != 0);
+                let frame_sizes = MIN_FRAME_SIZE..=offered.max_frame as usize;
+                match codec {
+                    Some(codec) if frame_sizes.contains(&(agreed.max_frame as usize)) => {
+                        self.codec = codec;
+                        self.link.apply(&agreed);
+                        Ok(agreed)
+                    }
+                    _ => Err(Error::UnexpectedReply(Received::Hello(agreed))),
+                }
+            }
+            Received::Hello(supported) => Err(Error::NoAgreement(supported)),
+            other => Err(Error::UnexpectedReply(other)),
+        }
+    }
+
+    pub(crate) fn full_crc(&self) -> bool {
+        self.link.full_crc()
+    }
+
+    /// Switches between CRCs covering the whole frame, and only its whole words, e.g. to reach
+    /// a device still at the settings agreed with an earlier client.
+    pub(crate) fn set_full_crc(&mut self, full: bool) {
+        self.link.set_full_crc(full);
+    }
+
+    /// Starts a request, returning the frames to write.
+    /// Reliable requests get a sequence number, and are retransmitted until acknowledged.
+    pub(crate) fn start_request(
+        &mut self,
+        mut request: Request,
+        reliable: bool,
+        now: Instant,
+    ) -> Result<Vec<u8>, Error> {
+        self.abandon();
+        request.seq = None;
+        if reliable {
+            // NOTE(unwrap): any previous reliable request was just abandoned.
+            request.seq = Some(self.sender.send(self.millis(now)).unwrap());
+        }
+        let seq = request.seq;
+        self.start(&Message::Request(request), seq, now)
+    }
+
+    /// Starts a fire-and-forget exchange of any message, returning the frames to write.
+    pub(crate) fn start_message(
+        &mut self,
+        message: &Message,
+        now: Instant,
+    ) -> Result<Vec<u8>, Error> {
+        self.abandon();
+        self.start(message, None, now)
+    }
+
+    fn start(
+        &mut self,
+        message: &Message,
+        seq: Option<u8>,
+        now: Instant,
+    ) -> Result<Vec<u8>, Error> {
+        let request_id = self.next_request_id;
+        // 0 means no request ID.
+        self.next_request_id = self.next_request_id.checked_add(1).unwrap_or(1);
+
+        let mut buffer = vec![0; MAX_MESSAGE_SIZE];
+        let len = envelope::encode(&self.codec, message, Some(request_id), &mut buffer)
+            .map_err(Error::Encode)?;
+        let mut frames = Vec::new();
+        self.link
+            .encode(&buffer[..len], &mut frames)
+            .map_err(Error::TooLarge)?;
+
+        self.exchange = Some(Exchange {
+            request_id,
+            frames: frames.clone(),
+            seq,
+            deadline: now + self.config.timeout,
+            reply: None,
+            acked: false,
+        });
+        Ok(frames)
+    }
+
+    /// Forgets a request nobody waits for anymore, e.g. because an I/O error interrupted it.
+    fn abandon(&mut self) {
+        if let Some(Exchange { seq: Some(seq), .. }) = self.exchange.take() {
+            self.sender.acknowledge(seq);
+        }
+    }
+
+    /// Feeds bytes read from the device.
+    pub(crate) fn receive(&mut self, bytes: &[u8]) {
+        let Self {
+            codec,
+            link,
+            exchange,
+            events,
+            stats,
+            ..
+        } = self;
+        link.receive(bytes, |message| {
+            let (request_id, received) = match envelope::decode(codec, message) {
+                Ok((header, message)) => (header.request_id, Received::from(message)),
+                Err(_) => {
+                    stats.undecodable += 1;
+                    return;
+                }
+            };
+            match (request_id, exchange.as_mut()) {
+                (None, _) => {
+                    if events.len() == EVENT_CAPACITY {
+                        events.pop_front();
+                        stats.dropped_events += 1;
+                    }
+                    events.push_back(received);
+                }
+                (Some(id), Some(exchange)) if id == exchange.request_id => match received {
+                    Received::Ack(ack) if Some(ack.seq) == exchange.seq => exchange.acked = true,
+                    // an error stands, whatever else arrives.
+                    _ if matches!(exchange.reply, Some(Received::Error(_))) => {}
+                    reply => exchange.reply = Some(reply),
+                },
+                (Some(_), _) => stats.stale_replies += 1,
+            }
+        });
+    }
+
+    /// Decides what to do about the request in progress.
+    pub(crate) fn step(&mut self, now: Instant) -> Step {
+        let now_ms = self.millis(now);
+        // NOTE(unwrap): only called while a request is in progress.
+        let exchange = self.exchange.as_mut().unwrap();
+        if let Some(Received::Error(error)) = exchange.reply {
+            self.abandon();
+            return Step::Done(Err(Error::Device(error.code)));
+        }
+        if exchange.reply.is_some() && (exchange.seq.is_none() || exchange.acked) {
+            // NOTE(unwrap): checked just above.
+            let reply = self.finish().reply.unwrap();
+            return Step::Done(Ok(reply));
+        }
+
+        if exchange.seq.is_some() {
+            // a lost reply or acknowledgement is recovered by retransmitting, the device replies
+            // to retransmissions too without executing them again.
+            match self.sender.poll(now_ms) {
+                Action::Wait => Step::Wait(now + RELIABLE_TICK),
+                Action::Retransmit(_) => Step::Send(exchange.frames.clone()),
+                Action::GiveUp(_) => {
+                    self.exchange = None;
+                    Step::Done(Err(Error::Timeout))
+                }
+            }
+        } else if now >= exchange.deadline {
+            self.exchange = None;
+            Step::Done(Err(Error::Timeout))
+        } else {
+            Step::Wait(exchange.deadline)
+        }
+    }
+
+    fn finish(&mut self) -> Exchange {
+        // NOTE(unwrap): only called while a request is in progress.
+        let exchange = self.exchange.take().unwrap();
+        if let Some(seq) = exchange.seq {
+            self.sender.acknowledge(seq);
+        }
+        exchange
+    }
+
+    /// The oldest unprompted message, such as telemetry or a log record.
+    pub(crate) fn take_event(&mut self) -> Option<Received> {
+        self.events.pop_front()
+    }
+
+    fn millis(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.epoch).as_millis() as u64
+    }
+}
+
+/// A request of `kind` without arguments.
+pub(crate) fn request(kind: RequestKind) -> Request {
+    Request {
+        kind,
+        log_level: None,
+        serial: None,
+        seq: None,
+    }
+}
+
+/// A subscription to `topic` every `period`, to the millisecond.
+pub(crate) fn subscription(topic: Topic, period: Duration) -> Subscription {
+    Subscription {
+        topic,
+        period_ms: period.as_millis().min(u128::from(u32::MAX)) as u32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use turret_protocol::hello::{AGREED, CODEC_CBOR, CODEC_JSON};
+
+    fn agreed(codecs: u8, max_frame: u16) -> Received {
+        Received::Hello(Hello {
+            min_version: PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+            codecs,
+            flags: AGREED | FULL_CRC,
+            max_frame,
+        })
+    }
+
+    #[test]
+    fn agreements_switch_the_settings() {
+        let mut session = Session::new(Config::default());
+        session.agree(agreed(CODEC_JSON, 32)).unwrap();
+        assert_eq!(session.codec(), Codec::Json);
+        assert!(session.full_crc());
+    }
+
+    #[test]
+    fn agreements_on_what_wasnt_offered_are_refused() {
+        let mut session = Session::new(Config::default());
+        for reply in [
+            agreed(0, 64),
+            agreed(CODEC_CBOR | CODEC_JSON, 64),
+            agreed(1 << 6, 64),
+            agreed(CODEC_JSON, MIN_FRAME_SIZE as u16 - 1),
+            agreed(CODEC_JSON, 0),
+            agreed(CODEC_JSON, MAX_FRAME_SIZE as u16 + 1),
+        ] {
+            assert!(matches!(
+                session.agree(reply),
+                Err(Error::UnexpectedReply(Received::Hello(_)))
+            ));
+        }
+        // still at the settings it started with.
+        assert_eq!(session.codec(), Codec::Cbor);
+        assert!(!session.full_crc());
+    }
+}
@@ -0,0 +1,44 @@
+#![cfg(feature = "async")]
+
+mod common;
+
+use std::time::Duration;
+
+use common::still;
+use turret_client::protocol::datamodel::log_level::LogLevel;
+use turret_client::protocol::datamodel::subscription::Topic;
+use turret_client::protocol::hello::FULL_CRC;
+use turret_client::{AsyncClient, Codec, Received};
+use turret_sim::{Faults, Options, Simulator};
+
+#[tokio::test]
+async fn requests_get_their_replies() {
+    let sim = Simulator::spawn(Options {
+        codecs: vec![Codec::Json, Codec::Cbor],
+        faults: Faults {
+            lost_messages: 1,
+            ..Faults::default()
+        },
+        ..still(42)
+    })
+    .unwrap();
+    let mut client = AsyncClient::open(sim.path(), 115_200).unwrap();
+    // the hello's reply is lost after the device switched to full CRCs, which the retry uses.
+    assert_ne!(client.hello().await.unwrap().flags & FULL_CRC, 0);
+    assert_eq!(client.codec(), Codec::Json);
+    assert_eq!(client.telemetry().await.unwrap().turret_pos, 42);
+    assert_eq!(
+        client.set_log_level(LogLevel::Trace).await.unwrap().level,
+        LogLevel::Trace
+    );
+    // neither was retransmitted.
+    assert_eq!(sim.requests(), 2);
+    client
+        .subscribe(Topic::Telemetry, Duration::from_millis(20))
+        .await
+        .unwrap();
+    match client.next_event(Duration::from_secs(1)).await.unwrap() {
+        Some(Received::Telemetry(telemetry)) => assert_eq!(telemetry.turret_pos, 42),
+        other => panic!("expected telemetry, got {:?}", other),
+    }
+}
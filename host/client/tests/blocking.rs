@@ -0,0 +1,276 @@
+mod common;
+
+use std::time::Duration;
+
+use common::still;
+use turret_client::protocol::address::BROADCAST;
+use turret_client::protocol::datamodel::crash_record::CrashKind;
+use turret_client::protocol::datamodel::error::ErrorCode;
+use turret_client::protocol::datamodel::log_level::LogLevel;
+use turret_client::protocol::datamodel::reset_cause::ResetCause;
+use turret_client::protocol::datamodel::serial_config::{Parity, SerialConfig};
+use turret_client::protocol::datamodel::subscription::Topic;
+use turret_client::protocol::datamodel::telemetry_packet::TurretDirection;
+use turret_client::protocol::hello::FULL_CRC;
+use turret_client::{Client, Codec, Config, Error, Received};
+use turret_sim::{Faults, Options, Simulator};
+
+fn simulate(options: Options) -> (Simulator, Client) {
+    let sim = Simulator::spawn(options).unwrap();
+    let client = Client::open(sim.path(), 115_200).unwrap();
+    (sim, client)
+}
+
+#[test]
+fn requests_get_their_replies() {
+    let (_sim, mut client) = simulate(still(1234));
+    assert_eq!(client.telemetry().unwrap().turret_pos, 1234);
+    assert_eq!(client.reset_cause().unwrap().cause, ResetCause::PowerOn);
+    assert_eq!(client.crash_record().unwrap().kind, CrashKind::None);
+    assert_eq!(client.link_errors().unwrap().tx_timeouts, 0);
+    assert_eq!(client.stats().link.bad_crc, 0);
+}
+
+#[test]
+fn hello_switches_to_the_agreed_settings() {
+    let (_sim, mut client) = simulate(Options {
+        codecs: vec![Codec::Json, Codec::Cbor],
+        frame_size: 32,
+        ..still(7)
+    });
+    let agreed = client.hello().unwrap();
+    assert_eq!(client.codec(), Codec::Json);
+    assert_ne!(agreed.flags & FULL_CRC, 0);
+    assert_eq!(agreed.max_frame, 32);
+    // JSON replies take several of these small frames.
+    assert_eq!(client.telemetry().unwrap().turret_pos, 7);
+    assert_eq!(client.log_level().unwrap().level, LogLevel::Info);
+}
+
+#[test]
+fn hello_reaches_a_device_at_earlier_settings() {
+    let (sim, mut client) = simulate(still(3));
+    client.hello().unwrap();
+    drop(client);
+    // a new client starts at the defaults, while the device kept the full CRCs agreed on.
+    let mut client = Client::open(sim.path(), 115_200).unwrap();
+    assert_ne!(client.hello().unwrap().flags & FULL_CRC, 0);
+    assert_eq!(client.telemetry().unwrap().turret_pos, 3);
+}
+
+#[test]
+fn lost_replies_are_retransmitted_and_executed_once() {
+    // a count per millisecond, so zeroing twice would read zero.
+    let script = "0 0\n100000 100000\n".parse().unwrap();
+    let (sim, mut client) = simulate(Options {
+        script,
+        // the first attempt's reply and acknowledgement.
+        faults: Faults {
+            lost_messages: 2,
+            ..Faults::default()
+        },
+        ..Options::default()
+    });
+    // the retransmission's reply reads the count since the first attempt zeroed it.
+    assert!(client.zero().unwrap().turret_pos > 0);
+    assert_eq!(sim.requests(), 2);
+}
+
+#[test]
+fn zeroing_follows_the_script() {
+    let script = "0 100\n200 300\n".parse().unwrap();
+    let (_sim, mut client) = simulate(Options {
+        script,
+        ..Options::default()
+    });
+    let moving = client.telemetry().unwrap();
+    assert!(matches!(moving.turret_rot, TurretDirection::Forward));
+    std::thread::sleep(Duration::from_millis(300));
+    assert_eq!(client.telemetry().unwrap().turret_pos, 300);
+    assert_eq!(client.zero().unwrap().turret_pos, 0);
+    assert_eq!(client.telemetry().unwrap().turret_pos, 0);
+}
+
+#[test]
+fn saved_settings_survive_a_reboot() {
+    let (sim, mut client) = simulate(still(5));
+    client.hello().unwrap();
+    client.set_log_level(LogLevel::Warn).unwrap();
+    assert_eq!(client.save_config().unwrap().level, LogLevel::Warn);
+    client.set_log_level(LogLevel::Trace).unwrap();
+    assert_eq!(client.reboot().unwrap().cause, ResetCause::Software);
+
+    // the device is back at its defaults, which a new session starts from.
+    drop(client);
+    let mut client = Client::open(sim.path(), 115_200).unwrap();
+    assert_eq!(client.log_level().unwrap().level, LogLevel::Warn);
+    assert_eq!(client.reset_cause().unwrap().cause, ResetCause::Software);
+}
+
+#[test]
+fn devices_can_be_moved_to_another_address() {
+    let (sim, mut client) = simulate(still(5));
+    assert_eq!(client.node_address().unwrap().address, Some(1));
+    assert_eq!(client.set_node_address(9).unwrap().address, Some(9));
+    // the client followed the device.
+    assert_eq!(client.config().address, 9);
+    assert_eq!(client.telemetry().unwrap().turret_pos, 5);
+    match client.set_node_address(BROADCAST) {
+        Err(Error::Device(ErrorCode::Malformed)) => {}
+        other => panic!("expected a malformed error, got {:?}", other),
+    }
+    assert_eq!(client.reboot().unwrap().cause, ResetCause::Software);
+
+    // the device kept its address, which a lone device gives through the broadcast address.
+    drop(client);
+    let mut client = Client::open(sim.path(), 115_200).unwrap();
+    assert!(matches!(client.telemetry(), Err(Error::Timeout)));
+    drop(client);
+    let config = Config {
+        address: BROADCAST,
+        ..Config::default()
+    };
+    let mut client = Client::open_with(sim.path(), 115_200, config).unwrap();
+    assert_eq!(client.node_address().unwrap().address, Some(9));
+}
+
+#[test]
+fn serial_settings_are_switched() {
+    let (_sim, mut client) = simulate(still(5));
+    let requested = SerialConfig {
+        baud: 230_400,
+        parity: Parity::Even,
+        ..SerialConfig::DEFAULT
+    };
+    assert!(client.switch_serial(requested).unwrap().switching);
+    assert_eq!(client.serial_config().unwrap().config, requested);
+}
+
+#[test]
+fn device_errors_are_reported() {
+    let (_sim, mut client) = simulate(Options {
+        faults: Faults {
+            busy: true,
+            ..Faults::default()
+        },
+        ..Options::default()
+    });
+    match client.reset_cause() {
+        Err(Error::Device(ErrorCode::Busy)) => {}
+        other => panic!("expected a busy error, got {:?}", other),
+    }
+}
+
+#[test]
+fn silent_devices_time_out() {
+    let sim = Simulator::spawn(Options {
+        faults: Faults {
+            silent: true,
+            ..Faults::default()
+        },
+        ..Options::default()
+    })
+    .unwrap();
+    let port = serialport::new(sim.path(), 115_200)
+        .timeout(Duration::from_millis(10))
+        .open()
+        .unwrap();
+    let config = Config {
+        timeout: Duration::from_millis(50),
+        ..Config::default()
+    };
+    let mut client = Client::new(port, config);
+    assert!(matches!(client.telemetry(), Err(Error::Timeout)));
+    // reliable requests give up after their last retransmission.
+    assert!(matches!(
+        client.set_log_level(LogLevel::Warn),
+        Err(Error::Timeout)
+    ));
+    let attempts = config.retransmit.max_attempts as usize;
+    assert_eq!(sim.requests(), 1 + attempts);
+}
+
+#[test]
+fn unprompted_telemetry_is_an_event() {
+    let (_sim, mut client) = simulate(Options {
+        telemetry_period: Some(Duration::from_millis(20)),
+        ..still(99)
+    });
+    // replies are told apart from the telemetry arriving meanwhile.
+    assert_eq!(client.log_level().unwrap().level, LogLevel::Info);
+    match client.next_event(Duration::from_secs(1)).unwrap() {
+        Some(Received::Telemetry(telemetry)) => assert_eq!(telemetry.turret_pos, 99),
+        other => panic!("expected telemetry, got {:?}", other),
+    }
+}
+
+#[test]
+fn subscribed_topics_are_streamed() {
+    let (_sim, mut client) = simulate(still(11));
+    let granted = client
+        .subscribe(Topic::LinkErrors, Duration::from_millis(100))
+        .unwrap();
+    assert_eq!(granted.period_ms, 100);
+    // more often than the line carries, so the device slows it down.
+    let granted = client
+        .subscribe(Topic::Telemetry, Duration::from_millis(1))
+        .unwrap();
+    assert!(granted.period_ms > 1);
+
+    let (mut telemetry, mut link_errors) = (0, 0);
+    while telemetry < 3 || link_errors < 2 {
+        match client.next_event(Duration::from_secs(1)).unwrap() {
+            Some(Received::Telemetry(packet)) => {
+                assert_eq!(packet.turret_pos, 11);
+                telemetry += 1;
+            }
+            Some(Received::LinkErrors(_)) => link_errors += 1,
+            other => panic!("expected a subscribed topic, got {:?}", other),
+        }
+    }
+
+    let granted = client.subscribe(Topic::Telemetry, Duration::ZERO).unwrap();
+    assert_eq!(granted.period_ms, 0);
+    // whatever was sent before the reply arrived before it.
+    while client.next_event(Duration::ZERO).unwrap().is_some() {}
+    match client.next_event(Duration::from_millis(300)).unwrap() {
+        Some(Received::LinkErrors(_)) => {}
+        other => panic!("expected link errors only, got {:?}", other),
+    }
+}
+
+#[test]
+fn batches_hold_every_sample() {
+    // a count per millisecond.
+    let script = "0 0\n100000 100000\n".parse().unwrap();
+    let (_sim, mut client) = simulate(Options {
+        script,
+        ..Options::default()
+    });
+    let granted = client
+        .subscribe(Topic::TelemetryBatch, Duration::from_millis(50))
+        .unwrap();
+    assert_eq!(granted.period_ms, 50);
+
+    let mut next_ms = None;
+    let mut samples = 0;
+    while samples < 200 {
+        let batch = match client.next_event(Duration::from_secs(1)).unwrap() {
+            Some(Received::TelemetryBatch(batch)) => batch,
+            other => panic!("expected a batch, got {:?}", other),
+        };
+        assert_eq!(batch.dropped, 0);
+        for (t, pos) in batch.samples() {
+            assert_eq!(t, next_ms.unwrap_or(t));
+            // sampled at `t`, or later while the simulator was busy.
+            assert!(pos >= t, "{} at {}ms", pos, t);
+            next_ms = Some(t + 1);
+            samples += 1;
+        }
+    }
+
+    let granted = client
+        .subscribe(Topic::TelemetryBatch, Duration::ZERO)
+        .unwrap();
+    assert_eq!(granted.period_ms, 0);
+}
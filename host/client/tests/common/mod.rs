@@ -0,0 +1,12 @@
+//! The device the tests talk to: the firmware's task logic running in the simulator, on the far
+//! end of a pseudo-terminal.
+
+use turret_sim::{Options, Script};
+
+/// The firmware's default build, watching a turret that stays at `position`.
+pub fn still(position: i64) -> Options {
+    Options {
+        script: Script::still(position),
+        ..Options::default()
+    }
+}
@@ -0,0 +1,40 @@
+//! The client's framing, against the golden vectors in `protocol/vectors`.
+
+use turret_client::codec::Codec;
+use turret_client::link::Link;
+use turret_client::protocol::address::BROADCAST;
+use turret_client::protocol::envelope;
+use turret_client::protocol::vectors::{self, to_hex, Vector};
+
+#[test]
+fn requests_are_sent_as_the_vectors() {
+    for vector in vectors::all().into_iter().filter(|v| !v.from_device()) {
+        let codec = Codec::from_hello_bit(vector.codec).unwrap();
+        let mut buffer = [0u8; 256];
+        let len =
+            envelope::encode(&codec, &vector.message(), vector.request_id, &mut buffer).unwrap();
+        let mut link = Link::new(vector.address);
+        link.set_frame_size(vector.frame_size);
+        link.set_full_crc(vector.full_crc);
+        let mut wire = Vec::new();
+        link.encode(&buffer[..len], &mut wire).unwrap();
+        assert_eq!(to_hex(&wire), to_hex(&vector.frame), "{}", vector.name);
+    }
+}
+
+#[test]
+fn replies_are_received_as_their_messages() {
+    for vector in vectors::all().into_iter().filter(Vector::from_device) {
+        let codec = Codec::from_hello_bit(vector.codec).unwrap();
+        let mut link = Link::new(BROADCAST);
+        link.set_full_crc(vector.full_crc);
+        let mut messages = 0;
+        link.receive(&vector.frame, |message| {
+            let (header, decoded) = envelope::decode(&codec, message).unwrap();
+            assert_eq!(header.request_id, vector.request_id, "{}", vector.name);
+            assert!(vector.matches(&decoded), "{}", vector.name);
+            messages += 1;
+        });
+        assert_eq!(messages, 1, "{}", vector.name);
+    }
+}
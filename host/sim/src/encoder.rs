@@ -0,0 +1,232 @@
+//! A simulated encoder, following a script of the turret's positions over time.
+//!
+//! A script holds a keyframe per line, a time in milliseconds and the position in encoder
+//! counts the turret is at by then. In between, the turret moves at a constant speed.
+//! `#` starts a comment, and a `loop` line repeats the script from the start once its last
+//! keyframe is reached, otherwise the turret stays there.
+//!
+//! ```text
+//! # sweep a quarter turn back and forth, every four seconds.
+//! 0     0
+//! 2000  1000
+//! 4000  0
+//! loop
+//! ```
+//!
+//! Positions may be negative, they wrap around like the hardware counter does.
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use turret_client::protocol::datamodel::telemetry_packet::TurretDirection;
+use turret_device::hal::{Clock, Encoder};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Script {
+    /// Times and positions, in order of time.
+    keyframes: Vec<(u32, i64)>,
+    looping: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptError {
+    /// The line at fault, counting from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl Script {
+    /// A turret that stays at `position`.
+    pub fn still(position: i64) -> Self {
+        Self {
+            keyframes: vec![(0, position)],
+            looping: false,
+        }
+    }
+
+    /// Where the turret is `t` milliseconds into the script, and which way it's turning.
+    fn at(&self, t: u32) -> (i64, Option<TurretDirection>) {
+        // NOTE(unwrap): parsing rejects scripts without keyframes.
+        let &(end, last) = self.keyframes.last().unwrap();
+        let t = if self.looping && end > 0 { t % end } else { t };
+        let next = match self.keyframes.iter().position(|&(time, _)| time > t) {
+            Some(0) => return (self.keyframes[0].1, None),
+            Some(next) => next,
+            None => return (last, None),
+        };
+        let (t0, p0) = self.keyframes[next - 1];
+        let (t1, p1) = self.keyframes[next];
+        let position = p0 + (p1 - p0) * (t - t0) as i64 / (t1 - t0) as i64;
+        let direction = match p1.cmp(&p0) {
+            std::cmp::Ordering::Greater => Some(TurretDirection::Forward),
+            std::cmp::Ordering::Less => Some(TurretDirection::Backward),
+            std::cmp::Ordering::Equal => None,
+        };
+        (position, direction)
+    }
+}
+
+impl Default for Script {
+    /// The sweep in the module's example.
+    fn default() -> Self {
+        Self {
+            keyframes: vec![(0, 0), (2000, 1000), (4000, 0)],
+            looping: true,
+        }
+    }
+}
+
+impl FromStr for Script {
+    type Err = ScriptError;
+
+    fn from_str(script: &str) -> Result<Self, ScriptError> {
+        let mut keyframes: Vec<(u32, i64)> = Vec::new();
+        let mut looping = false;
+        for (i, line) in script.lines().enumerate() {
+            let error = |message: &str| ScriptError {
+                line: i + 1,
+                message: message.to_string(),
+            };
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line == "loop" {
+                looping = true;
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let (time, position) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(time), Some(position), None) => (time, position),
+                _ => return Err(error("expected a time and a position")),
+            };
+            let time: u32 = time.parse().map_err(|_| error("invalid time"))?;
+            let position: i64 = position.parse().map_err(|_| error("invalid position"))?;
+            if keyframes.last().is_some_and(|&(last, _)| time <= last) {
+                return Err(error("keyframes must be in order of time"));
+            }
+            keyframes.push((time, position));
+        }
+        if keyframes.is_empty() {
+            return Err(ScriptError {
+                line: 0,
+                message: "the script has no keyframes".to_string(),
+            });
+        }
+        Ok(Self { keyframes, looping })
+    }
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for ScriptError {}
+
+/// An [`Encoder`] following a [`Script`], which starts when the encoder is created.
+pub struct ScriptedEncoder<C> {
+    script: Script,
+    clock: C,
+    start_ms: u32,
+    /// The position counted from, which zeroing moves.
+    zero: i64,
+}
+
+impl<C: Clock> ScriptedEncoder<C> {
+    pub fn new(script: Script, clock: C) -> Self {
+        let start_ms = clock.now_ms();
+        Self {
+            script,
+            clock,
+            start_ms,
+            zero: 0,
+        }
+    }
+
+    fn now(&self) -> (i64, Option<TurretDirection>) {
+        self.script
+            .at(self.clock.now_ms().wrapping_sub(self.start_ms))
+    }
+
+    /// Makes the current position read as zero, as resetting the hardware counter does.
+    pub fn zero(&mut self) {
+        self.zero = self.now().0;
+    }
+}
+
+impl<C: Clock> Encoder for ScriptedEncoder<C> {
+    fn count(&self) -> u32 {
+        // wraps around like the 32 bit hardware counter.
+        (self.now().0 - self.zero) as u32
+    }
+
+    fn direction(&self) -> TurretDirection {
+        // the hardware keeps reporting the way it last counted, which a still turret can't tell.
+        self.now().1.unwrap_or(TurretDirection::Forward)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct ManualClock(Rc<Cell<u32>>);
+
+    impl Clock for ManualClock {
+        fn now_ms(&self) -> u32 {
+            self.0.get()
+        }
+    }
+
+    #[test]
+    fn scripts_are_parsed() {
+        let script: Script = "# a comment\n0 0\n\n100 -50 # back\nloop\n"
+            .parse()
+            .unwrap();
+        assert_eq!(script.keyframes, vec![(0, 0), (100, -50)]);
+        assert!(script.looping);
+        assert_eq!("0 0\n0 1".parse::<Script>().unwrap_err().line, 2);
+        assert_eq!("0 zero".parse::<Script>().unwrap_err().line, 1);
+        assert!("# nothing".parse::<Script>().is_err());
+    }
+
+    #[test]
+    fn keyframes_are_interpolated() {
+        let clock = ManualClock::default();
+        let script: Script = "1000 100\n2000 -100\n".parse().unwrap();
+        let mut encoder = ScriptedEncoder::new(script, clock.clone());
+        assert_eq!(encoder.count(), 100);
+        clock.0.set(1500);
+        assert_eq!(encoder.count(), 0);
+        assert!(matches!(encoder.direction(), TurretDirection::Backward));
+        clock.0.set(1750);
+        assert_eq!(encoder.count(), (-50i64) as u32);
+        clock.0.set(5000);
+        assert_eq!(encoder.count(), (-100i64) as u32);
+
+        encoder.zero();
+        assert_eq!(encoder.count(), 0);
+    }
+
+    #[test]
+    fn looping_scripts_repeat() {
+        let clock = ManualClock::default();
+        let mut encoder = ScriptedEncoder::new(Script::default(), clock.clone());
+        clock.0.set(1000);
+        assert_eq!(encoder.count(), 500);
+        assert!(matches!(encoder.direction(), TurretDirection::Forward));
+        clock.0.set(4000 + 3000);
+        assert_eq!(encoder.count(), 500);
+        assert!(matches!(encoder.direction(), TurretDirection::Backward));
+        encoder.zero();
+        clock.0.set(8000);
+        assert_eq!(encoder.count(), (-500i64) as u32);
+    }
+}
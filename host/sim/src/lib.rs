@@ -0,0 +1,21 @@
+//! Software-in-the-loop simulator of the turret monitor firmware.
+//!
+//! Runs the firmware's task logic from `turret_device` on Linux: requests arrive on a
+//! pseudo-terminal instead of USART1, and telemetry follows a scripted encoder instead of the
+//! turret, so host software can be integration tested without hardware.
+//!
+//! ```no_run
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let sim = turret_sim::Simulator::spawn(turret_sim::Options::default())?;
+//! let mut client = turret_client::Client::open(sim.path(), 115_200)?;
+//! client.hello()?;
+//! println!("turret at {}", client.telemetry()?.turret_pos);
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod encoder;
+mod simulator;
+
+pub use encoder::{Script, ScriptError, ScriptedEncoder};
+pub use simulator::{Faults, Options, Simulator, SystemClock, MAX_FRAME_SIZE};
@@ -0,0 +1,80 @@
+//! Simulates a turret monitor on a pseudo-terminal, for host software to talk to.
+//!
+//! ```text
+//! turret_sim --script sweep.txt --telemetry-period 100
+//! turretctl --port "$(the path it prints)" telemetry --follow
+//! ```
+
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+
+use clap::{Parser, ValueEnum};
+use turret_client::Codec;
+use turret_sim::{Faults, Options, Script, Simulator};
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Where the turret is over time, see the book's simulator chapter. Sweeps by default.
+    #[arg(long)]
+    script: Option<PathBuf>,
+    /// The device's address on the bus.
+    #[arg(long, default_value_t = 1)]
+    address: u8,
+    /// The codecs the device is built with, most preferred first.
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "cbor")]
+    codecs: Vec<CodecArg>,
+    /// Size of the frames sent, as set with `TURRET_BUF_SIZE`.
+    #[arg(long, default_value_t = 64)]
+    frame_size: usize,
+    /// Subscribe to telemetry at this period from boot, in milliseconds, until a hello.
+    #[arg(long)]
+    telemetry_period: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum CodecArg {
+    Cbor,
+    Postcard,
+    Json,
+}
+
+fn main() {
+    let cli = Cli::parse();
+    if let Err(e) = run(cli) {
+        eprintln!("turret_sim: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let script = match &cli.script {
+        Some(path) => fs::read_to_string(path)?
+            .parse()
+            .map_err(|e| format!("{}: {}", path.display(), e))?,
+        None => Script::default(),
+    };
+    let options = Options {
+        address: cli.address,
+        codecs: cli
+            .codecs
+            .iter()
+            .map(|codec| match codec {
+                CodecArg::Cbor => Codec::Cbor,
+                CodecArg::Postcard => Codec::Postcard,
+                CodecArg::Json => Codec::Json,
+            })
+            .collect(),
+        frame_size: cli.frame_size,
+        telemetry_period: cli.telemetry_period.map(Duration::from_millis),
+        script,
+        faults: Faults::default(),
+    };
+    let sim = Simulator::spawn(options)?;
+    // the only thing printed, so scripts can read the path.
+    println!("{}", sim.path());
+    sim.wait();
+    Ok(())
+}
@@ -0,0 +1,532 @@
+//! The simulated device, answering requests on a pseudo-terminal like the firmware does.
+
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use serialport::{SerialPort, TTYPort};
+use turret_client::protocol::address;
+use turret_client::protocol::datamodel::crash_record::{CrashKind, CrashRecordPacket};
+use turret_client::protocol::datamodel::error::{ErrorCode, ErrorPacket};
+use turret_client::protocol::datamodel::link_errors::LinkErrorsPacket;
+use turret_client::protocol::datamodel::log_level::{LogLevel, LogLevelPacket};
+use turret_client::protocol::datamodel::node_address::NodeAddress;
+use turret_client::protocol::datamodel::request::{Request, RequestKind};
+use turret_client::protocol::datamodel::reset_cause::{ResetCause, ResetCausePacket};
+use turret_client::protocol::datamodel::serial_config::{SerialConfig, SerialConfigPacket};
+use turret_client::protocol::datamodel::subscription::{Subscription, Topic};
+use turret_client::protocol::envelope::{Message, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use turret_client::protocol::hello::{Hello, FULL_CRC, MIN_FRAME_SIZE};
+use turret_client::Codec;
+use turret_device::batch::Batcher;
+use turret_device::crc::SoftwareCrc;
+use turret_device::hal::{Clock, Encoder, SerialTx};
+use turret_device::rx::{Received, Receiver};
+use turret_device::schedule::{self, Scheduler};
+use turret_device::telemetry;
+use turret_device::tx::{encode, Priority, TxQueue};
+use turret_device::LinkConfig;
+
+use crate::encoder::{Script, ScriptedEncoder};
+
+/// Largest frame size a simulated device can be given.
+pub const MAX_FRAME_SIZE: usize = 256;
+/// Largest message, the firmware's default `TURRET_MAX_MESSAGE_SIZE`.
+const MAX_MESSAGE_SIZE: usize = 256;
+/// Capacity of each priority's queue, plenty for the host tools' traffic.
+const TX_QUEUE_SIZE: usize = 64;
+
+/// How the simulated device is built and behaves.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// The device's address on the bus until a host moves it, the firmware's
+    /// `TURRET_NODE_ADDRESS`.
+    pub address: u8,
+    /// Codecs the device was built with, in order of preference.
+    pub codecs: Vec<Codec>,
+    /// Size of the frames sent, the firmware's `TURRET_BUF_SIZE`.
+    pub frame_size: usize,
+    /// Subscribes to telemetry at this period from boot, as if a host had.
+    pub telemetry_period: Option<Duration>,
+    /// Where the turret is over time.
+    pub script: Script,
+    /// Faults to inject, none by default.
+    pub faults: Faults,
+}
+
+impl Default for Options {
+    /// The firmware's default build, watching a sweeping turret.
+    fn default() -> Self {
+        Self {
+            address: 1,
+            codecs: vec![Codec::Cbor],
+            frame_size: 64,
+            telemetry_period: None,
+            script: Script::default(),
+            faults: Faults::default(),
+        }
+    }
+}
+
+/// Faults of the device or the line, to test how host software copes with them.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Faults {
+    /// Messages lost on the way to the host, before the rest get through.
+    pub lost_messages: usize,
+    /// Replies `Busy` to every request, as the firmware does when it can't spawn their replies.
+    pub busy: bool,
+    /// Never sends anything, as if the line to the host was cut.
+    pub silent: bool,
+}
+
+/// A simulated device, running until dropped.
+pub struct Simulator {
+    /// Path of the host's end of the pseudo-terminal.
+    path: String,
+    requests: Arc<AtomicUsize>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Simulator {
+    /// Starts simulating a device on a new pseudo-terminal, see [`path`](Self::path).
+    pub fn spawn(options: Options) -> io::Result<Self> {
+        if options.codecs.is_empty() {
+            return Err(invalid("the device needs at least one codec"));
+        }
+        if !address::is_node(options.address) {
+            return Err(invalid(&format!(
+                "addresses must be below the broadcast address, {}",
+                address::BROADCAST
+            )));
+        }
+        if !(MIN_FRAME_SIZE..=MAX_FRAME_SIZE).contains(&options.frame_size) {
+            return Err(invalid(&format!(
+                "frames must be {} to {} bytes",
+                MIN_FRAME_SIZE, MAX_FRAME_SIZE
+            )));
+        }
+        let (device_end, host_end) = TTYPort::pair().map_err(io::Error::from)?;
+        // closed, so the host can open it by path like any serial port.
+        let path = host_end
+            .name()
+            .ok_or_else(|| invalid("the pseudo-terminal has no name"))?;
+        drop(host_end);
+        let requests = Arc::new(AtomicUsize::new(0));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let requests = requests.clone();
+            let stop = stop.clone();
+            thread::spawn(move || Device::new(device_end, options, requests).run(&stop))
+        };
+        Ok(Self {
+            path,
+            requests,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Path of the host's end of the pseudo-terminal, to open as the device's serial port.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Requests received so far, retransmissions included.
+    pub fn requests(&self) -> usize {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    /// Keeps simulating until the simulator panics.
+    pub fn wait(mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Simulator {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Milliseconds since the simulator started.
+#[derive(Debug, Clone, Copy)]
+pub struct SystemClock {
+    epoch: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        Self {
+            epoch: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now_ms(&self) -> u32 {
+        self.epoch.elapsed().as_millis() as u32
+    }
+}
+
+/// The pseudo-terminal, as the serial port frames are sent on. Frames are written whole, so
+/// it's never busy.
+struct Pty<'a>(&'a mut TTYPort);
+
+impl SerialTx for Pty<'_> {
+    fn is_busy(&self) -> bool {
+        false
+    }
+
+    fn start(&mut self, frame: &[u8]) {
+        // lost while the host's end is closed, as on a real line.
+        let _ = self.0.write_all(frame);
+    }
+}
+
+/// What the firmware keeps in its tasks' resources, and what's reset along with it.
+struct Device {
+    port: TTYPort,
+    options: Options,
+    clock: SystemClock,
+    encoder: ScriptedEncoder<SystemClock>,
+    crc: SoftwareCrc,
+    codec: Codec,
+    link: LinkConfig,
+    receiver: Receiver<MAX_FRAME_SIZE, MAX_MESSAGE_SIZE>,
+    queue: TxQueue<MAX_FRAME_SIZE, TX_QUEUE_SIZE>,
+    reset_cause: ResetCause,
+    log_level: LogLevel,
+    /// The log level kept across resets.
+    saved_log_level: Option<LogLevel>,
+    /// The address a host moved the device to, kept across resets.
+    saved_address: Option<u8>,
+    serial: SerialConfig,
+    subscriptions: Scheduler,
+    batcher: Batcher,
+    /// When the turret was last sampled for the batcher.
+    sampled_ms: u32,
+    /// Messages still to be lost, see [`Faults::lost_messages`].
+    lost_messages: usize,
+    requests: Arc<AtomicUsize>,
+}
+
+impl Device {
+    fn new(mut port: TTYPort, options: Options, requests: Arc<AtomicUsize>) -> Self {
+        let _ = port.set_timeout(Duration::from_millis(1));
+        let clock = SystemClock::new();
+        let mut device = Self {
+            port,
+            encoder: ScriptedEncoder::new(options.script.clone(), clock),
+            clock,
+            crc: SoftwareCrc::new(),
+            codec: options.codecs[0],
+            link: LinkConfig::new(options.address, options.frame_size),
+            receiver: Receiver::new(),
+            queue: TxQueue::new(),
+            reset_cause: ResetCause::PowerOn,
+            log_level: LogLevel::Info,
+            saved_log_level: None,
+            saved_address: None,
+            serial: SerialConfig::DEFAULT,
+            subscriptions: Scheduler::new(),
+            batcher: Batcher::new(),
+            sampled_ms: 0,
+            lost_messages: options.faults.lost_messages,
+            requests,
+            options,
+        };
+        device.subscribe_from_boot();
+        device
+    }
+
+    /// Makes the subscriptions the options ask for from boot.
+    fn subscribe_from_boot(&mut self) {
+        if let Some(period) = self.options.telemetry_period {
+            self.subscribe(Subscription {
+                topic: Topic::Telemetry,
+                period_ms: period.as_millis() as u32,
+            });
+        }
+    }
+
+    fn run(mut self, stop: &AtomicBool) {
+        let mut buffer = [0u8; MAX_FRAME_SIZE];
+        while !stop.load(Ordering::Relaxed) {
+            let n = match self.port.read(&mut buffer) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => 0,
+                // while the host's end is closed.
+                Err(_) => {
+                    thread::sleep(Duration::from_millis(1));
+                    0
+                }
+            };
+            for &byte in &buffer[..n] {
+                let received = self
+                    .receiver
+                    .push(byte, &self.codec, &self.link, &mut self.crc);
+                if let Some(Ok(received)) = received {
+                    self.handle(received);
+                }
+            }
+
+            self.sample();
+            self.publish();
+            self.flush();
+        }
+    }
+
+    /// Sends everything queued, the way the TX DMA's completion interrupt does.
+    fn flush(&mut self) {
+        while self.queue.start_next(&mut Pty(&mut self.port)).is_some() {}
+    }
+
+    fn handle(&mut self, received: Received) {
+        match received {
+            Received::Ignored | Received::Fragment => {}
+            Received::Hello { request_id, offer } => self.hello(request_id, offer),
+            Received::Subscription {
+                request_id,
+                requested,
+            } => {
+                let granted = self.subscribe(requested);
+                self.send(&Message::Subscription(granted), request_id, Priority::Reply);
+            }
+            Received::NodeAddress {
+                request_id,
+                requested,
+            } => self.move_to(request_id, requested.address),
+            Received::Request {
+                request_id,
+                request,
+                fresh,
+            } => {
+                self.requests.fetch_add(1, Ordering::Relaxed);
+                if self.options.faults.busy {
+                    // neither handled nor acknowledged, so the host may retry.
+                    let busy = Message::Error(ErrorPacket {
+                        code: ErrorCode::Busy,
+                    });
+                    return self.send(&busy, request_id, Priority::Reply);
+                }
+                self.reply(request_id, &request, fresh);
+                if let Some(ack) = self.receiver.acknowledge(&request, fresh) {
+                    self.send(&Message::Ack(ack), request_id, Priority::Reply);
+                }
+                // only once the reply is sent, like the firmware.
+                if let RequestKind::Reboot = request.kind {
+                    self.flush();
+                    self.reboot();
+                }
+            }
+            Received::Rejected {
+                request_id, code, ..
+            } => self.send(
+                &Message::Error(ErrorPacket { code }),
+                request_id,
+                Priority::Reply,
+            ),
+        }
+    }
+
+    fn reply(&mut self, request_id: Option<u8>, request: &Request, fresh: bool) {
+        let message = match request.kind {
+            RequestKind::Default | RequestKind::Telemetry => {
+                Message::Telemetry(telemetry::sample(&self.encoder))
+            }
+            RequestKind::ResetCause => Message::ResetCause(ResetCausePacket {
+                cause: self.reset_cause,
+            }),
+            RequestKind::CrashRecord => Message::CrashRecord(CrashRecordPacket {
+                kind: CrashKind::None,
+                file: "",
+                line: 0,
+                pc: 0,
+                lr: 0,
+                cfsr: 0,
+                hfsr: 0,
+                mmfar: 0,
+                bfar: 0,
+                msg: "",
+            }),
+            RequestKind::LogLevel => {
+                if let Some(level) = request.log_level.filter(|_| fresh) {
+                    self.log_level = level;
+                }
+                Message::LogLevel(LogLevelPacket {
+                    level: self.log_level,
+                })
+            }
+            RequestKind::LinkErrors => Message::LinkErrors(LinkErrorsPacket::default()),
+            RequestKind::SerialConfig => {
+                // the pty takes any settings, and every frame confirms them.
+                let switching = request.serial.filter(|_| fresh);
+                if let Some(config) = switching {
+                    self.serial = config;
+                }
+                Message::SerialConfig(SerialConfigPacket {
+                    config: self.serial,
+                    switching: switching.is_some(),
+                })
+            }
+            RequestKind::Zero => {
+                if fresh {
+                    self.encoder.zero();
+                }
+                Message::Telemetry(telemetry::sample(&self.encoder))
+            }
+            RequestKind::Reboot => Message::ResetCause(ResetCausePacket {
+                cause: ResetCause::Software,
+            }),
+            RequestKind::SaveConfig => {
+                if fresh {
+                    self.saved_log_level = Some(self.log_level);
+                }
+                Message::LogLevel(LogLevelPacket {
+                    level: self.log_level,
+                })
+            }
+        };
+        self.send(&message, request_id, Priority::Reply)
+    }
+
+    /// Agrees on a protocol version and capabilities with the host's `offer`, replying at the
+    /// current settings before switching, like the firmware.
+    fn hello(&mut self, request_id: Option<u8>, offer: Hello) {
+        let preference: Vec<u8> = self.options.codecs.iter().map(|c| c.hello_bit()).collect();
+        let supported = Hello {
+            min_version: MIN_PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+            codecs: preference.iter().fold(0, |codecs, codec| codecs | codec),
+            flags: FULL_CRC,
+            max_frame: self.options.frame_size as u16,
+        };
+        let agreed = match supported.negotiate(&offer, &preference) {
+            Some(agreed) => agreed,
+            None => return self.send(&Message::Hello(supported), request_id, Priority::Reply),
+        };
+        self.send(&Message::Hello(agreed), request_id, Priority::Reply);
+        // NOTE(unwrap): `negotiate` only picks codecs from our preference.
+        self.codec = Codec::from_hello_bit(agreed.codecs).unwrap();
+        self.link.apply(&agreed);
+        // queued before the reply but sent after it, at settings the host no longer expects.
+        self.queue.discard(Priority::Telemetry);
+        self.queue.discard(Priority::Log);
+        self.subscriptions.clear();
+        self.batcher.stop();
+    }
+
+    /// Replies with the device's address, then moves it to `requested` if given, like the
+    /// firmware: the reply still comes from the old address.
+    fn move_to(&mut self, request_id: Option<u8>, requested: Option<u8>) {
+        let reply = NodeAddress {
+            address: Some(requested.unwrap_or(self.link.address)),
+        };
+        self.send(&Message::NodeAddress(reply), request_id, Priority::Reply);
+        if let Some(address) = requested {
+            self.link.address = address;
+            self.saved_address = Some(address);
+        }
+    }
+
+    /// Fits `requested` into the link's bandwidth, like the firmware.
+    fn subscribe(&mut self, requested: Subscription) -> Subscription {
+        let line_rate = schedule::line_rate(&self.serial);
+        let mut buffer = [0u8; MAX_MESSAGE_SIZE];
+        let wire_bytes =
+            schedule::wire_bytes(&self.codec, requested.topic, &self.link, &mut buffer);
+        let granted = match wire_bytes {
+            Some(wire_bytes) => {
+                self.subscriptions
+                    .subscribe(requested, wire_bytes, line_rate, self.clock.now_ms())
+            }
+            None => Subscription {
+                topic: requested.topic,
+                period_ms: 0,
+            },
+        };
+        if granted.topic == Topic::TelemetryBatch {
+            match granted.period_ms {
+                0 => self.batcher.stop(),
+                _ => self.batcher.start(),
+            }
+        }
+        granted
+    }
+
+    /// Samples the turret every millisecond, like the firmware's sampling timer. Samples due
+    /// while a request was being handled read the current count.
+    fn sample(&mut self) {
+        let now = self.clock.now_ms();
+        while self.sampled_ms != now {
+            self.sampled_ms = self.sampled_ms.wrapping_add(1);
+            self.batcher.push(self.sampled_ms, self.encoder.count());
+        }
+    }
+
+    /// Sends the subscribed topics that are due.
+    fn publish(&mut self) {
+        while let Some(topic) = self.subscriptions.next_due(self.clock.now_ms()) {
+            let message = match topic {
+                Topic::Telemetry => Message::Telemetry(telemetry::sample(&self.encoder)),
+                Topic::LinkErrors => Message::LinkErrors(LinkErrorsPacket::default()),
+                Topic::TelemetryBatch => match self.batcher.take() {
+                    Some(batch) => Message::TelemetryBatch(batch),
+                    None => continue,
+                },
+            };
+            self.send(&message, None, Priority::Telemetry);
+        }
+    }
+
+    /// Forgets everything but the saved settings, and restarts the encoder's count.
+    fn reboot(&mut self) {
+        self.codec = self.options.codecs[0];
+        let address = self.saved_address.unwrap_or(self.options.address);
+        self.link = LinkConfig::new(address, self.options.frame_size);
+        self.receiver.reset();
+        self.queue = TxQueue::new();
+        self.reset_cause = ResetCause::Software;
+        self.log_level = self.saved_log_level.unwrap_or(LogLevel::Info);
+        self.encoder.zero();
+        self.subscriptions.clear();
+        self.batcher.stop();
+        self.subscribe_from_boot();
+    }
+
+    fn send(&mut self, message: &Message, request_id: Option<u8>, priority: Priority) {
+        if self.options.faults.silent {
+            return;
+        }
+        if self.lost_messages > 0 {
+            self.lost_messages -= 1;
+            return;
+        }
+        let mut buffer = [0u8; MAX_MESSAGE_SIZE];
+        let result = encode(&self.codec, message, request_id, &mut buffer).and_then(|payload| {
+            self.queue
+                .push(payload, priority, &self.link, &mut self.crc)
+        });
+        if let Err(e) = result {
+            eprintln!("turret_sim: failed to send {:?}: {:?}", message, e);
+        }
+    }
+}
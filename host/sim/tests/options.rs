@@ -0,0 +1,23 @@
+//! The simulator refuses to build a device the firmware couldn't be built as.
+
+use turret_client::protocol::address::BROADCAST;
+use turret_sim::{Options, Simulator};
+
+#[test]
+fn invalid_options_are_refused() {
+    assert!(Simulator::spawn(Options {
+        frame_size: 8,
+        ..Options::default()
+    })
+    .is_err());
+    assert!(Simulator::spawn(Options {
+        address: BROADCAST,
+        ..Options::default()
+    })
+    .is_err());
+    assert!(Simulator::spawn(Options {
+        codecs: Vec::new(),
+        ..Options::default()
+    })
+    .is_err());
+}
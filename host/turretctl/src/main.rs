@@ -0,0 +1,427 @@
+//! Queries and configures the turret monitor over its serial port.
+//!
+//! ```text
+//! turretctl --port /dev/ttyACM0 telemetry --follow --format csv
+//! ```
+
+mod output;
+mod settings;
+
+use std::io;
+use std::process;
+use std::time::{Duration, Instant};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use serde_json::json;
+use turret_client::link::DEFAULT_FRAME_SIZE;
+use turret_client::protocol::datamodel::log_level::LogLevel;
+use turret_client::protocol::datamodel::serial_config::SerialConfig;
+use turret_client::protocol::datamodel::subscription::Topic;
+use turret_client::protocol::datamodel::telemetry_packet::TurretTelemetryPacket;
+use turret_client::protocol::hello::{Hello, FULL_CRC};
+use turret_client::{Client, Codec, Config, Error, Received};
+
+use crate::output::{Format, Output, Record};
+use crate::settings::{log_level_name, parity_name, stop_bits_number, Key, Setting};
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// The device's serial port.
+    #[arg(short, long, env = "TURRET_PORT")]
+    port: String,
+    /// The port's baud rate, which must match the device's.
+    #[arg(short, long, default_value_t = SerialConfig::DEFAULT.baud)]
+    baud: u32,
+    /// The device's address on the bus, as set with `TURRET_NODE_ADDRESS` or moved with
+    /// `config set address`. The broadcast address, 127, reaches a lone device at any address.
+    #[arg(long, default_value_t = 1)]
+    address: u8,
+    /// The codec the device was built with, used until a hello agrees on one.
+    #[arg(long, value_enum, default_value_t = CodecArg::Cbor)]
+    codec: CodecArg,
+    /// How long to wait for each reply, in milliseconds.
+    #[arg(long, default_value_t = 500)]
+    timeout: u64,
+    /// Skip the hello, for firmware older than it.
+    #[arg(long)]
+    no_hello: bool,
+    #[arg(short, long, value_enum, default_value_t = Format::Table)]
+    format: Format,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum CodecArg {
+    Cbor,
+    Postcard,
+    Json,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// What the device is, and the settings agreed with it.
+    Info,
+    /// The turret's position.
+    Telemetry {
+        /// Keep sampling, a line per sample.
+        #[arg(long)]
+        follow: bool,
+        /// Time between samples when following, in milliseconds.
+        #[arg(long, default_value_t = 100)]
+        interval: u64,
+        /// Stop following after this many samples.
+        #[arg(long)]
+        count: Option<u64>,
+    },
+    /// The turret's position sampled at 1 kHz, a line per sample.
+    Samples {
+        /// Time between the batches the samples are sent in, in milliseconds. A batch holds 64
+        /// samples, so longer periods are shortened to 64.
+        #[arg(long, default_value_t = 50)]
+        batch: u64,
+        /// Stop after this many samples.
+        #[arg(long)]
+        count: Option<u64>,
+    },
+    /// Why the device last reset, its crash record, and both ends' link error counters.
+    Diag,
+    /// The device's runtime settings.
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Make the turret's current position the new zero.
+    Zero,
+    /// Reboot the device.
+    Reboot,
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print the settings in use.
+    Get,
+    /// Change a setting. Serial settings are kept across resets once confirmed, the address at
+    /// once, others once saved.
+    Set {
+        #[arg(value_enum)]
+        key: Key,
+        value: String,
+    },
+    /// Keep the settings changed at runtime across resets.
+    Save,
+}
+
+fn main() {
+    let cli = Cli::parse();
+    if let Err(e) = run(cli) {
+        eprintln!("turretctl: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let config = Config {
+        address: cli.address,
+        codec: match cli.codec {
+            CodecArg::Cbor => Codec::Cbor,
+            CodecArg::Postcard => Codec::Postcard,
+            CodecArg::Json => Codec::Json,
+        },
+        timeout: Duration::from_millis(cli.timeout),
+        ..Config::default()
+    };
+    let mut client = Client::open_with(&cli.port, cli.baud, config)?;
+    let hello = if cli.no_hello {
+        None
+    } else {
+        say_hello(&mut client)?
+    };
+    let mut out = Output::new(cli.format, io::stdout());
+
+    match cli.command {
+        Command::Info => {
+            let address = node_address(&mut client)?;
+            let serial = client.serial_config()?.config;
+            let log_level = client.log_level()?.level;
+            let reset_cause = client.reset_cause()?.cause;
+            let mut record: Record = vec![
+                ("port", json!(cli.port)),
+                ("protocol_version", json!(hello.map(|h| h.max_version))),
+                ("codec", json!(format!("{:?}", client.codec()))),
+                (
+                    "full_crc",
+                    json!(hello.is_some_and(|h| h.flags & FULL_CRC != 0)),
+                ),
+                (
+                    "frame_size",
+                    json!(hello.map_or(DEFAULT_FRAME_SIZE, |h| h.max_frame as usize)),
+                ),
+            ];
+            record.extend(config_record(address, log_level, serial));
+            record.push(("reset_cause", json!(format!("{:?}", reset_cause))));
+            out.record(&record)?;
+        }
+        Command::Telemetry { follow: false, .. } => {
+            out.record(&telemetry_record(&client.telemetry()?))?
+        }
+        Command::Telemetry {
+            follow: true,
+            interval,
+            count,
+        } => follow_telemetry(
+            &mut client,
+            &mut out,
+            Duration::from_millis(interval),
+            count,
+        )?,
+        Command::Samples { batch, count } => {
+            follow_samples(&mut client, &mut out, Duration::from_millis(batch), count)?
+        }
+        Command::Diag => {
+            let reset_cause = client.reset_cause()?.cause;
+            let crash = client.crash_record()?;
+            let errors = client.link_errors()?;
+            let stats = client.stats();
+            let record: Record = vec![
+                ("reset_cause", json!(format!("{:?}", reset_cause))),
+                ("crash", json!(format!("{:?}", crash.kind))),
+                ("crash_file", json!(crash.file)),
+                ("crash_line", json!(crash.line)),
+                ("crash_pc", json!(format!("{:#010x}", crash.pc))),
+                ("crash_lr", json!(format!("{:#010x}", crash.lr))),
+                ("crash_cfsr", json!(format!("{:#010x}", crash.cfsr))),
+                ("crash_hfsr", json!(format!("{:#010x}", crash.hfsr))),
+                ("crash_mmfar", json!(format!("{:#010x}", crash.mmfar))),
+                ("crash_bfar", json!(format!("{:#010x}", crash.bfar))),
+                ("crash_msg", json!(crash.msg)),
+                ("overrun", json!(errors.overrun)),
+                ("framing", json!(errors.framing)),
+                ("noise", json!(errors.noise)),
+                ("parity", json!(errors.parity)),
+                ("dma_transfer", json!(errors.dma_transfer)),
+                ("dma_fifo", json!(errors.dma_fifo)),
+                ("dma_direct_mode", json!(errors.dma_direct_mode)),
+                ("rx_restarts", json!(errors.rx_restarts)),
+                ("tx_timeouts", json!(errors.tx_timeouts)),
+                // what this end dropped while running the command.
+                ("host_bad_crc", json!(stats.link.bad_crc)),
+                ("host_malformed", json!(stats.link.malformed)),
+                ("host_fragment", json!(stats.link.fragment)),
+                ("host_undecodable", json!(stats.undecodable)),
+            ];
+            out.record(&record)?;
+        }
+        Command::Config { action } => {
+            match action {
+                ConfigAction::Get => {}
+                ConfigAction::Set { key, value } => set(&mut client, Setting::parse(key, &value)?)?,
+                ConfigAction::Save => {
+                    client.save_config()?;
+                }
+            }
+            let address = node_address(&mut client)?;
+            let serial = client.serial_config()?.config;
+            let log_level = client.log_level()?.level;
+            out.record(&config_record(address, log_level, serial))?;
+        }
+        Command::Zero => out.record(&telemetry_record(&client.zero()?))?,
+        Command::Reboot => {
+            let cause = client.reboot()?.cause;
+            out.record(&vec![("reset_cause", json!(format!("{:?}", cause)))])?;
+        }
+    }
+    Ok(())
+}
+
+/// Agrees on the device's best settings, or carries on with its defaults if it's too old for a
+/// hello.
+fn say_hello(client: &mut Client) -> Result<Option<Hello>, Error> {
+    match client.hello() {
+        Ok(agreed) => Ok(Some(agreed)),
+        Err(Error::Device(code)) => {
+            eprintln!(
+                "turretctl: the device doesn't understand hellos ({:?}), using its defaults.",
+                code
+            );
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// The address the device says it is at, or `None` if it's too old to say.
+fn node_address(client: &mut Client) -> Result<Option<u8>, Error> {
+    match client.node_address() {
+        Ok(reply) => Ok(reply.address),
+        Err(Error::Device(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn set(client: &mut Client, setting: Setting) -> Result<(), Box<dyn std::error::Error>> {
+    match setting {
+        Setting::LogLevel(level) => {
+            client.set_log_level(level)?;
+            return Ok(());
+        }
+        Setting::Address(address) => {
+            client.set_node_address(address)?;
+            eprintln!(
+                "turretctl: moved to address {}, pass --address {} from now on.",
+                address, address
+            );
+            return Ok(());
+        }
+        _ => {}
+    }
+    let current = client.serial_config()?.config;
+    // NOTE(unwrap): every other setting is a serial one.
+    let requested = setting.apply(current).unwrap();
+    let reply = client.switch_serial(requested)?;
+    if !reply.switching {
+        return Err(format!("the device can't run at {:?}", requested).into());
+    }
+    eprintln!(
+        "turretctl: switched to {} baud, pass --baud {} from now on.",
+        requested.baud, requested.baud
+    );
+    Ok(())
+}
+
+/// Streams the samples if the device can, polling only when the stream stalls, and polls every
+/// `interval` otherwise.
+fn follow_telemetry(
+    client: &mut Client,
+    out: &mut Output<io::Stdout>,
+    interval: Duration,
+    count: Option<u64>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let streamed = match client.subscribe(Topic::Telemetry, interval) {
+        Ok(granted) if granted.period_ms > 0 => {
+            let period = Duration::from_millis(granted.period_ms.into());
+            if period != interval {
+                eprintln!(
+                    "turretctl: the link only carries a sample every {}ms.",
+                    granted.period_ms
+                );
+            }
+            Some(period)
+        }
+        Ok(_) | Err(Error::Device(_)) => None,
+        Err(e) => return Err(e.into()),
+    };
+    // a stream that misses two periods is polled until it catches up.
+    let poll_after = streamed.map_or(interval, |period| period * 2);
+
+    let start = Instant::now();
+    let mut samples = 0;
+    let mut next = if streamed.is_some() {
+        start + poll_after
+    } else {
+        start
+    };
+    while count.is_none_or(|count| samples < count) {
+        let now = Instant::now();
+        if now >= next {
+            let telemetry = client.telemetry()?;
+            out.row(&timed(start, telemetry_record(&telemetry)))?;
+            samples += 1;
+            next += poll_after;
+            continue;
+        }
+        // telemetry the device sends unprompted is a sample too.
+        match client.next_event(next - now)? {
+            Some(Received::Telemetry(telemetry)) => {
+                out.row(&timed(start, telemetry_record(&telemetry)))?;
+                samples += 1;
+                if streamed.is_some() {
+                    next = Instant::now() + poll_after;
+                }
+            }
+            Some(Received::Log(record)) => {
+                eprintln!("device {:?}: {}", record.level, record.msg)
+            }
+            _ => {}
+        }
+    }
+
+    if streamed.is_some() {
+        client.subscribe(Topic::Telemetry, Duration::ZERO)?;
+    }
+    Ok(())
+}
+
+/// Prints every sample of the batches the device streams, and how many it lost between them.
+fn follow_samples(
+    client: &mut Client,
+    out: &mut Output<io::Stdout>,
+    period: Duration,
+    count: Option<u64>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let granted = client.subscribe(Topic::TelemetryBatch, period)?;
+    if granted.period_ms == 0 {
+        return Err("the link can't carry the samples".into());
+    }
+    if u128::from(granted.period_ms) != period.as_millis() {
+        eprintln!(
+            "turretctl: the device sends a batch every {}ms instead.",
+            granted.period_ms
+        );
+    }
+    let period = Duration::from_millis(granted.period_ms.into());
+
+    let mut samples = 0;
+    while count.is_none_or(|count| samples < count) {
+        match client.next_event(period * 2 + client.config().timeout)? {
+            Some(Received::TelemetryBatch(batch)) => {
+                if batch.dropped > 0 {
+                    eprintln!("turretctl: the device lost {} samples.", batch.dropped);
+                }
+                for (t_ms, pos) in batch.samples() {
+                    if count.is_some_and(|count| samples >= count) {
+                        break;
+                    }
+                    out.row(&vec![
+                        ("device_ms", json!(t_ms)),
+                        ("turret_pos", json!(pos)),
+                    ])?;
+                    samples += 1;
+                }
+            }
+            Some(Received::Log(record)) => {
+                eprintln!("device {:?}: {}", record.level, record.msg)
+            }
+            Some(_) => {}
+            None => return Err("the device stopped sending samples".into()),
+        }
+    }
+
+    client.subscribe(Topic::TelemetryBatch, Duration::ZERO)?;
+    Ok(())
+}
+
+fn telemetry_record(telemetry: &TurretTelemetryPacket) -> Record {
+    vec![
+        ("turret_pos", json!(telemetry.turret_pos)),
+        ("turret_rot", json!(format!("{:?}", telemetry.turret_rot))),
+    ]
+}
+
+/// Prepends the milliseconds since following started.
+fn timed(start: Instant, record: Record) -> Record {
+    let mut timed = vec![("t_ms", json!(start.elapsed().as_millis() as u64))];
+    timed.extend(record);
+    timed
+}
+
+fn config_record(address: Option<u8>, log_level: LogLevel, serial: SerialConfig) -> Record {
+    vec![
+        ("address", json!(address)),
+        ("log_level", json!(log_level_name(log_level))),
+        ("baud", json!(serial.baud)),
+        ("parity", json!(parity_name(serial.parity))),
+        ("stop_bits", json!(stop_bits_number(serial.stop_bits))),
+    ]
+}
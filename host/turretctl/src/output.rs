@@ -0,0 +1,180 @@
+//! Prints records as a table, JSON or CSV.
+
+use std::io::{self, Write};
+
+use clap::ValueEnum;
+use serde_json::{Map, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Format {
+    /// Aligned columns, for people.
+    Table,
+    /// An object per record, one per line when following.
+    Json,
+    /// A header, then a line per record.
+    Csv,
+}
+
+/// Fields in the order they're printed.
+pub type Record = Vec<(&'static str, Value)>;
+
+/// Columns of a followed table are at least this wide, so most values line up.
+const MIN_COLUMN_WIDTH: usize = 12;
+
+pub struct Output<W> {
+    format: Format,
+    out: W,
+    /// Set once a CSV or table header was printed.
+    header: bool,
+}
+
+impl<W: Write> Output<W> {
+    pub fn new(format: Format, out: W) -> Self {
+        Self {
+            format,
+            out,
+            header: false,
+        }
+    }
+
+    /// Prints a command's only record, a field per line in a table.
+    pub fn record(&mut self, record: &Record) -> io::Result<()> {
+        match self.format {
+            Format::Table => {
+                let width = record.iter().map(|(key, _)| key.len()).max().unwrap_or(0);
+                for (key, value) in record {
+                    writeln!(self.out, "{:width$}  {}", key, text(value), width = width)?;
+                }
+            }
+            Format::Json => {
+                serde_json::to_writer_pretty(&mut self.out, &object(record))?;
+                writeln!(self.out)?;
+            }
+            Format::Csv => self.row(record)?,
+        }
+        self.out.flush()
+    }
+
+    /// Prints one of a stream of records with the same fields, a line each.
+    pub fn row(&mut self, record: &Record) -> io::Result<()> {
+        match self.format {
+            Format::Table => {
+                let widths = record
+                    .iter()
+                    .map(|(key, _)| key.len().max(MIN_COLUMN_WIDTH));
+                if !self.header {
+                    let header = record.iter().map(|(key, _)| key.to_string());
+                    writeln!(self.out, "{}", columns(header, widths.clone()))?;
+                }
+                let values = record.iter().map(|(_, value)| text(value));
+                writeln!(self.out, "{}", columns(values, widths))?;
+            }
+            Format::Json => {
+                serde_json::to_writer(&mut self.out, &object(record))?;
+                writeln!(self.out)?;
+            }
+            Format::Csv => {
+                if !self.header {
+                    let header: Vec<_> = record.iter().map(|(key, _)| csv_field(key)).collect();
+                    writeln!(self.out, "{}", header.join(","))?;
+                }
+                let values: Vec<_> = record
+                    .iter()
+                    .map(|(_, value)| csv_field(&text(value)))
+                    .collect();
+                writeln!(self.out, "{}", values.join(","))?;
+            }
+        }
+        self.header = true;
+        self.out.flush()
+    }
+}
+
+fn object(record: &Record) -> Map<String, Value> {
+    record
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.clone()))
+        .collect()
+}
+
+/// A value as people read it, strings unquoted and nothing as blank.
+fn text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn columns(cells: impl Iterator<Item = String>, widths: impl Iterator<Item = usize>) -> String {
+    let padded: Vec<_> = cells
+        .zip(widths)
+        .map(|(cell, width)| format!("{:width$}", cell, width = width))
+        .collect();
+    padded.join("  ").trim_end().to_string()
+}
+
+/// Quotes a field if it holds a separator, quote or line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn printed(format: Format, print: impl FnOnce(&mut Output<&mut Vec<u8>>)) -> String {
+        let mut out = Vec::new();
+        print(&mut Output::new(format, &mut out));
+        String::from_utf8(out).unwrap()
+    }
+
+    fn sample(pos: u32) -> Record {
+        vec![
+            ("turret_pos", json!(pos)),
+            ("turret_rot", json!("Forward")),
+            ("msg", json!("a, \"b\"")),
+        ]
+    }
+
+    #[test]
+    fn records_are_listed_by_field() {
+        let table = printed(Format::Table, |out| out.record(&sample(7)).unwrap());
+        assert_eq!(
+            table,
+            "turret_pos  7\nturret_rot  Forward\nmsg         a, \"b\"\n"
+        );
+        let json = printed(Format::Json, |out| out.record(&sample(7)).unwrap());
+        let parsed: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed["turret_pos"], 7);
+    }
+
+    #[test]
+    fn rows_share_one_header() {
+        let csv = printed(Format::Csv, |out| {
+            out.row(&sample(1)).unwrap();
+            out.row(&sample(2)).unwrap();
+        });
+        assert_eq!(
+            csv,
+            "turret_pos,turret_rot,msg\n\
+             1,Forward,\"a, \"\"b\"\"\"\n\
+             2,Forward,\"a, \"\"b\"\"\"\n"
+        );
+        let table = printed(Format::Table, |out| {
+            out.row(&sample(1)).unwrap();
+            out.row(&sample(22)).unwrap();
+        });
+        let lines: Vec<_> = table.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[2].starts_with("22            Forward"));
+        let json = printed(Format::Json, |out| out.row(&sample(1)).unwrap());
+        assert_eq!(json.lines().count(), 1);
+    }
+}
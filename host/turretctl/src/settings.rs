@@ -0,0 +1,127 @@
+//! The device settings `config` reads and writes, and how they're spelled on the command line.
+
+use clap::ValueEnum;
+use turret_client::protocol::address;
+use turret_client::protocol::datamodel::log_level::LogLevel;
+use turret_client::protocol::datamodel::serial_config::{Parity, SerialConfig, StopBits};
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Key {
+    /// off, error, warn, info, debug or trace.
+    LogLevel,
+    /// One of the rates the firmware supports, e.g. 115200.
+    Baud,
+    /// none, even or odd.
+    Parity,
+    /// 1 or 2.
+    StopBits,
+    /// The device's address on the bus, below the broadcast address 127.
+    Address,
+}
+
+/// A setting to change.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Setting {
+    LogLevel(LogLevel),
+    Baud(u32),
+    Parity(Parity),
+    StopBits(StopBits),
+    Address(u8),
+}
+
+impl Setting {
+    pub fn parse(key: Key, value: &str) -> Result<Self, String> {
+        let value = value.to_ascii_lowercase();
+        let setting = match (key, value.as_str()) {
+            (Key::LogLevel, "off") => Setting::LogLevel(LogLevel::Off),
+            (Key::LogLevel, "error") => Setting::LogLevel(LogLevel::Error),
+            (Key::LogLevel, "warn") => Setting::LogLevel(LogLevel::Warn),
+            (Key::LogLevel, "info") => Setting::LogLevel(LogLevel::Info),
+            (Key::LogLevel, "debug") => Setting::LogLevel(LogLevel::Debug),
+            (Key::LogLevel, "trace") => Setting::LogLevel(LogLevel::Trace),
+            (Key::Baud, baud) => Setting::Baud(
+                baud.parse()
+                    .map_err(|_| format!("invalid baud rate {:?}", baud))?,
+            ),
+            (Key::Parity, "none") => Setting::Parity(Parity::None),
+            (Key::Parity, "even") => Setting::Parity(Parity::Even),
+            (Key::Parity, "odd") => Setting::Parity(Parity::Odd),
+            (Key::StopBits, "1") => Setting::StopBits(StopBits::One),
+            (Key::StopBits, "2") => Setting::StopBits(StopBits::Two),
+            (Key::Address, address) => Setting::Address(
+                address
+                    .parse()
+                    .ok()
+                    .filter(|&address| address::is_node(address))
+                    .ok_or_else(|| format!("invalid address {:?}", address))?,
+            ),
+            (key, value) => return Err(format!("invalid {:?} {:?}", key, value)),
+        };
+        Ok(setting)
+    }
+
+    /// The serial settings `serial` becomes, or `None` for settings that aren't serial ones.
+    pub fn apply(self, serial: SerialConfig) -> Option<SerialConfig> {
+        match self {
+            Setting::LogLevel(_) | Setting::Address(_) => None,
+            Setting::Baud(baud) => Some(SerialConfig { baud, ..serial }),
+            Setting::Parity(parity) => Some(SerialConfig { parity, ..serial }),
+            Setting::StopBits(stop_bits) => Some(SerialConfig {
+                stop_bits,
+                ..serial
+            }),
+        }
+    }
+}
+
+/// How `config get` spells a log level, the same way `config set` takes it.
+pub fn log_level_name(level: LogLevel) -> &'static str {
+    match level {
+        LogLevel::Off => "off",
+        LogLevel::Error => "error",
+        LogLevel::Warn => "warn",
+        LogLevel::Info => "info",
+        LogLevel::Debug => "debug",
+        LogLevel::Trace => "trace",
+    }
+}
+
+pub fn parity_name(parity: Parity) -> &'static str {
+    match parity {
+        Parity::None => "none",
+        Parity::Even => "even",
+        Parity::Odd => "odd",
+    }
+}
+
+pub fn stop_bits_number(stop_bits: StopBits) -> u8 {
+    match stop_bits {
+        StopBits::One => 1,
+        StopBits::Two => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_are_parsed_and_applied() {
+        assert_eq!(
+            Setting::parse(Key::LogLevel, "Warn"),
+            Ok(Setting::LogLevel(LogLevel::Warn))
+        );
+        assert!(Setting::parse(Key::LogLevel, "loud").is_err());
+        assert!(Setting::parse(Key::Baud, "fast").is_err());
+        assert_eq!(Setting::parse(Key::Address, "5"), Ok(Setting::Address(5)));
+        assert!(Setting::parse(Key::Address, "127").is_err());
+
+        let baud = Setting::parse(Key::Baud, "230400").unwrap();
+        let serial = baud.apply(SerialConfig::DEFAULT).unwrap();
+        assert_eq!(serial.baud, 230_400);
+        assert_eq!(serial.parity, Parity::None);
+        let parity = Setting::parse(Key::Parity, "odd").unwrap();
+        assert_eq!(parity.apply(serial).unwrap().parity, Parity::Odd);
+        assert_eq!(Setting::LogLevel(LogLevel::Off).apply(serial), None);
+    }
+}
@@ -0,0 +1,88 @@
+//! Node addressing, so several devices can share one bus.
+//!
+//! Every frame starts with an address byte, ahead of its fragment header. Its top bit tells
+//! which way the frame travels, the rest is a node address:
+//! - Requests carry the address of the device they are meant for, or [`BROADCAST`], with
+//!   [`REPLY`] clear.
+//! - Replies carry the address of the device that sent them, with [`REPLY`] set.
+//!
+//! So a device never mistakes another device's replies for requests, nor its own echoed back by a
+//! half-duplex transceiver.
+
+/// Length of the address at the front of every frame.
+pub const ADDRESS_LEN: usize = 1;
+/// Set in the address byte of every frame a device sends, clear in those a host sends.
+pub const REPLY: u8 = 0x80;
+/// Requests sent to this address are meant for every device.
+pub const BROADCAST: u8 = 0x7F;
+
+/// Splits a frame's contents into its address and the rest.
+pub fn split(frame: &[u8]) -> Option<(u8, &[u8])> {
+    let (address, rest) = frame.split_first()?;
+    Some((*address, rest))
+}
+
+/// Whether a device can be given `address`: any below [`BROADCAST`].
+pub fn is_node(address: u8) -> bool {
+    address < BROADCAST
+}
+
+/// The address byte of the frames a device at `own` address sends.
+pub fn reply(own: u8) -> u8 {
+    own | REPLY
+}
+
+/// Whether a device at `own` address should handle a frame carrying `address`: a request sent
+/// to it, or broadcast.
+pub fn accepts(own: u8, address: u8) -> bool {
+    address & REPLY == 0 && (address == own || address == BROADCAST)
+}
+
+/// Whether a host talking to the device at `device` should handle a frame carrying `address`: a
+/// reply from that device, or from any device if `device` is [`BROADCAST`].
+pub fn from_device(device: u8, address: u8) -> bool {
+    address & REPLY != 0 && (device == BROADCAST || address & !REPLY == device)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_address() {
+        assert_eq!(split(&[7, 1, 2]), Some((7, &[1, 2][..])));
+        assert_eq!(split(&[7]), Some((7, &[][..])));
+        assert_eq!(split(&[]), None);
+    }
+
+    #[test]
+    fn accepts_own_and_broadcast() {
+        assert!(accepts(3, 3));
+        assert!(accepts(3, BROADCAST));
+        assert!(!accepts(3, 4));
+    }
+
+    #[test]
+    fn devices_ignore_replies() {
+        assert!(!accepts(3, reply(3)));
+        assert!(!accepts(3, reply(4)));
+        assert!(!accepts(3, reply(BROADCAST)));
+    }
+
+    #[test]
+    fn hosts_only_take_replies() {
+        assert!(from_device(3, reply(3)));
+        assert!(!from_device(3, reply(4)));
+        assert!(!from_device(3, 3));
+        assert!(from_device(BROADCAST, reply(4)));
+        assert!(!from_device(BROADCAST, BROADCAST));
+    }
+
+    #[test]
+    fn node_addresses() {
+        assert!(is_node(0));
+        assert!(is_node(BROADCAST - 1));
+        assert!(!is_node(BROADCAST));
+        assert!(!is_node(reply(1)));
+    }
+}
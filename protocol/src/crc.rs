@@ -0,0 +1,60 @@
+//! Software model of the STM32's CRC peripheral, for hosts checking and signing frames.
+//!
+//! The peripheral computes a CRC-32/MPEG-2 (polynomial `0x04C11DB7`, initial value `0xFFFFFFFF`,
+//! neither input nor output reflected, no final xor) over 32 bit words, which the firmware feeds
+//! big endian. That's the same as the CRC of the bytes in order, as long as only whole words are
+//! fed: by default the last partial word of a frame isn't covered at all, and once a
+//! [hello](crate::hello) agreed on [`FULL_CRC`](crate::hello::FULL_CRC) it's padded with zeros.
+
+const POLYNOMIAL: u32 = 0x04C1_1DB7;
+/// The CRC of nothing, which the peripheral starts from once reset.
+pub const INITIAL: u32 = 0xFFFF_FFFF;
+
+/// Computes the CRC the device computes over `data`, covering its last partial word if `full`.
+pub fn crc32(data: &[u8], full: bool) -> u32 {
+    let words = data.len() / 4 * 4;
+    let (words, tail) = data.split_at(words);
+    let mut crc = words.iter().fold(INITIAL, |crc, &byte| update(crc, byte));
+    if full && !tail.is_empty() {
+        let mut last = [0u8; 4];
+        last[..tail.len()].copy_from_slice(tail);
+        crc = last.iter().fold(crc, |crc, &byte| update(crc, byte));
+    }
+    crc
+}
+
+/// Feeds one more byte into `crc`.
+pub fn update(crc: u32, byte: u8) -> u32 {
+    let mut crc = crc ^ (byte as u32) << 24;
+    for _ in 0..8 {
+        crc = if crc & 0x8000_0000 != 0 {
+            crc << 1 ^ POLYNOMIAL
+        } else {
+            crc << 1
+        };
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_peripheral() {
+        // the example frame of the book's interface chapter, COBS decoded and without its CRC.
+        let frame = b"\x01\x00\x00\x01\x01\x01\x00{\"turret_pos\":1.0}";
+        assert_eq!(crc32(frame, false), 0xC999_E0C8);
+        assert_eq!(crc32(b"12345678", false), 0x49E3_C2FB);
+        assert_eq!(crc32(b"", false), INITIAL);
+    }
+
+    #[test]
+    fn partial_words() {
+        // only whole words are covered by default.
+        assert_eq!(crc32(b"123456789", false), crc32(b"12345678", false));
+        assert_eq!(crc32(b"123456789", true), crc32(b"123456789\0\0\0", false));
+        assert_eq!(crc32(b"123456789", true), 0xAE24_E09D);
+        assert_eq!(crc32(b"12345678", true), crc32(b"12345678", false));
+    }
+}
@@ -0,0 +1,11 @@
+use serde::{Deserialize, Serialize};
+
+/// Acknowledges a request sent with a sequence number, see [`reliable`](crate::reliable).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[cfg_attr(any(test, feature = "schema"), derive(schemars::JsonSchema))]
+pub struct AckPacket {
+    /// The request's sequence number.
+    pub seq: u8,
+    /// The request was a retransmission, and wasn't executed again.
+    pub duplicate: bool,
+}
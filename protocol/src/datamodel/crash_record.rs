@@ -0,0 +1,37 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(any(test, feature = "schema"), derive(schemars::JsonSchema))]
+pub enum CrashKind {
+    /// The device didn't crash since the record was last taken.
+    None,
+    Panic,
+    HardFault,
+}
+
+/// The crash record preserved across the last reset, as reported to the host.
+/// Fields which don't apply to `kind` are zero.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[cfg_attr(any(test, feature = "schema"), derive(schemars::JsonSchema))]
+pub struct CrashRecordPacket<'a> {
+    pub kind: CrashKind,
+    /// Source file of the panic, the end of its path as much as the crash record holds.
+    pub file: &'a str,
+    /// Source line of the panic.
+    pub line: u32,
+    /// Stacked program counter of the hard fault.
+    pub pc: u32,
+    /// Stacked link register of the hard fault.
+    pub lr: u32,
+    /// Configurable fault status register.
+    pub cfsr: u32,
+    /// Hard fault status register.
+    pub hfsr: u32,
+    /// MemManage fault address register, only meaningful if `cfsr` flags it valid.
+    pub mmfar: u32,
+    /// Bus fault address register, only meaningful if `cfsr` flags it valid.
+    pub bfar: u32,
+    /// Start of the panic message, as much as the crash record holds. The device shortens it
+    /// further if the reply wouldn't fit a message otherwise.
+    pub msg: &'a str,
+}
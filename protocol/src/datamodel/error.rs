@@ -0,0 +1,19 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(any(test, feature = "schema"), derive(schemars::JsonSchema))]
+pub enum ErrorCode {
+    /// The request couldn't be decoded, or asked for something no device can do.
+    Malformed,
+    /// The request's message type or protocol version isn't understood by this firmware.
+    Unsupported,
+    /// The device was too busy to handle the request, try again later.
+    Busy,
+}
+
+/// Sent instead of the usual reply when a request couldn't be handled.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[cfg_attr(any(test, feature = "schema"), derive(schemars::JsonSchema))]
+pub struct ErrorPacket {
+    pub code: ErrorCode,
+}
@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+
+/// How often each USART1 and DMA error occurred since boot.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+#[cfg_attr(any(test, feature = "schema"), derive(schemars::JsonSchema))]
+pub struct LinkErrorsPacket {
+    /// A byte arrived before the DMA read the previous one, which was lost.
+    pub overrun: u32,
+    /// A byte's stop bit was missing, e.g. a baud rate mismatch or line glitch.
+    pub framing: u32,
+    /// Noise was detected while sampling a byte.
+    pub noise: u32,
+    pub parity: u32,
+    /// The RX DMA hit a bus error, which stops its stream.
+    pub dma_transfer: u32,
+    pub dma_fifo: u32,
+    pub dma_direct_mode: u32,
+    /// How often the RX DMA stream had to be restarted.
+    pub rx_restarts: u32,
+    /// TX transfers aborted because their completion interrupt never came.
+    pub tx_timeouts: u32,
+}
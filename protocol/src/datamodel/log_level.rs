@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+
+/// Verbosity of the device's log output, each level includes the ones before it.
+#[repr(u8)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, PartialOrd)]
+#[cfg_attr(any(test, feature = "schema"), derive(schemars::JsonSchema))]
+pub enum LogLevel {
+    Off = 0,
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(any(test, feature = "schema"), derive(schemars::JsonSchema))]
+pub struct LogLevelPacket {
+    pub level: LogLevel,
+}
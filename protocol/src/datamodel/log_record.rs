@@ -0,0 +1,17 @@
+use serde::{Deserialize, Serialize};
+
+use super::log_level::LogLevel;
+
+/// A warning or error logged by the device, forwarded to the host unprompted.
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(any(test, feature = "schema"), derive(schemars::JsonSchema))]
+pub struct LogRecordPacket<'a> {
+    pub level: LogLevel,
+    /// Milliseconds since boot at which the record was logged.
+    pub ts: u32,
+    /// Records dropped since the previous log frame, because they arrived faster than the
+    /// device is allowed to forward them.
+    pub dropped: u32,
+    /// Start of the log message, truncated to 64 bytes.
+    pub msg: &'a str,
+}
@@ -0,0 +1,14 @@
+//! Payloads carried by [envelopes](crate::envelope), shared by the firmware and its hosts.
+pub mod ack;
+pub mod crash_record;
+pub mod error;
+pub mod link_errors;
+pub mod log_level;
+pub mod log_record;
+pub mod node_address;
+pub mod request;
+pub mod reset_cause;
+pub mod serial_config;
+pub mod subscription;
+pub mod telemetry_batch;
+pub mod telemetry_packet;
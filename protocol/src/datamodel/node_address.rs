@@ -0,0 +1,17 @@
+use serde::{Deserialize, Serialize};
+
+/// Sent by the host to move the device to `address`, or with `None` to ask which address it is
+/// at, which is how to find a lone device's address through [`BROADCAST`].
+///
+/// The device replies with its address, the new one if it is moving. The reply is sent from the
+/// address the device is leaving, so the host hears it, and the device only answers to its new
+/// address after that. The new address is stored at once, and survives resets.
+///
+/// Addresses from [`BROADCAST`] up are refused as malformed.
+///
+/// [`BROADCAST`]: crate::address::BROADCAST
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(any(test, feature = "schema"), derive(schemars::JsonSchema))]
+pub struct NodeAddress {
+    pub address: Option<u8>,
+}
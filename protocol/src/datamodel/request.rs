@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+
+use super::log_level::LogLevel;
+use super::serial_config::SerialConfig;
+
+#[repr(u32)]
+#[derive(Deserialize, Serialize, Debug)]
+#[cfg_attr(any(test, feature = "schema"), derive(schemars::JsonSchema))]
+pub enum RequestKind {
+    Default = 0,
+    Telemetry = 1,
+    /// Ask the device why it last reset, see [`ResetCause`](super::reset_cause::ResetCause).
+    ResetCause = 2,
+    /// Ask for the panic or hard fault record preserved across the last reset.
+    CrashRecord = 3,
+    /// Report the runtime log level, after setting it to `log_level` if one is given.
+    LogLevel = 4,
+    /// Ask for the USART1 and DMA error counters.
+    LinkErrors = 5,
+    /// Report USART1's settings, after switching to `serial` if given.
+    SerialConfig = 6,
+    /// Make the turret's current position the new zero, and reply with telemetry.
+    Zero = 7,
+    /// Reply with the reset cause about to be latched, then reset once the reply is sent.
+    /// Send it fire-and-forget: reliable requests aren't deduplicated across resets.
+    Reboot = 8,
+    /// Store the runtime log level so it survives resets, and reply with it.
+    /// Serial settings need no saving, they're stored once the host confirms them.
+    SaveConfig = 9,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(any(test, feature = "schema"), derive(schemars::JsonSchema))]
+pub struct Request {
+    pub kind: RequestKind,
+    /// Only used by `LogLevel` requests, may be omitted otherwise.
+    pub log_level: Option<LogLevel>,
+    /// Only used by `SerialConfig` requests, may be omitted otherwise.
+    pub serial: Option<SerialConfig>,
+    /// Makes the request reliable: it is acknowledged, and executed once however often it is
+    /// retransmitted. May be omitted for fire-and-forget requests.
+    pub seq: Option<u8>,
+}
@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+
+/// Cause of the most recent device reset, as latched by the RCC's `CSR` register.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(any(test, feature = "schema"), derive(schemars::JsonSchema))]
+pub enum ResetCause {
+    /// The independent watchdog expired, some critical task stopped checking in.
+    IndependentWatchdog,
+    WindowWatchdog,
+    LowPower,
+    /// Software requested reset (`SYSRESETREQ`).
+    Software,
+    PowerOn,
+    BrownOut,
+    /// The NRST pin was pulled low (e.g. the reset button or a debug probe).
+    Pin,
+    Unknown,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(any(test, feature = "schema"), derive(schemars::JsonSchema))]
+pub struct ResetCausePacket {
+    pub cause: ResetCause,
+}
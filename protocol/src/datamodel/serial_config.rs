@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(any(test, feature = "schema"), derive(schemars::JsonSchema))]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(any(test, feature = "schema"), derive(schemars::JsonSchema))]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+/// USART1's line settings. There are always 8 data bits.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(any(test, feature = "schema"), derive(schemars::JsonSchema))]
+pub struct SerialConfig {
+    pub baud: u32,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+impl SerialConfig {
+    /// 115200 8N1, used until the host picks something else.
+    pub const DEFAULT: Self = Self {
+        baud: 115_200,
+        parity: Parity::None,
+        stop_bits: StopBits::One,
+    };
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(any(test, feature = "schema"), derive(schemars::JsonSchema))]
+pub struct SerialConfigPacket {
+    /// The settings the device switches to after this reply if `switching`,
+    /// otherwise the settings in use.
+    pub config: SerialConfig,
+    pub switching: bool,
+}
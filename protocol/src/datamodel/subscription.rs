@@ -0,0 +1,30 @@
+use serde::{Deserialize, Serialize};
+
+/// A stream the device sends unprompted once subscribed to, without a request ID.
+/// New topics are only ever appended.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(any(test, feature = "schema"), derive(schemars::JsonSchema))]
+pub enum Topic {
+    /// `Telemetry` messages, the turret's position and direction.
+    Telemetry,
+    /// `LinkErrors` messages, the USART1 and DMA error counters.
+    LinkErrors,
+    /// `TelemetryBatch` messages, the turret's position sampled at 1 kHz. Each batch holds the
+    /// samples taken since the previous one, so the period sets the batches' length.
+    TelemetryBatch,
+}
+
+impl Topic {
+    /// Every topic, in order.
+    pub const ALL: [Topic; 3] = [Topic::Telemetry, Topic::LinkErrors, Topic::TelemetryBatch];
+}
+
+/// Sent by the host to stream `topic` every `period_ms`, or to stop it with a period of 0.
+/// The device replies with the period it granted, which is longer than requested if the link
+/// can't carry the topic that often, and 0 if it can't carry it at all.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(any(test, feature = "schema"), derive(schemars::JsonSchema))]
+pub struct Subscription {
+    pub topic: Topic,
+    pub period_ms: u32,
+}
@@ -0,0 +1,43 @@
+use heapless::Vec;
+use serde::{Deserialize, Serialize};
+
+/// Most samples a batch holds, the first one's count included.
+pub const BATCH_LEN: usize = 64;
+
+/// The turret's position sampled at a fixed rate, sent unprompted while the
+/// `TelemetryBatch` topic is subscribed to.
+///
+/// Rather than every count, a batch carries the first one and how much each following sample
+/// moved from the one before it. A move that doesn't fit a delta starts the next batch.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(any(test, feature = "schema"), derive(schemars::JsonSchema))]
+pub struct TelemetryBatch {
+    /// Milliseconds since boot at which the first sample was taken.
+    pub start_ms: u32,
+    /// Microseconds between samples.
+    pub period_us: u32,
+    /// Encoder count of the first sample.
+    pub first_pos: u32,
+    /// Counts from each sample to the next, one fewer than the samples.
+    #[cfg_attr(any(test, feature = "schema"), schemars(with = "std::vec::Vec<i8>"))]
+    pub deltas: Vec<i8, { BATCH_LEN - 1 }>,
+    /// Samples lost right before this batch because the device's buffer was full, so the
+    /// batch doesn't follow on from the previous one.
+    pub dropped: u32,
+}
+
+impl TelemetryBatch {
+    /// Each sample's time in milliseconds since boot, and encoder count.
+    pub fn samples(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        let first = core::iter::once(0i8);
+        let mut pos = self.first_pos;
+        first
+            .chain(self.deltas.iter().copied())
+            .enumerate()
+            .map(move |(i, delta)| {
+                pos = pos.wrapping_add(delta as u32);
+                let offset_ms = (i as u64 * u64::from(self.period_us) / 1000) as u32;
+                (self.start_ms.wrapping_add(offset_ms), pos)
+            })
+    }
+}
@@ -0,0 +1,17 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(any(test, feature = "schema"), derive(schemars::JsonSchema))]
+pub enum TurretDirection {
+    Forward,
+    Backward,
+}
+
+/// Fields are only ever appended, so older hosts decode newer firmware's telemetry by ignoring
+/// the fields they don't know, with every codec.
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(any(test, feature = "schema"), derive(schemars::JsonSchema))]
+pub struct TurretTelemetryPacket {
+    pub turret_pos: u32,
+    pub turret_rot: TurretDirection,
+}
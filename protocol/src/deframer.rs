@@ -0,0 +1,211 @@
+//! Splits a byte stream into COBS frames.
+//!
+//! Frames are delimited by the `0x00` sentinel, regardless of how the bytes were chunked on the
+//! way in: one push may hold several frames, and one frame may span several pushes.
+//! Since `0x00` never appears inside a COBS frame, the deframer resynchronises on the first
+//! sentinel after any garbage.
+
+use postcard_cobs::{DecodeResult, DecoderState};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeframeError {
+    /// The frame decoded to more than the deframer's capacity, and was discarded.
+    Overflow,
+    /// The bytes before the sentinel weren't a well-formed COBS frame.
+    Cobs,
+}
+
+/// Accumulates bytes until a sentinel completes a frame, decoding as it goes.
+/// `N` is the largest decoded frame accepted.
+pub struct Deframer<const N: usize> {
+    buffer: [u8; N],
+    len: usize,
+    state: DecoderState,
+    overflowed: bool,
+}
+
+impl<const N: usize> Deframer<N> {
+    pub const fn new() -> Self {
+        Self {
+            buffer: [0; N],
+            len: 0,
+            state: DecoderState::Idle,
+            overflowed: false,
+        }
+    }
+
+    /// Feeds one received byte.
+    ///
+    /// Returns the decoded frame once `byte` is the sentinel that terminates it.
+    /// Sentinels that don't terminate anything, such as padding, are ignored.
+    pub fn push(&mut self, byte: u8) -> Option<Result<&mut [u8], DeframeError>> {
+        let result = match self.state.feed(byte) {
+            Ok(DecodeResult::NoData) => return None,
+            Ok(DecodeResult::DataContinue(decoded)) => {
+                if self.len < N {
+                    self.buffer[self.len] = decoded;
+                    self.len += 1;
+                } else {
+                    self.overflowed = true;
+                }
+                return None;
+            }
+            Ok(DecodeResult::DataComplete) if self.overflowed => Err(DeframeError::Overflow),
+            Ok(DecodeResult::DataComplete) => Ok(self.len),
+            // the decoder only fails on a sentinel, so it is already realigned with the stream.
+            Err(()) if self.overflowed => Err(DeframeError::Overflow),
+            Err(()) => Err(DeframeError::Cobs),
+        };
+        self.len = 0;
+        self.overflowed = false;
+        Some(result.map(move |len| &mut self.buffer[..len]))
+    }
+
+    /// Discards a partially received frame.
+    pub fn reset(&mut self) {
+        self.state = DecoderState::Idle;
+        self.len = 0;
+        self.overflowed = false;
+    }
+}
+
+impl<const N: usize> Default for Deframer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pushes `bytes`, collecting every frame or error produced along the way.
+    fn push_all<const N: usize>(
+        deframer: &mut Deframer<N>,
+        bytes: &[u8],
+    ) -> Vec<Result<Vec<u8>, DeframeError>> {
+        bytes
+            .iter()
+            .filter_map(|byte| deframer.push(*byte).map(|r| r.map(|frame| frame.to_vec())))
+            .collect()
+    }
+
+    /// COBS encodes `data`, terminated by a sentinel.
+    fn frame(data: &[u8]) -> Vec<u8> {
+        let mut encoded = vec![0; postcard_cobs::max_encoding_length(data.len())];
+        let len = postcard_cobs::encode(data, &mut encoded);
+        encoded.truncate(len);
+        encoded.push(0x00);
+        encoded
+    }
+
+    #[test]
+    fn single_frame() {
+        let mut deframer = Deframer::<64>::new();
+        let frames = push_all(&mut deframer, &frame(&[1, 2, 0, 3]));
+        assert_eq!(frames, vec![Ok(vec![1, 2, 0, 3])]);
+    }
+
+    #[test]
+    fn frame_ending_in_zero() {
+        let mut deframer = Deframer::<64>::new();
+        let frames = push_all(&mut deframer, &frame(&[1, 0]));
+        assert_eq!(frames, vec![Ok(vec![1, 0])]);
+    }
+
+    #[test]
+    fn multiple_frames_in_one_push() {
+        let mut deframer = Deframer::<64>::new();
+        let mut burst = frame(&[1, 2, 3]);
+        burst.extend(frame(&[4, 0, 5]));
+        burst.extend(frame(&[6]));
+        let frames = push_all(&mut deframer, &burst);
+        assert_eq!(
+            frames,
+            vec![Ok(vec![1, 2, 3]), Ok(vec![4, 0, 5]), Ok(vec![6])]
+        );
+    }
+
+    #[test]
+    fn frame_split_across_pushes() {
+        let mut deframer = Deframer::<64>::new();
+        let encoded = frame(&[1, 2, 0, 3, 4]);
+        let (head, tail) = encoded.split_at(3);
+        assert_eq!(push_all(&mut deframer, head), vec![]);
+        assert_eq!(push_all(&mut deframer, tail), vec![Ok(vec![1, 2, 0, 3, 4])]);
+    }
+
+    #[test]
+    fn frame_split_byte_by_byte() {
+        let mut deframer = Deframer::<64>::new();
+        let data: Vec<u8> = (0..40).collect();
+        let mut frames = Vec::new();
+        for byte in frame(&data) {
+            frames.extend(push_all(&mut deframer, &[byte]));
+        }
+        assert_eq!(frames, vec![Ok(data)]);
+    }
+
+    #[test]
+    fn padding_sentinels_are_ignored() {
+        let mut deframer = Deframer::<64>::new();
+        let mut burst = vec![0, 0, 0];
+        burst.extend(frame(&[7, 8]));
+        burst.extend([0, 0, 0, 0]);
+        burst.extend(frame(&[9]));
+        let frames = push_all(&mut deframer, &burst);
+        assert_eq!(frames, vec![Ok(vec![7, 8]), Ok(vec![9])]);
+    }
+
+    #[test]
+    fn resynchronises_after_truncated_frame() {
+        let mut deframer = Deframer::<64>::new();
+        // a frame cut short by a sentinel, e.g. the host restarted mid-transmission.
+        let mut burst = frame(&[1, 2, 3, 4, 5]);
+        burst.truncate(3);
+        burst.push(0x00);
+        burst.extend(frame(&[6, 7]));
+        let frames = push_all(&mut deframer, &burst);
+        assert_eq!(frames, vec![Err(DeframeError::Cobs), Ok(vec![6, 7])]);
+    }
+
+    #[test]
+    fn resynchronises_after_garbage() {
+        let mut deframer = Deframer::<64>::new();
+        // joining the stream mid-frame, the leftovers decode to something, then we are aligned.
+        let mut burst = vec![0x13, 0x37, 0x42];
+        burst.push(0x00);
+        burst.extend(frame(&[1, 2, 3]));
+        let frames = push_all(&mut deframer, &burst);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1], Ok(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn oversized_frame_is_discarded() {
+        let mut deframer = Deframer::<8>::new();
+        let data: Vec<u8> = (1..20).collect();
+        let mut burst = frame(&data);
+        burst.extend(frame(&[1, 2]));
+        let frames = push_all(&mut deframer, &burst);
+        assert_eq!(frames, vec![Err(DeframeError::Overflow), Ok(vec![1, 2])]);
+    }
+
+    #[test]
+    fn frame_of_exactly_capacity() {
+        let mut deframer = Deframer::<8>::new();
+        let data: Vec<u8> = (1..=8).collect();
+        let frames = push_all(&mut deframer, &frame(&data));
+        assert_eq!(frames, vec![Ok(data)]);
+    }
+
+    #[test]
+    fn reset_discards_partial_frame() {
+        let mut deframer = Deframer::<64>::new();
+        let encoded = frame(&[1, 2, 3, 4]);
+        push_all(&mut deframer, &encoded[..3]);
+        deframer.reset();
+        let frames = push_all(&mut deframer, &frame(&[5]));
+        assert_eq!(frames, vec![Ok(vec![5])]);
+    }
+}
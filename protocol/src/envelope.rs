@@ -0,0 +1,506 @@
+//! Every message, whichever way it is sent, is wrapped in an envelope saying what it is.
+//!
+//! The envelope is a fixed header in front of the payload, which is encoded with the codec the
+//! firmware was built with:
+//! ```text
+//! | version | message type | request id | <payload> |
+//! ```
+//! The header is plain bytes, so a receiver can tell what a message is before picking a type to
+//! decode its payload as, and can skip payloads of message types it doesn't know.
+
+use serde::{Deserialize, Serialize};
+
+use crate::datamodel::{
+    ack::AckPacket, crash_record::CrashRecordPacket, error::ErrorPacket,
+    link_errors::LinkErrorsPacket, log_level::LogLevelPacket, log_record::LogRecordPacket,
+    node_address::NodeAddress, request::Request, reset_cause::ResetCausePacket,
+    serial_config::SerialConfigPacket, subscription::Subscription, telemetry_batch::TelemetryBatch,
+    telemetry_packet::TurretTelemetryPacket,
+};
+use crate::hello::{Hello, HELLO_LEN};
+
+/// Version of the protocol this crate implements, sent in every envelope.
+pub const PROTOCOL_VERSION: u8 = 1;
+/// Oldest protocol version this crate still understands.
+pub const MIN_PROTOCOL_VERSION: u8 = 1;
+/// Length of the [`EnvelopeHeader`] in front of every payload.
+pub const ENVELOPE_LEN: usize = 3;
+
+/// What an envelope's payload is. New types are only ever appended.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MessageType {
+    Request = 0,
+    Telemetry = 1,
+    ResetCause = 2,
+    CrashRecord = 3,
+    LogLevel = 4,
+    LinkErrors = 5,
+    SerialConfig = 6,
+    Ack = 7,
+    Log = 8,
+    Error = 9,
+    /// Negotiates the protocol version and capabilities, see [`hello`](crate::hello).
+    Hello = 10,
+    /// Starts, changes or stops streaming a topic, see
+    /// [`Subscription`](crate::datamodel::subscription::Subscription).
+    Subscription = 11,
+    /// The turret's position sampled at a fixed rate, see
+    /// [`TelemetryBatch`](crate::datamodel::telemetry_batch::TelemetryBatch).
+    TelemetryBatch = 12,
+    /// Asks for or moves the device's address on the bus, see
+    /// [`NodeAddress`](crate::datamodel::node_address::NodeAddress).
+    NodeAddress = 13,
+}
+
+impl MessageType {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        Some(match value {
+            0 => Self::Request,
+            1 => Self::Telemetry,
+            2 => Self::ResetCause,
+            3 => Self::CrashRecord,
+            4 => Self::LogLevel,
+            5 => Self::LinkErrors,
+            6 => Self::SerialConfig,
+            7 => Self::Ack,
+            8 => Self::Log,
+            9 => Self::Error,
+            10 => Self::Hello,
+            11 => Self::Subscription,
+            12 => Self::TelemetryBatch,
+            13 => Self::NodeAddress,
+            _ => return None,
+        })
+    }
+}
+
+// ANCHOR: envelope_header
+/// The header in front of every payload.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EnvelopeHeader {
+    /// The sender's [`PROTOCOL_VERSION`].
+    pub version: u8,
+    /// The payload's [`MessageType`], kept as a number since it may be one we don't know.
+    pub message_type: u8,
+    /// Chosen by the host for a request, and echoed by the device's replies to it.
+    /// `None` is sent as 0, so hosts pick IDs from 1 to 255.
+    pub request_id: Option<u8>,
+}
+// ANCHOR_END: envelope_header
+
+impl EnvelopeHeader {
+    /// Splits a message into its header and payload.
+    pub fn split(message: &mut [u8]) -> Option<(Self, &mut [u8])> {
+        if message.len() < ENVELOPE_LEN {
+            return None;
+        }
+        let (header, payload) = message.split_at_mut(ENVELOPE_LEN);
+        let header = Self {
+            version: header[0],
+            message_type: header[1],
+            request_id: match header[2] {
+                0 => None,
+                id => Some(id),
+            },
+        };
+        Some((header, payload))
+    }
+
+    pub fn to_bytes(self) -> [u8; ENVELOPE_LEN] {
+        [
+            self.version,
+            self.message_type,
+            self.request_id.unwrap_or(0),
+        ]
+    }
+}
+
+/// Any message the device or a host sends.
+#[derive(Debug)]
+pub enum Message<'a> {
+    Request(Request),
+    Telemetry(TurretTelemetryPacket),
+    ResetCause(ResetCausePacket),
+    CrashRecord(CrashRecordPacket<'a>),
+    LogLevel(LogLevelPacket),
+    LinkErrors(LinkErrorsPacket),
+    SerialConfig(SerialConfigPacket),
+    Ack(AckPacket),
+    Log(LogRecordPacket<'a>),
+    Error(ErrorPacket),
+    Hello(Hello),
+    Subscription(Subscription),
+    TelemetryBatch(TelemetryBatch),
+    NodeAddress(NodeAddress),
+    /// A message type this crate doesn't know, from a newer sender. Its payload was skipped.
+    Unknown(u8),
+}
+
+impl Message<'_> {
+    /// The type sent in the envelope, or the unknown type's number.
+    pub fn message_type(&self) -> Result<MessageType, u8> {
+        Ok(match self {
+            Message::Request(_) => MessageType::Request,
+            Message::Telemetry(_) => MessageType::Telemetry,
+            Message::ResetCause(_) => MessageType::ResetCause,
+            Message::CrashRecord(_) => MessageType::CrashRecord,
+            Message::LogLevel(_) => MessageType::LogLevel,
+            Message::LinkErrors(_) => MessageType::LinkErrors,
+            Message::SerialConfig(_) => MessageType::SerialConfig,
+            Message::Ack(_) => MessageType::Ack,
+            Message::Log(_) => MessageType::Log,
+            Message::Error(_) => MessageType::Error,
+            Message::Hello(_) => MessageType::Hello,
+            Message::Subscription(_) => MessageType::Subscription,
+            Message::TelemetryBatch(_) => MessageType::TelemetryBatch,
+            Message::NodeAddress(_) => MessageType::NodeAddress,
+            Message::Unknown(message_type) => return Err(*message_type),
+        })
+    }
+}
+
+/// A payload encoding, implemented by whichever codecs the firmware or host support.
+pub trait WireCodec {
+    type Error;
+
+    /// Encodes `value` into `buffer`, returning the number of bytes written.
+    fn serialize<T: Serialize>(&self, value: &T, buffer: &mut [u8]) -> Result<usize, Self::Error>;
+
+    /// Decodes a `T` from `buffer`.
+    fn deserialize<'a, T: Deserialize<'a>>(&self, buffer: &'a mut [u8]) -> Result<T, Self::Error>;
+}
+
+#[derive(Debug, PartialEq)]
+pub enum EnvelopeError<E> {
+    /// The message is too short to hold an envelope header, or a hello.
+    Truncated,
+    /// The buffer is too small for the envelope header, or a hello.
+    BufferTooSmall,
+    /// `Message::Unknown` can't be encoded, there's no payload to send.
+    UnknownType(u8),
+    /// The codec failed to encode or decode the payload.
+    Codec(E),
+}
+
+/// Wraps `message` in an envelope, encoding it into `buffer`.
+/// Returns the number of bytes written.
+pub fn encode<C: WireCodec>(
+    codec: &C,
+    message: &Message,
+    request_id: Option<u8>,
+    buffer: &mut [u8],
+) -> Result<usize, EnvelopeError<C::Error>> {
+    let header = EnvelopeHeader {
+        version: PROTOCOL_VERSION,
+        message_type: message.message_type().map_err(EnvelopeError::UnknownType)? as u8,
+        request_id,
+    };
+    if buffer.len() < ENVELOPE_LEN {
+        return Err(EnvelopeError::BufferTooSmall);
+    }
+    let (header_buffer, payload) = buffer.split_at_mut(ENVELOPE_LEN);
+    header_buffer.copy_from_slice(&header.to_bytes());
+    let payload_size = match message {
+        Message::Request(m) => codec.serialize(m, payload),
+        Message::Telemetry(m) => codec.serialize(m, payload),
+        Message::ResetCause(m) => codec.serialize(m, payload),
+        Message::CrashRecord(m) => codec.serialize(m, payload),
+        Message::LogLevel(m) => codec.serialize(m, payload),
+        Message::LinkErrors(m) => codec.serialize(m, payload),
+        Message::SerialConfig(m) => codec.serialize(m, payload),
+        Message::Ack(m) => codec.serialize(m, payload),
+        Message::Log(m) => codec.serialize(m, payload),
+        Message::Error(m) => codec.serialize(m, payload),
+        Message::Subscription(m) => codec.serialize(m, payload),
+        Message::TelemetryBatch(m) => codec.serialize(m, payload),
+        Message::NodeAddress(m) => codec.serialize(m, payload),
+        // sent as plain bytes, so it can be read before a codec was agreed on.
+        Message::Hello(hello) => {
+            if payload.len() < HELLO_LEN {
+                return Err(EnvelopeError::BufferTooSmall);
+            }
+            payload[..HELLO_LEN].copy_from_slice(&hello.to_bytes());
+            Ok(HELLO_LEN)
+        }
+        // NOTE(unreachable): rejected by `message_type` above.
+        Message::Unknown(_) => unreachable!(),
+    }
+    .map_err(EnvelopeError::Codec)?;
+    Ok(ENVELOPE_LEN + payload_size)
+}
+
+/// Unwraps a message from its envelope, decoding its payload.
+/// Messages of unknown types decode to [`Message::Unknown`], so the caller can skip them.
+pub fn decode<'a, C: WireCodec>(
+    codec: &C,
+    message: &'a mut [u8],
+) -> Result<(EnvelopeHeader, Message<'a>), EnvelopeError<C::Error>> {
+    let (header, payload) = EnvelopeHeader::split(message).ok_or(EnvelopeError::Truncated)?;
+    let message_type = match MessageType::from_u8(header.message_type) {
+        Some(message_type) => message_type,
+        None => return Ok((header, Message::Unknown(header.message_type))),
+    };
+    let message = match message_type {
+        MessageType::Request => codec.deserialize(payload).map(Message::Request),
+        MessageType::Telemetry => codec.deserialize(payload).map(Message::Telemetry),
+        MessageType::ResetCause => codec.deserialize(payload).map(Message::ResetCause),
+        MessageType::CrashRecord => codec.deserialize(payload).map(Message::CrashRecord),
+        MessageType::LogLevel => codec.deserialize(payload).map(Message::LogLevel),
+        MessageType::LinkErrors => codec.deserialize(payload).map(Message::LinkErrors),
+        MessageType::SerialConfig => codec.deserialize(payload).map(Message::SerialConfig),
+        MessageType::Ack => codec.deserialize(payload).map(Message::Ack),
+        MessageType::Log => codec.deserialize(payload).map(Message::Log),
+        MessageType::Error => codec.deserialize(payload).map(Message::Error),
+        MessageType::Hello => {
+            let hello = Hello::from_bytes(payload).ok_or(EnvelopeError::Truncated)?;
+            Ok(Message::Hello(hello))
+        }
+        MessageType::Subscription => codec.deserialize(payload).map(Message::Subscription),
+        MessageType::TelemetryBatch => codec.deserialize(payload).map(Message::TelemetryBatch),
+        MessageType::NodeAddress => codec.deserialize(payload).map(Message::NodeAddress),
+    }
+    .map_err(EnvelopeError::Codec)?;
+    Ok((header, message))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::datamodel::crash_record::CrashKind;
+    use crate::datamodel::error::ErrorCode;
+    use crate::datamodel::log_level::LogLevel;
+    use crate::datamodel::request::RequestKind;
+    use crate::datamodel::subscription::Topic;
+    use crate::datamodel::telemetry_packet::TurretDirection;
+
+    /// The firmware's default codec.
+    pub(crate) struct Cbor;
+
+    impl WireCodec for Cbor {
+        type Error = ();
+
+        fn serialize<T: Serialize>(&self, value: &T, buffer: &mut [u8]) -> Result<usize, ()> {
+            let mut writer = serde_cbor::ser::SliceWrite::new(buffer);
+            let mut serializer = serde_cbor::Serializer::new(&mut writer);
+            value.serialize(&mut serializer).map_err(|_| ())?;
+            Ok(writer.bytes_written())
+        }
+
+        fn deserialize<'a, T: Deserialize<'a>>(&self, buffer: &'a mut [u8]) -> Result<T, ()> {
+            serde_cbor::de::from_mut_slice(buffer).map_err(|_| ())
+        }
+    }
+
+    /// A positional codec, where the payload's layout must match exactly.
+    pub(crate) struct Postcard;
+
+    impl WireCodec for Postcard {
+        type Error = ();
+
+        fn serialize<T: Serialize>(&self, value: &T, buffer: &mut [u8]) -> Result<usize, ()> {
+            postcard::to_slice(value, buffer)
+                .map(|used| used.len())
+                .map_err(|_| ())
+        }
+
+        fn deserialize<'a, T: Deserialize<'a>>(&self, buffer: &'a mut [u8]) -> Result<T, ()> {
+            postcard::from_bytes(buffer).map_err(|_| ())
+        }
+    }
+
+    /// The bench codec.
+    pub(crate) struct Json;
+
+    impl WireCodec for Json {
+        type Error = ();
+
+        fn serialize<T: Serialize>(&self, value: &T, buffer: &mut [u8]) -> Result<usize, ()> {
+            let json = serde_json::to_vec(value).map_err(|_| ())?;
+            buffer
+                .get_mut(..json.len())
+                .ok_or(())?
+                .copy_from_slice(&json);
+            Ok(json.len())
+        }
+
+        fn deserialize<'a, T: Deserialize<'a>>(&self, buffer: &'a mut [u8]) -> Result<T, ()> {
+            serde_json::from_slice(buffer).map_err(|_| ())
+        }
+    }
+
+    fn round_trip<C: WireCodec>(codec: &C, message: &Message, request_id: Option<u8>) -> String
+    where
+        C::Error: core::fmt::Debug,
+    {
+        let mut buffer = [0u8; 256];
+        let len = encode(codec, message, request_id, &mut buffer).unwrap();
+        let (header, decoded) = decode(codec, &mut buffer[..len]).unwrap();
+        assert_eq!(header.version, PROTOCOL_VERSION);
+        assert_eq!(header.request_id, request_id);
+        assert_eq!(
+            MessageType::from_u8(header.message_type),
+            decoded.message_type().ok()
+        );
+        format!("{:?}", decoded)
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let messages = [
+            Message::Request(Request {
+                kind: RequestKind::LogLevel,
+                log_level: Some(LogLevel::Warn),
+                serial: None,
+                seq: Some(3),
+            }),
+            Message::Telemetry(TurretTelemetryPacket {
+                turret_pos: 42,
+                turret_rot: TurretDirection::Forward,
+            }),
+            Message::Ack(AckPacket {
+                seq: 3,
+                duplicate: false,
+            }),
+            Message::Error(ErrorPacket {
+                code: ErrorCode::Busy,
+            }),
+            Message::Subscription(Subscription {
+                topic: Topic::LinkErrors,
+                period_ms: 250,
+            }),
+            Message::CrashRecord(CrashRecordPacket {
+                kind: CrashKind::HardFault,
+                file: "src/main.rs",
+                line: 0,
+                pc: 0x0800_1234,
+                lr: 0xFFFF_FFF9,
+                cfsr: 0x0000_8200,
+                hfsr: 0x4000_0000,
+                mmfar: 0,
+                bfar: 0x2002_0000,
+                msg: "",
+            }),
+            Message::TelemetryBatch(TelemetryBatch {
+                start_ms: 1_000,
+                period_us: 1_000,
+                first_pos: u32::MAX,
+                deltas: heapless::Vec::from_slice(&[1, 2, -128, 127]).unwrap(),
+                dropped: 3,
+            }),
+            Message::NodeAddress(NodeAddress { address: Some(5) }),
+            Message::NodeAddress(NodeAddress { address: None }),
+        ];
+        for message in &messages {
+            let expected = format!("{:?}", message);
+            assert_eq!(round_trip(&Cbor, message, Some(9)), expected);
+            assert_eq!(round_trip(&Postcard, message, None), expected);
+            assert_eq!(round_trip(&Json, message, Some(255)), expected);
+        }
+    }
+
+    /// A later firmware's telemetry, with a field appended.
+    #[derive(Serialize)]
+    struct NewerTelemetry {
+        turret_pos: u32,
+        turret_rot: TurretDirection,
+        turret_speed: f32,
+    }
+
+    fn decode_newer_telemetry<C: WireCodec>(codec: &C) -> String
+    where
+        C::Error: core::fmt::Debug,
+    {
+        let newer = NewerTelemetry {
+            turret_pos: 7,
+            turret_rot: TurretDirection::Backward,
+            turret_speed: 1.5,
+        };
+        let mut buffer = [0u8; 128];
+        buffer[..ENVELOPE_LEN].copy_from_slice(&[
+            PROTOCOL_VERSION,
+            MessageType::Telemetry as u8,
+            0,
+        ]);
+        let len = ENVELOPE_LEN
+            + codec
+                .serialize(&newer, &mut buffer[ENVELOPE_LEN..])
+                .unwrap();
+        let (_, decoded) = decode(codec, &mut buffer[..len]).unwrap();
+        format!("{:?}", decoded)
+    }
+
+    #[test]
+    fn appended_fields_are_ignored() {
+        let expected = format!(
+            "{:?}",
+            Message::Telemetry(TurretTelemetryPacket {
+                turret_pos: 7,
+                turret_rot: TurretDirection::Backward,
+            })
+        );
+        assert_eq!(decode_newer_telemetry(&Cbor), expected);
+        assert_eq!(decode_newer_telemetry(&Postcard), expected);
+        assert_eq!(decode_newer_telemetry(&Json), expected);
+    }
+
+    #[test]
+    fn hello_ignores_the_codec() {
+        let hello = Hello {
+            min_version: 1,
+            max_version: 1,
+            codecs: crate::hello::CODEC_JSON,
+            flags: 0,
+            max_frame: 64,
+        };
+        let mut buffer = [0u8; 16];
+        let len = encode(&Postcard, &Message::Hello(hello), Some(1), &mut buffer).unwrap();
+        assert_eq!(len, ENVELOPE_LEN + HELLO_LEN);
+        let (_, decoded) = decode(&Cbor, &mut buffer[..len]).unwrap();
+        assert!(matches!(decoded, Message::Hello(h) if h == hello));
+    }
+
+    #[test]
+    fn header_layout() {
+        let mut buffer = [0u8; 32];
+        let message = Message::Ack(AckPacket {
+            seq: 1,
+            duplicate: true,
+        });
+        encode(&Postcard, &message, Some(0x42), &mut buffer).unwrap();
+        assert_eq!(
+            buffer[..ENVELOPE_LEN],
+            [PROTOCOL_VERSION, MessageType::Ack as u8, 0x42]
+        );
+    }
+
+    #[test]
+    fn unknown_types_are_skipped() {
+        let mut message = [PROTOCOL_VERSION, 200, 0, 0xDE, 0xAD];
+        let (header, decoded) = decode(&Cbor, &mut message).unwrap();
+        assert_eq!(header.message_type, 200);
+        assert!(matches!(decoded, Message::Unknown(200)));
+        assert_eq!(
+            encode(&Cbor, &decoded, None, &mut [0u8; 8]),
+            Err(EnvelopeError::UnknownType(200))
+        );
+    }
+
+    #[test]
+    fn truncated_envelope() {
+        assert_eq!(
+            decode(&Cbor, &mut [PROTOCOL_VERSION, 0]).unwrap_err(),
+            EnvelopeError::Truncated
+        );
+    }
+
+    #[test]
+    fn message_types_are_stable() {
+        for value in 0..=u8::MAX {
+            if let Some(message_type) = MessageType::from_u8(value) {
+                assert_eq!(message_type as u8, value);
+            }
+        }
+        assert_eq!(MessageType::from_u8(14), None);
+    }
+}
@@ -0,0 +1,377 @@
+//! Splits logical messages into fragments that each fit in a frame, and reassembles them.
+//!
+//! Every frame carries exactly one fragment, prefixed with the frame's
+//! [address](crate::address) and a [`FragmentHeader`], and followed by the frame's CRC:
+//! ```text
+//! | address | message id | index | count | chunk | 4 byte CRC |
+//! ```
+//! A message that fits in one frame is simply a message of one fragment.
+//! Fragments of a message are sent back to back and in order, the link never reorders them.
+
+use crate::address::ADDRESS_LEN;
+
+/// Length of the [`FragmentHeader`], which follows the address at the front of every frame.
+pub const HEADER_LEN: usize = 3;
+/// Length of the CRC-32 at the back of every frame.
+pub const CRC_LEN: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FragmentError {
+    /// The frame is too short to hold a header.
+    Truncated,
+    /// The header's index isn't below its count.
+    Malformed,
+    /// The message needs more fragments than a header can count.
+    TooManyFragments,
+    /// The reassembled message outgrew the reassembler's capacity, and was discarded.
+    Overflow,
+    /// The fragment doesn't continue the message being reassembled, which was discarded.
+    OutOfSequence,
+}
+
+// ANCHOR: header
+/// Identifies a fragment's place in its message.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FragmentHeader {
+    /// Shared by every fragment of a message, and different from the previous message's.
+    pub message_id: u8,
+    /// Position of this fragment within the message, starting at 0.
+    pub index: u8,
+    /// Number of fragments in the message, at least 1.
+    pub count: u8,
+}
+// ANCHOR_END: header
+
+impl FragmentHeader {
+    /// Splits a frame's contents, address and CRC already removed, into its header and chunk.
+    pub fn split(fragment: &[u8]) -> Result<(Self, &[u8]), FragmentError> {
+        if fragment.len() < HEADER_LEN {
+            return Err(FragmentError::Truncated);
+        }
+        let header = Self {
+            message_id: fragment[0],
+            index: fragment[1],
+            count: fragment[2],
+        };
+        if header.index >= header.count {
+            return Err(FragmentError::Malformed);
+        }
+        Ok((header, &fragment[HEADER_LEN..]))
+    }
+
+    pub fn to_bytes(self) -> [u8; HEADER_LEN] {
+        [self.message_id, self.index, self.count]
+    }
+}
+
+/// Largest chunk of a message that fits in a frame of `frame_size` bytes on the wire, once the
+/// address, header and CRC are added, the result COBS encoded and followed by its sentinel.
+pub const fn max_chunk_len(frame_size: usize) -> usize {
+    // COBS adds an overhead byte per started run of 254 bytes, plus the sentinel.
+    let mut encoded = frame_size - 2;
+    while encoded + encoded / 254 + 2 > frame_size {
+        encoded -= 1;
+    }
+    encoded - ADDRESS_LEN - HEADER_LEN - CRC_LEN
+}
+
+/// Iterator over the fragments of a message, see [`fragment`].
+pub struct Fragments<'a> {
+    message: &'a [u8],
+    chunk_len: usize,
+    message_id: u8,
+    index: u8,
+    count: u8,
+}
+
+/// Splits `message` into fragments whose chunks are at most `chunk_len` bytes.
+///
+/// An empty message still yields one, empty, fragment.
+pub fn fragment(
+    message: &[u8],
+    message_id: u8,
+    chunk_len: usize,
+) -> Result<Fragments<'_>, FragmentError> {
+    let count = match message.len() {
+        0 => 1,
+        len => (len - 1) / chunk_len + 1,
+    };
+    if count > u8::MAX as usize {
+        return Err(FragmentError::TooManyFragments);
+    }
+    Ok(Fragments {
+        message,
+        chunk_len,
+        message_id,
+        index: 0,
+        count: count as u8,
+    })
+}
+
+impl<'a> Iterator for Fragments<'a> {
+    type Item = (FragmentHeader, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index == self.count {
+            return None;
+        }
+        let header = FragmentHeader {
+            message_id: self.message_id,
+            index: self.index,
+            count: self.count,
+        };
+        let (chunk, rest) = self
+            .message
+            .split_at(self.chunk_len.min(self.message.len()));
+        self.message = rest;
+        self.index += 1;
+        Some((header, chunk))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = (self.count - self.index) as usize;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for Fragments<'_> {}
+
+/// Collects fragments until a message is complete. `N` is the largest message accepted.
+///
+/// A first fragment always starts a new message, abandoning any partially received one,
+/// so a sender that gave up midway doesn't wedge the receiver.
+pub struct Reassembler<const N: usize> {
+    buffer: [u8; N],
+    len: usize,
+    /// the fragment expected next, if a message is partially received.
+    expected: Option<FragmentHeader>,
+}
+
+impl<const N: usize> Reassembler<N> {
+    pub const fn new() -> Self {
+        Self {
+            buffer: [0; N],
+            len: 0,
+            expected: None,
+        }
+    }
+
+    /// Adds a fragment, returning the whole message once `header` is its last fragment.
+    pub fn push(
+        &mut self,
+        header: FragmentHeader,
+        chunk: &[u8],
+    ) -> Result<Option<&mut [u8]>, FragmentError> {
+        if header.index == 0 {
+            self.reset();
+        } else if self.expected != Some(header) {
+            self.reset();
+            return Err(FragmentError::OutOfSequence);
+        }
+
+        let end = self.len + chunk.len();
+        if end > N {
+            self.reset();
+            return Err(FragmentError::Overflow);
+        }
+        self.buffer[self.len..end].copy_from_slice(chunk);
+        self.len = end;
+
+        if header.index + 1 == header.count {
+            let len = self.len;
+            self.reset();
+            Ok(Some(&mut self.buffer[..len]))
+        } else {
+            self.expected = Some(FragmentHeader {
+                index: header.index + 1,
+                ..header
+            });
+            Ok(None)
+        }
+    }
+
+    /// Discards a partially received message.
+    pub fn reset(&mut self) {
+        self.len = 0;
+        self.expected = None;
+    }
+}
+
+impl<const N: usize> Default for Reassembler<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pushes every fragment of `message`, collecting whatever the reassembler returns.
+    fn round_trip<const N: usize>(
+        reassembler: &mut Reassembler<N>,
+        message: &[u8],
+        message_id: u8,
+        chunk_len: usize,
+    ) -> Vec<Result<Option<Vec<u8>>, FragmentError>> {
+        fragment(message, message_id, chunk_len)
+            .unwrap()
+            .map(|(header, chunk)| {
+                reassembler
+                    .push(header, chunk)
+                    .map(|message| message.map(|m| m.to_vec()))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn single_fragment_message() {
+        let mut reassembler = Reassembler::<64>::new();
+        let results = round_trip(&mut reassembler, &[1, 2, 3], 7, 16);
+        assert_eq!(results, vec![Ok(Some(vec![1, 2, 3]))]);
+    }
+
+    #[test]
+    fn multi_fragment_message() {
+        let mut reassembler = Reassembler::<256>::new();
+        let message: Vec<u8> = (0..=200).collect();
+        let results = round_trip(&mut reassembler, &message, 1, 55);
+        assert_eq!(results.len(), 4);
+        assert!(results[..3].iter().all(|r| *r == Ok(None)));
+        assert_eq!(results[3], Ok(Some(message)));
+    }
+
+    #[test]
+    fn fragments_are_numbered() {
+        let message = [0u8; 10];
+        let headers: Vec<_> = fragment(&message, 9, 4)
+            .unwrap()
+            .map(|(h, c)| (h, c.len()))
+            .collect();
+        assert_eq!(
+            headers,
+            vec![
+                (
+                    FragmentHeader {
+                        message_id: 9,
+                        index: 0,
+                        count: 3
+                    },
+                    4
+                ),
+                (
+                    FragmentHeader {
+                        message_id: 9,
+                        index: 1,
+                        count: 3
+                    },
+                    4
+                ),
+                (
+                    FragmentHeader {
+                        message_id: 9,
+                        index: 2,
+                        count: 3
+                    },
+                    2
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_message_is_one_fragment() {
+        let fragments: Vec<_> = fragment(&[], 0, 8).unwrap().collect();
+        assert_eq!(fragments.len(), 1);
+        assert_eq!(fragments[0].0.count, 1);
+        assert!(fragments[0].1.is_empty());
+    }
+
+    #[test]
+    fn too_many_fragments() {
+        let message = [0u8; 256];
+        assert!(matches!(
+            fragment(&message, 0, 1),
+            Err(FragmentError::TooManyFragments)
+        ));
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let header = FragmentHeader {
+            message_id: 3,
+            index: 1,
+            count: 2,
+        };
+        let mut frame = header.to_bytes().to_vec();
+        frame.extend([0xAA, 0xBB]);
+        assert_eq!(
+            FragmentHeader::split(&frame),
+            Ok((header, &[0xAA, 0xBB][..]))
+        );
+    }
+
+    #[test]
+    fn malformed_headers() {
+        assert_eq!(
+            FragmentHeader::split(&[1, 0]),
+            Err(FragmentError::Truncated)
+        );
+        assert_eq!(
+            FragmentHeader::split(&[1, 2, 2]),
+            Err(FragmentError::Malformed)
+        );
+        assert_eq!(
+            FragmentHeader::split(&[1, 0, 0]),
+            Err(FragmentError::Malformed)
+        );
+    }
+
+    #[test]
+    fn missing_fragment_is_out_of_sequence() {
+        let mut reassembler = Reassembler::<64>::new();
+        let message: Vec<u8> = (0..12).collect();
+        let mut fragments = fragment(&message, 4, 4).unwrap();
+        let (first, chunk) = fragments.next().unwrap();
+        assert_eq!(reassembler.push(first, chunk), Ok(None));
+        fragments.next();
+        let (last, chunk) = fragments.next().unwrap();
+        assert_eq!(
+            reassembler.push(last, chunk),
+            Err(FragmentError::OutOfSequence)
+        );
+    }
+
+    #[test]
+    fn new_message_abandons_partial_one() {
+        let mut reassembler = Reassembler::<64>::new();
+        let (first, chunk) = fragment(&[1; 8], 1, 4).unwrap().next().unwrap();
+        assert_eq!(reassembler.push(first, chunk), Ok(None));
+        let results = round_trip(&mut reassembler, &[2, 3], 2, 4);
+        assert_eq!(results, vec![Ok(Some(vec![2, 3]))]);
+    }
+
+    #[test]
+    fn oversized_message_is_discarded() {
+        let mut reassembler = Reassembler::<8>::new();
+        let results = round_trip(&mut reassembler, &[0; 12], 1, 4);
+        assert_eq!(results[2], Err(FragmentError::Overflow));
+        // and the next message is received as usual.
+        let results = round_trip(&mut reassembler, &[5, 6], 2, 4);
+        assert_eq!(results, vec![Ok(Some(vec![5, 6]))]);
+    }
+
+    #[test]
+    fn max_chunk_fits_the_frame() {
+        for frame_size in [16, 64, 128, 255, 256, 300, 512, 1024] {
+            let chunk_len = max_chunk_len(frame_size);
+            let decoded = ADDRESS_LEN + HEADER_LEN + chunk_len + CRC_LEN;
+            let encoded = postcard_cobs::max_encoding_length(decoded);
+            assert!(encoded < frame_size, "frame size {}", frame_size);
+        }
+        assert_eq!(
+            max_chunk_len(64),
+            64 - 2 - ADDRESS_LEN - HEADER_LEN - CRC_LEN
+        );
+    }
+}
@@ -0,0 +1,188 @@
+//! Version and capability negotiation between a host and the device.
+//!
+//! The host sends a [`Hello`] offering what it supports, and the device replies with a [`Hello`]
+//! holding what both agreed on, which both use from then on. Until then, and for hosts that never
+//! say hello, the device uses its build-time defaults.
+//!
+//! A hello's payload is plain bytes rather than codec encoded, so it can be sent before the host
+//! knows which codec the device speaks:
+//! ```text
+//! | min version | max version | codecs | flags | max frame size (2 bytes, BE) | ... |
+//! ```
+//! Later versions may append fields, which older receivers ignore.
+
+use crate::fragment::max_chunk_len;
+
+/// Length of a [`Hello`] as this version sends it.
+pub const HELLO_LEN: usize = 6;
+
+/// Codec bits of [`Hello::codecs`].
+pub const CODEC_CBOR: u8 = 1 << 0;
+pub const CODEC_POSTCARD: u8 = 1 << 1;
+pub const CODEC_JSON: u8 = 1 << 2;
+
+/// Flag of [`Hello::flags`]: the CRC covers the whole frame, its last partial word padded with
+/// zeros, rather than only its whole words.
+pub const FULL_CRC: u8 = 1 << 0;
+/// Flag of [`Hello::flags`], only set by the device: the hello holds the agreed settings.
+/// Without it, nothing was agreed, and the hello holds what the device supports.
+pub const AGREED: u8 = 1 << 7;
+
+/// Smallest frame size a hello may agree on. Smaller frames carry little besides their overhead,
+/// and the device sizes its TX queues for messages split into frames of this size.
+pub const MIN_FRAME_SIZE: usize = 32;
+
+/// What one side supports, or what both agreed on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hello {
+    /// Lowest protocol version supported, or the agreed one.
+    pub min_version: u8,
+    /// Highest protocol version supported, or the agreed one.
+    pub max_version: u8,
+    /// Bitmask of the codecs supported, or the agreed one's bit.
+    pub codecs: u8,
+    /// Bitmask of the optional features supported, or agreed.
+    pub flags: u8,
+    /// Largest frame, in bytes on the wire and sentinel included, the sender can receive.
+    /// In the device's reply, the size of the frames it sends from then on.
+    pub max_frame: u16,
+}
+
+impl Hello {
+    /// Reads a hello, ignoring any fields a later version appended.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < HELLO_LEN {
+            return None;
+        }
+        Some(Self {
+            min_version: bytes[0],
+            max_version: bytes[1],
+            codecs: bytes[2],
+            flags: bytes[3],
+            max_frame: u16::from_be_bytes([bytes[4], bytes[5]]),
+        })
+    }
+
+    pub fn to_bytes(self) -> [u8; HELLO_LEN] {
+        let max_frame = self.max_frame.to_be_bytes();
+        [
+            self.min_version,
+            self.max_version,
+            self.codecs,
+            self.flags,
+            max_frame[0],
+            max_frame[1],
+        ]
+    }
+
+    /// Whether this is a device's reply holding agreed settings.
+    pub fn is_agreed(&self) -> bool {
+        self.flags & AGREED != 0
+    }
+
+    /// Picks what both `self`, the device, and the host's `offer` support: the highest common
+    /// version, the first common codec in `preference`, the common flags, and the smaller frame.
+    /// Returns `None` if they have no version or codec in common, or the frame is too small.
+    pub fn negotiate(&self, offer: &Hello, preference: &[u8]) -> Option<Hello> {
+        let version = self.max_version.min(offer.max_version);
+        if version < self.min_version.max(offer.min_version) {
+            return None;
+        }
+        let codec = *preference
+            .iter()
+            .find(|&&codec| self.codecs & offer.codecs & codec != 0)?;
+        let max_frame = self.max_frame.min(offer.max_frame);
+        if (max_frame as usize) < MIN_FRAME_SIZE {
+            return None;
+        }
+        Some(Hello {
+            min_version: version,
+            max_version: version,
+            codecs: codec,
+            flags: (self.flags & offer.flags & !AGREED) | AGREED,
+            max_frame,
+        })
+    }
+
+    /// Largest chunk of a message carried by each frame, at the agreed frame size.
+    pub fn chunk_len(&self) -> usize {
+        max_chunk_len(self.max_frame as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEVICE: Hello = Hello {
+        min_version: 1,
+        max_version: 2,
+        codecs: CODEC_CBOR | CODEC_POSTCARD,
+        flags: FULL_CRC,
+        max_frame: 64,
+    };
+    const PREFERENCE: [u8; 3] = [CODEC_CBOR, CODEC_POSTCARD, CODEC_JSON];
+
+    #[test]
+    fn agrees_on_the_best_common_settings() {
+        let offer = Hello {
+            min_version: 1,
+            max_version: 5,
+            codecs: CODEC_POSTCARD | CODEC_JSON,
+            flags: FULL_CRC | 1 << 4,
+            max_frame: 32,
+        };
+        let agreed = DEVICE.negotiate(&offer, &PREFERENCE).unwrap();
+        assert_eq!(
+            agreed,
+            Hello {
+                min_version: 2,
+                max_version: 2,
+                codecs: CODEC_POSTCARD,
+                flags: FULL_CRC | AGREED,
+                max_frame: 32,
+            }
+        );
+        assert!(agreed.is_agreed());
+        assert_eq!(agreed.chunk_len(), max_chunk_len(32));
+    }
+
+    #[test]
+    fn nothing_in_common() {
+        let old = Hello {
+            min_version: 3,
+            max_version: 3,
+            ..DEVICE
+        };
+        assert_eq!(DEVICE.negotiate(&old, &PREFERENCE), None);
+        let json_only = Hello {
+            codecs: CODEC_JSON,
+            ..DEVICE
+        };
+        assert_eq!(DEVICE.negotiate(&json_only, &PREFERENCE), None);
+        let tiny = Hello {
+            max_frame: MIN_FRAME_SIZE as u16 - 1,
+            ..DEVICE
+        };
+        assert_eq!(DEVICE.negotiate(&tiny, &PREFERENCE), None);
+    }
+
+    #[test]
+    fn host_cannot_claim_agreement() {
+        let offer = Hello {
+            flags: AGREED,
+            ..DEVICE
+        };
+        let agreed = DEVICE.negotiate(&offer, &PREFERENCE).unwrap();
+        assert_eq!(agreed.flags, AGREED);
+    }
+
+    #[test]
+    fn appended_fields_are_ignored() {
+        let mut bytes = DEVICE.to_bytes().to_vec();
+        assert_eq!(Hello::from_bytes(&bytes), Some(DEVICE));
+        bytes.extend([0xAB, 0xCD]);
+        assert_eq!(Hello::from_bytes(&bytes), Some(DEVICE));
+        assert_eq!(Hello::from_bytes(&bytes[..HELLO_LEN - 1]), None);
+    }
+}
@@ -0,0 +1,21 @@
+//! Wire protocol shared by the turret monitor firmware and its hosts.
+//!
+//! This crate is `no_std` and free of hardware dependencies, so everything in it can be unit
+//! tested on the host with a plain `cargo test`.
+//!
+//! The `vectors` feature adds golden wire-format test vectors, and the `schema` feature a
+//! machine-readable schema of the protocol. Both need std.
+#![cfg_attr(not(any(test, feature = "vectors", feature = "schema")), no_std)]
+
+pub mod address;
+pub mod crc;
+pub mod datamodel;
+pub mod deframer;
+pub mod envelope;
+pub mod fragment;
+pub mod hello;
+pub mod reliable;
+#[cfg(any(test, feature = "schema"))]
+pub mod schema;
+#[cfg(any(test, feature = "vectors"))]
+pub mod vectors;
@@ -0,0 +1,209 @@
+//! Optional reliable delivery, for requests that must be executed exactly once.
+//!
+//! A host sends such a request with a sequence number, and the device acknowledges it.
+//! If the acknowledgement doesn't arrive in time, the host sends the request again with the
+//! same sequence number, and the device acknowledges it again without executing it again.
+//!
+//! Delivery is stop-and-wait: a host sends the next reliable request only once the previous one
+//! was acknowledged or given up on, so the device only needs to remember the last one.
+//! Requests sent without a sequence number, and telemetry, are fire-and-forget.
+
+/// Whether a reliable request should be executed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Delivery {
+    /// First time this request was received, execute it.
+    New,
+    /// A retransmission of the last request, which was already executed.
+    Duplicate,
+}
+
+/// Detects retransmissions of the last reliable request, on the device.
+pub struct DuplicateFilter {
+    last: Option<u8>,
+}
+
+impl DuplicateFilter {
+    pub const fn new() -> Self {
+        Self { last: None }
+    }
+
+    /// Classifies a reliable request with sequence number `seq`.
+    pub fn check(&self, seq: u8) -> Delivery {
+        if self.last == Some(seq) {
+            Delivery::Duplicate
+        } else {
+            Delivery::New
+        }
+    }
+
+    /// Remembers that the request with sequence number `seq` was executed.
+    /// Only call this once it was, so a request that failed is executed when retransmitted.
+    pub fn record(&mut self, seq: u8) {
+        self.last = Some(seq);
+    }
+}
+
+impl Default for DuplicateFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// When a host retransmits an unacknowledged request, and when it gives up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetransmitPolicy {
+    /// How long to wait for an acknowledgement before retransmitting.
+    pub timeout_ms: u32,
+    /// How many times a request is sent in total, at least 1.
+    pub max_attempts: u8,
+}
+
+impl RetransmitPolicy {
+    /// Suits the default 115200 baud link, where a reply arrives within a few milliseconds.
+    pub const DEFAULT: Self = Self {
+        timeout_ms: 100,
+        max_attempts: 5,
+    };
+}
+
+impl Default for RetransmitPolicy {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// What a host should do about its outstanding request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    /// Nothing to do, or still waiting for the acknowledgement.
+    Wait,
+    /// Send the outstanding request again, with the same sequence number.
+    Retransmit(u8),
+    /// The request was sent `max_attempts` times without being acknowledged.
+    GiveUp(u8),
+}
+
+/// A host's side of reliable delivery. Time is passed in, in milliseconds from any epoch.
+pub struct Sender {
+    policy: RetransmitPolicy,
+    next_seq: u8,
+    /// the outstanding request's sequence number, attempts so far and when it was last sent.
+    outstanding: Option<(u8, u8, u64)>,
+}
+
+impl Sender {
+    pub const fn new(policy: RetransmitPolicy) -> Self {
+        Self {
+            policy,
+            next_seq: 0,
+            outstanding: None,
+        }
+    }
+
+    /// Whether a request is waiting for its acknowledgement.
+    pub fn is_busy(&self) -> bool {
+        self.outstanding.is_some()
+    }
+
+    /// Assigns a sequence number to a new request, sent at `now_ms`.
+    /// Returns `None` while the previous request is outstanding.
+    pub fn send(&mut self, now_ms: u64) -> Option<u8> {
+        if self.is_busy() {
+            return None;
+        }
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        self.outstanding = Some((seq, 1, now_ms));
+        Some(seq)
+    }
+
+    /// Handles an acknowledgement, returning whether it was for the outstanding request.
+    pub fn acknowledge(&mut self, seq: u8) -> bool {
+        match self.outstanding {
+            Some((outstanding, _, _)) if outstanding == seq => {
+                self.outstanding = None;
+                true
+            }
+            // a late acknowledgement of a request already given up on.
+            _ => false,
+        }
+    }
+
+    /// Decides whether the outstanding request needs sending again at `now_ms`.
+    pub fn poll(&mut self, now_ms: u64) -> Action {
+        let (seq, attempts, sent) = match self.outstanding {
+            Some(outstanding) => outstanding,
+            None => return Action::Wait,
+        };
+        if now_ms.saturating_sub(sent) < self.policy.timeout_ms as u64 {
+            Action::Wait
+        } else if attempts >= self.policy.max_attempts {
+            self.outstanding = None;
+            Action::GiveUp(seq)
+        } else {
+            self.outstanding = Some((seq, attempts + 1, now_ms));
+            Action::Retransmit(seq)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duplicates_are_detected() {
+        let mut filter = DuplicateFilter::new();
+        assert_eq!(filter.check(4), Delivery::New);
+        // not executed yet, e.g. it failed.
+        assert_eq!(filter.check(4), Delivery::New);
+        filter.record(4);
+        assert_eq!(filter.check(4), Delivery::Duplicate);
+        assert_eq!(filter.check(5), Delivery::New);
+        filter.record(5);
+        assert_eq!(filter.check(4), Delivery::New);
+    }
+
+    #[test]
+    fn acknowledged_request_frees_the_sender() {
+        let mut sender = Sender::new(RetransmitPolicy::DEFAULT);
+        let seq = sender.send(0).unwrap();
+        assert_eq!(sender.send(1), None);
+        assert!(!sender.acknowledge(seq.wrapping_add(1)));
+        assert!(sender.acknowledge(seq));
+        assert_eq!(sender.poll(1_000), Action::Wait);
+        assert_eq!(sender.send(1_000), Some(seq.wrapping_add(1)));
+    }
+
+    #[test]
+    fn retransmits_then_gives_up() {
+        let policy = RetransmitPolicy {
+            timeout_ms: 10,
+            max_attempts: 3,
+        };
+        let mut sender = Sender::new(policy);
+        let seq = sender.send(0).unwrap();
+        assert_eq!(sender.poll(9), Action::Wait);
+        assert_eq!(sender.poll(10), Action::Retransmit(seq));
+        assert_eq!(sender.poll(15), Action::Wait);
+        assert_eq!(sender.poll(20), Action::Retransmit(seq));
+        assert_eq!(sender.poll(30), Action::GiveUp(seq));
+        assert!(!sender.is_busy());
+        assert!(!sender.acknowledge(seq));
+    }
+
+    #[test]
+    fn retransmissions_reach_the_device_once() {
+        let mut sender = Sender::new(RetransmitPolicy::DEFAULT);
+        let mut filter = DuplicateFilter::new();
+        let seq = sender.send(0).unwrap();
+        assert_eq!(filter.check(seq), Delivery::New);
+        filter.record(seq);
+        // the acknowledgement was lost.
+        assert_eq!(sender.poll(100), Action::Retransmit(seq));
+        assert_eq!(filter.check(seq), Delivery::Duplicate);
+        assert!(sender.acknowledge(seq));
+        let next = sender.send(200).unwrap();
+        assert_eq!(filter.check(next), Delivery::New);
+    }
+}
@@ -0,0 +1,200 @@
+//! A machine-readable description of the protocol, generated from the types in this crate.
+//!
+//! The schema is checked in at `schema/protocol.json`, for client code generators and
+//! compatibility checks. It isn't generated by a build script, which can't use this crate's
+//! types, so instead this crate's tests fail whenever it is out of date with the types.
+//! Regenerate it with
+//! ```text
+//! TURRET_UPDATE_SCHEMA=1 cargo test
+//! ```
+//! It holds:
+//! - `protocol_version` and `min_protocol_version`, see [`envelope`](crate::envelope).
+//! - `codecs`, the [hello](crate::hello) bit of each payload encoding.
+//! - `message_types`, the envelope's type number of each message, and the definition its payload
+//!   is described by. A hello's payload is plain bytes rather than codec encoded, so it has none
+//!   and `hello` lists its fields instead.
+//! - `definitions`, a JSON Schema of each payload as serde represents it. Postcard encodes
+//!   fields by their position and variants by their index, so `x-field-order` lists each struct's
+//!   fields in order, and `x-variant-order` each enum's variants.
+//!
+//! Needs the `schema` feature, which pulls in std.
+
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::JsonSchema;
+use serde::de::value::{Error, U32Deserializer};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::address::{ADDRESS_LEN, BROADCAST, REPLY};
+use crate::datamodel::{
+    ack::AckPacket,
+    crash_record::{CrashKind, CrashRecordPacket},
+    error::{ErrorCode, ErrorPacket},
+    link_errors::LinkErrorsPacket,
+    log_level::{LogLevel, LogLevelPacket},
+    log_record::LogRecordPacket,
+    node_address::NodeAddress,
+    request::{Request, RequestKind},
+    reset_cause::{ResetCause, ResetCausePacket},
+    serial_config::{Parity, SerialConfigPacket, StopBits},
+    subscription::{Subscription, Topic},
+    telemetry_batch::TelemetryBatch,
+    telemetry_packet::{TurretDirection, TurretTelemetryPacket},
+};
+use crate::envelope::{MessageType, ENVELOPE_LEN, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use crate::hello::{CODEC_CBOR, CODEC_JSON, CODEC_POSTCARD, HELLO_LEN};
+
+/// The schema, as checked in.
+pub const PROTOCOL_JSON: &str = include_str!("../schema/protocol.json");
+
+/// Generates the schema from the types in this crate.
+pub fn export() -> Value {
+    let mut generator = SchemaSettings::draft07().into_generator();
+    let message_types: Vec<Value> = MESSAGE_TYPES
+        .iter()
+        .map(|&(message_type, name)| {
+            let payload = match message_type {
+                MessageType::Request => define::<Request>(&mut generator),
+                MessageType::Telemetry => define::<TurretTelemetryPacket>(&mut generator),
+                MessageType::ResetCause => define::<ResetCausePacket>(&mut generator),
+                MessageType::CrashRecord => define::<CrashRecordPacket>(&mut generator),
+                MessageType::LogLevel => define::<LogLevelPacket>(&mut generator),
+                MessageType::LinkErrors => define::<LinkErrorsPacket>(&mut generator),
+                MessageType::SerialConfig => define::<SerialConfigPacket>(&mut generator),
+                MessageType::Ack => define::<AckPacket>(&mut generator),
+                MessageType::Log => define::<LogRecordPacket>(&mut generator),
+                MessageType::Error => define::<ErrorPacket>(&mut generator),
+                MessageType::Hello => Value::Null,
+                MessageType::Subscription => define::<Subscription>(&mut generator),
+                MessageType::TelemetryBatch => define::<TelemetryBatch>(&mut generator),
+                MessageType::NodeAddress => define::<NodeAddress>(&mut generator),
+            };
+            json!({ "id": message_type as u8, "name": name, "payload": payload })
+        })
+        .collect();
+    let mut definitions = Map::new();
+    for (name, schema) in generator.take_definitions() {
+        // NOTE(unwrap): schemas only hold types JSON represents.
+        let mut definition = serde_json::to_value(&schema).unwrap();
+        if let Some(object) = schema.into_object().object {
+            let fields: Vec<&String> = object.properties.keys().collect();
+            definition["x-field-order"] = json!(fields);
+        }
+        definitions.insert(name, definition);
+    }
+    for (name, variants) in enum_variants() {
+        if let Some(definition) = definitions.get_mut(&name) {
+            definition["x-variant-order"] = variants;
+        }
+    }
+    json!({
+        "protocol_version": PROTOCOL_VERSION,
+        "min_protocol_version": MIN_PROTOCOL_VERSION,
+        "address": { "len": ADDRESS_LEN, "reply": REPLY, "broadcast": BROADCAST },
+        "envelope_len": ENVELOPE_LEN,
+        "codecs": { "cbor": CODEC_CBOR, "postcard": CODEC_POSTCARD, "json": CODEC_JSON },
+        "message_types": message_types,
+        "hello": {
+            "len": HELLO_LEN,
+            "fields": [
+                { "name": "min_version", "len": 1 },
+                { "name": "max_version", "len": 1 },
+                { "name": "codecs", "len": 1 },
+                { "name": "flags", "len": 1 },
+                { "name": "max_frame", "len": 2, "endian": "big" },
+            ],
+        },
+        "definitions": definitions,
+    })
+}
+
+/// Every message type, and the name the schema gives it.
+const MESSAGE_TYPES: [(MessageType, &str); 14] = [
+    (MessageType::Request, "Request"),
+    (MessageType::Telemetry, "Telemetry"),
+    (MessageType::ResetCause, "ResetCause"),
+    (MessageType::CrashRecord, "CrashRecord"),
+    (MessageType::LogLevel, "LogLevel"),
+    (MessageType::LinkErrors, "LinkErrors"),
+    (MessageType::SerialConfig, "SerialConfig"),
+    (MessageType::Ack, "Ack"),
+    (MessageType::Log, "Log"),
+    (MessageType::Error, "Error"),
+    (MessageType::Hello, "Hello"),
+    (MessageType::Subscription, "Subscription"),
+    (MessageType::TelemetryBatch, "TelemetryBatch"),
+    (MessageType::NodeAddress, "NodeAddress"),
+];
+
+/// Adds `T` to the definitions, returning the name it is defined as.
+fn define<T: JsonSchema>(generator: &mut SchemaGenerator) -> Value {
+    generator.subschema_for::<T>();
+    Value::String(T::schema_name())
+}
+
+/// The variants of every enum in the payloads, in order.
+fn enum_variants() -> [(String, Value); 9] {
+    [
+        variants::<CrashKind>(),
+        variants::<ErrorCode>(),
+        variants::<LogLevel>(),
+        variants::<RequestKind>(),
+        variants::<ResetCause>(),
+        variants::<Parity>(),
+        variants::<StopBits>(),
+        variants::<Topic>(),
+        variants::<TurretDirection>(),
+    ]
+}
+
+/// Lists the variants of a unit-only enum, by deserializing each index until one isn't a variant.
+fn variants<T: JsonSchema + Serialize + for<'de> Deserialize<'de>>() -> (String, Value) {
+    let names: Vec<Value> = (0..)
+        .map_while(|index| T::deserialize(U32Deserializer::<Error>::new(index)).ok())
+        // NOTE(unwrap): unit variants serialize as their name.
+        .map(|variant| serde_json::to_value(variant).unwrap())
+        .collect();
+    (T::schema_name(), Value::Array(names))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_message_type_is_described() {
+        let described: Vec<u8> = MESSAGE_TYPES.iter().map(|&(t, _)| t as u8).collect();
+        let known: Vec<u8> = (0..=u8::MAX)
+            .filter(|&id| MessageType::from_u8(id).is_some())
+            .collect();
+        assert_eq!(described, known);
+    }
+
+    #[test]
+    fn every_enum_lists_its_variants() {
+        let schema = export();
+        for (name, definition) in schema["definitions"].as_object().unwrap() {
+            if definition.get("oneOf").is_some() || definition.get("enum").is_some() {
+                assert!(definition.get("x-variant-order").is_some(), "{}", name);
+            }
+        }
+        let variants = &schema["definitions"]["CrashKind"]["x-variant-order"];
+        assert_eq!(variants, &json!(["None", "Panic", "HardFault"]));
+    }
+
+    #[test]
+    fn checked_in_schema_is_current() {
+        let schema = export();
+        if std::env::var_os("TURRET_UPDATE_SCHEMA").is_some() {
+            let path = concat!(env!("CARGO_MANIFEST_DIR"), "/schema/protocol.json");
+            let pretty = serde_json::to_string_pretty(&schema).unwrap();
+            std::fs::write(path, pretty + "\n").unwrap();
+            return;
+        }
+        let checked_in: Value = serde_json::from_str(PROTOCOL_JSON).unwrap();
+        assert!(
+            checked_in == schema,
+            "schema/protocol.json is out of date, regenerate it with TURRET_UPDATE_SCHEMA=1 cargo test"
+        );
+    }
+}
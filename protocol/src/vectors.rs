@@ -0,0 +1,278 @@
+//! Golden wire-format test vectors: messages, and the exact bytes they are sent as.
+//!
+//! The vectors live in `vectors/frames.json`, so implementations in any language can check their
+//! framing against them. Each one is the first message its sender sent, so its message id is 0:
+//! ```json
+//! {
+//!   "name": "telemetry reply, cbor",
+//!   "codec": "cbor",
+//!   "address": 129,
+//!   "frame_size": 64,
+//!   "full_crc": false,
+//!   "request_id": 1,
+//!   "type": "Telemetry",
+//!   "payload": { "turret_pos": 1234, "turret_rot": "Forward" },
+//!   "frame": "0f0101010101..."
+//! }
+//! ```
+//! `type` names a [`Message`] variant, and `payload` is the variant's contents as serde
+//! represents them in JSON. A hello's payload holds the [`Hello`] fields. `frame` is every byte
+//! on the wire in hex, sentinels included, and holds several frames if the message was fragmented.
+//!
+//! Needs the `vectors` feature, which pulls in std.
+
+use serde::de::{self, Deserializer};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::address::REPLY;
+use crate::datamodel::{
+    ack::AckPacket, crash_record::CrashRecordPacket, error::ErrorPacket,
+    link_errors::LinkErrorsPacket, log_level::LogLevelPacket, log_record::LogRecordPacket,
+    node_address::NodeAddress, request::Request, reset_cause::ResetCausePacket,
+    serial_config::SerialConfigPacket, subscription::Subscription, telemetry_batch::TelemetryBatch,
+    telemetry_packet::TurretTelemetryPacket,
+};
+use crate::envelope::Message;
+use crate::hello::{Hello, CODEC_CBOR, CODEC_JSON, CODEC_POSTCARD};
+
+/// The vectors file, as checked in.
+pub const FRAMES_JSON: &str = include_str!("../vectors/frames.json");
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Vector {
+    pub name: String,
+    /// The [hello bit](crate::hello) of the codec the payload is encoded with.
+    #[serde(deserialize_with = "codec_bit")]
+    pub codec: u8,
+    /// The address byte every frame carries, whose [`REPLY`](crate::address::REPLY) bit tells
+    /// whether the device sent it.
+    pub address: u8,
+    /// Size of the frames on the wire, which decides how the message is fragmented.
+    pub frame_size: usize,
+    /// Whether the CRCs cover the last partial word of each frame.
+    pub full_crc: bool,
+    pub request_id: Option<u8>,
+    #[serde(rename = "type")]
+    pub message_type: String,
+    pub payload: Value,
+    #[serde(deserialize_with = "hex")]
+    pub frame: Vec<u8>,
+}
+
+/// Every vector in [`FRAMES_JSON`].
+pub fn all() -> Vec<Vector> {
+    // NOTE(unwrap): the file is checked in, and its tests parse it.
+    serde_json::from_str(FRAMES_JSON).unwrap()
+}
+
+impl Vector {
+    /// The message the vector's frames carry.
+    /// Panics if the vector's `type` or `payload` are invalid, as its tests check they aren't.
+    pub fn message(&self) -> Message<'_> {
+        fn parse<'a, T: Deserialize<'a>>(payload: &'a Value) -> T {
+            T::deserialize(payload).unwrap()
+        }
+        let payload = &self.payload;
+        match self.message_type.as_str() {
+            "Request" => Message::Request(parse::<Request>(payload)),
+            "Telemetry" => Message::Telemetry(parse::<TurretTelemetryPacket>(payload)),
+            "ResetCause" => Message::ResetCause(parse::<ResetCausePacket>(payload)),
+            "CrashRecord" => Message::CrashRecord(parse::<CrashRecordPacket>(payload)),
+            "LogLevel" => Message::LogLevel(parse::<LogLevelPacket>(payload)),
+            "LinkErrors" => Message::LinkErrors(parse::<LinkErrorsPacket>(payload)),
+            "SerialConfig" => Message::SerialConfig(parse::<SerialConfigPacket>(payload)),
+            "Ack" => Message::Ack(parse::<AckPacket>(payload)),
+            "Log" => Message::Log(parse::<LogRecordPacket>(payload)),
+            "Error" => Message::Error(parse::<ErrorPacket>(payload)),
+            "Hello" => Message::Hello(parse::<HelloFields>(payload).into()),
+            "Subscription" => Message::Subscription(parse::<Subscription>(payload)),
+            "TelemetryBatch" => Message::TelemetryBatch(parse::<TelemetryBatch>(payload)),
+            "NodeAddress" => Message::NodeAddress(parse::<NodeAddress>(payload)),
+            other => panic!("vector {:?} has unknown type {:?}", self.name, other),
+        }
+    }
+
+    /// Whether a device sent the vector, rather than a host.
+    pub fn from_device(&self) -> bool {
+        self.address & REPLY != 0
+    }
+
+    /// Whether `message` is the vector's message.
+    pub fn matches(&self, message: &Message) -> bool {
+        let expected = self.message();
+        expected.message_type() == message.message_type() && payload(message) == self.payload
+    }
+}
+
+/// A message's contents as the vectors hold them.
+pub fn payload(message: &Message) -> Value {
+    fn value<T: Serialize>(payload: &T) -> Value {
+        // NOTE(unwrap): the datamodel only holds types JSON represents.
+        serde_json::to_value(payload).unwrap()
+    }
+    match message {
+        Message::Request(m) => value(m),
+        Message::Telemetry(m) => value(m),
+        Message::ResetCause(m) => value(m),
+        Message::CrashRecord(m) => value(m),
+        Message::LogLevel(m) => value(m),
+        Message::LinkErrors(m) => value(m),
+        Message::SerialConfig(m) => value(m),
+        Message::Ack(m) => value(m),
+        Message::Log(m) => value(m),
+        Message::Error(m) => value(m),
+        Message::Hello(hello) => value(&HelloFields::from(*hello)),
+        Message::Subscription(m) => value(m),
+        Message::TelemetryBatch(m) => value(m),
+        Message::NodeAddress(m) => value(m),
+        Message::Unknown(_) => Value::Null,
+    }
+}
+
+/// A [`Hello`]'s fields, which aren't codec encoded on the wire so it doesn't derive serde.
+#[derive(Serialize, Deserialize)]
+struct HelloFields {
+    min_version: u8,
+    max_version: u8,
+    codecs: u8,
+    flags: u8,
+    max_frame: u16,
+}
+
+impl From<HelloFields> for Hello {
+    fn from(fields: HelloFields) -> Self {
+        Self {
+            min_version: fields.min_version,
+            max_version: fields.max_version,
+            codecs: fields.codecs,
+            flags: fields.flags,
+            max_frame: fields.max_frame,
+        }
+    }
+}
+
+impl From<Hello> for HelloFields {
+    fn from(hello: Hello) -> Self {
+        Self {
+            min_version: hello.min_version,
+            max_version: hello.max_version,
+            codecs: hello.codecs,
+            flags: hello.flags,
+            max_frame: hello.max_frame,
+        }
+    }
+}
+
+fn codec_bit<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u8, D::Error> {
+    match String::deserialize(deserializer)?.as_str() {
+        "cbor" => Ok(CODEC_CBOR),
+        "postcard" => Ok(CODEC_POSTCARD),
+        "json" => Ok(CODEC_JSON),
+        other => Err(de::Error::custom(format_args!("unknown codec {:?}", other))),
+    }
+}
+
+fn hex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let hex = String::deserialize(deserializer)?;
+    if hex.len() % 2 != 0 {
+        return Err(de::Error::custom("odd number of hex digits"));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| de::Error::custom("invalid hex digit"))
+        })
+        .collect()
+}
+
+/// Formats `bytes` as the vectors hold them, for printing the frames of new vectors.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crc::crc32;
+    use crate::deframer::Deframer;
+    use crate::envelope::tests::{Cbor, Json, Postcard};
+    use crate::envelope::{decode, EnvelopeHeader, WireCodec};
+    use crate::fragment::{FragmentHeader, Reassembler, CRC_LEN};
+
+    fn decode_with<C: WireCodec>(codec: &C, vector: &Vector, message: &mut [u8]) -> bool
+    where
+        C::Error: core::fmt::Debug,
+    {
+        let (header, decoded) = decode(codec, message).unwrap();
+        assert_eq!(header.request_id, vector.request_id, "{}", vector.name);
+        vector.matches(&decoded)
+    }
+
+    /// Takes the frames apart by hand, so the vectors don't only agree with the code under test.
+    #[test]
+    fn frames_decode_to_their_messages() {
+        let vectors = all();
+        assert!(!vectors.is_empty());
+        for vector in &vectors {
+            let mut deframer = Deframer::<1024>::new();
+            let mut reassembler = Reassembler::<1024>::new();
+            let mut message = None;
+            for &byte in &vector.frame {
+                let frame = match deframer.push(byte) {
+                    Some(frame) => frame.unwrap(),
+                    None => continue,
+                };
+                assert!(frame.len() + 2 <= vector.frame_size, "{}", vector.name);
+                let (data, crc) = frame.split_at_mut(frame.len() - CRC_LEN);
+                assert_eq!(
+                    crc32(data, vector.full_crc).to_be_bytes(),
+                    crc,
+                    "{}",
+                    vector.name
+                );
+                assert_eq!(data[0], vector.address, "{}", vector.name);
+                let (header, chunk) = FragmentHeader::split(&data[1..]).unwrap();
+                assert_eq!(header.message_id, 0, "{}", vector.name);
+                if let Some(complete) = reassembler.push(header, chunk).unwrap() {
+                    message = Some(complete.to_vec());
+                }
+            }
+            let mut message = message.expect(&vector.name);
+            let (header, _) = EnvelopeHeader::split(&mut message).unwrap();
+            assert_eq!(header.version, crate::envelope::PROTOCOL_VERSION);
+            let matches = match vector.codec {
+                CODEC_CBOR => decode_with(&Cbor, vector, &mut message),
+                CODEC_POSTCARD => decode_with(&Postcard, vector, &mut message),
+                _ => decode_with(&Json, vector, &mut message),
+            };
+            assert!(matches, "{}", vector.name);
+        }
+    }
+
+    #[test]
+    fn names_are_unique() {
+        let vectors = all();
+        for (i, vector) in vectors.iter().enumerate() {
+            assert!(
+                vectors[..i].iter().all(|other| other.name != vector.name),
+                "{}",
+                vector.name
+            );
+        }
+    }
+
+    #[test]
+    fn hex_round_trips() {
+        let bytes = [0x00, 0x0f, 0xa1, 0xff];
+        let json = format!("{:?}", to_hex(&bytes));
+        let parsed = hex(&mut serde_json::Deserializer::from_str(&json)).unwrap();
+        assert_eq!(parsed, bytes);
+    }
+}
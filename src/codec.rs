@@ -0,0 +1,41 @@
+//! Payload encodings.
+//!
+//! Each codec is compiled in by its cargo feature:
+//! - `codec-cbor` (default), self-describing but spends a lot of the frame on field names.
+//! - `codec-postcard`, compact, suited for the rover.
+//! - `codec-json`, human-readable, suited for the bench.
+//!
+//! Frames use the [`active`] codec, which is the first enabled one in the order above until a
+//! host picks another enabled one with a hello, see `turret_protocol::hello`.
+//!
+//! The codecs themselves are `turret_device::codec`'s, which the fuzz targets decode with too.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+pub use turret_device::codec::{Codec, PREFERENCE};
+
+#[cfg(not(any(feature = "codec-cbor", feature = "codec-postcard", feature = "codec-json")))]
+compile_error!("at least one of the `codec-*` features must be enabled.");
+
+#[cfg(feature = "codec-cbor")]
+const DEFAULT_CODEC: Codec = Codec::Cbor;
+#[cfg(all(not(feature = "codec-cbor"), feature = "codec-postcard"))]
+const DEFAULT_CODEC: Codec = Codec::Postcard;
+#[cfg(all(not(feature = "codec-cbor"), not(feature = "codec-postcard")))]
+const DEFAULT_CODEC: Codec = Codec::Json;
+
+static ACTIVE: AtomicU8 = AtomicU8::new(DEFAULT_CODEC as u8);
+
+/// The codec payloads are encoded with.
+pub fn active() -> Codec {
+    match ACTIVE.load(Ordering::Relaxed) {
+        0 => Codec::Cbor,
+        1 => Codec::Postcard,
+        _ => Codec::Json,
+    }
+}
+
+/// Switches the codec payloads are encoded with, which must be enabled.
+pub fn set_active(codec: Codec) {
+    ACTIVE.store(codec as u8, Ordering::Relaxed);
+}
@@ -0,0 +1,26 @@
+//! Build-time configuration, read from environment variables when the firmware is compiled.
+//!
+//! For example `TURRET_BUF_SIZE=128 cargo build` doubles the frame size.
+
+/// Parses `value` as a decimal `usize`, or returns `default` if the variable is unset.
+/// Fails the build if the variable is set to anything but digits.
+pub(crate) const fn usize_from_env(value: Option<&str>, default: usize) -> usize {
+    let digits = match value {
+        Some(value) => value.as_bytes(),
+        None => return default,
+    };
+    if digits.is_empty() {
+        panic!("build configuration variable is set but empty.");
+    }
+    let mut parsed: usize = 0;
+    let mut i = 0;
+    while i < digits.len() {
+        let digit = digits[i];
+        if !digit.is_ascii_digit() {
+            panic!("build configuration variable must be a decimal number.");
+        }
+        parsed = parsed * 10 + (digit - b'0') as usize;
+        i += 1;
+    }
+    parsed
+}
@@ -0,0 +1,195 @@
+//! Crash records that survive a reset.
+//!
+//! Panics and hard faults write a [`CrashRecord`] into the `.uninit` RAM section, which the
+//! runtime leaves alone during start-up, and then reset the device.
+//! On the next boot `init` takes the record so the host can request it.
+
+use core::fmt::Write;
+use core::mem::MaybeUninit;
+use core::panic::PanicInfo;
+use core::ptr;
+
+use cortex_m::peripheral::SCB;
+use cortex_m_rt::{exception, ExceptionFrame};
+
+use crate::datamodel::crash_record::{CrashKind, CrashRecordPacket};
+use crate::logging::Truncating;
+
+/// Marks the record as written by a crash handler, rather than left-over RAM contents.
+const CRASH_MAGIC: u32 = 0xDEAD_C0DE;
+const KIND_PANIC: u32 = 1;
+const KIND_HARD_FAULT: u32 = 2;
+/// Longest source path kept, the tail of the path is the interesting part.
+const FILE_LEN: usize = 32;
+const MESSAGE_LEN: usize = 64;
+
+/// Everything we know about a crash, plain old data so it can live in uninitialized RAM.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CrashRecord {
+    magic: u32,
+    kind: u32,
+    line: u32,
+    pc: u32,
+    lr: u32,
+    cfsr: u32,
+    hfsr: u32,
+    mmfar: u32,
+    bfar: u32,
+    file_len: u32,
+    file: [u8; FILE_LEN],
+    message_len: u32,
+    message: [u8; MESSAGE_LEN],
+}
+
+#[link_section = ".uninit.CRASH_RECORD"]
+static mut CRASH_RECORD: MaybeUninit<CrashRecord> = MaybeUninit::uninit();
+
+impl CrashRecord {
+    const fn empty(kind: u32) -> Self {
+        Self {
+            magic: CRASH_MAGIC,
+            kind,
+            line: 0,
+            pc: 0,
+            lr: 0,
+            cfsr: 0,
+            hfsr: 0,
+            mmfar: 0,
+            bfar: 0,
+            file_len: 0,
+            file: [0; FILE_LEN],
+            message_len: 0,
+            message: [0; MESSAGE_LEN],
+        }
+    }
+
+    pub fn kind(&self) -> CrashKind {
+        match self.kind {
+            KIND_PANIC => CrashKind::Panic,
+            KIND_HARD_FAULT => CrashKind::HardFault,
+            _ => CrashKind::None,
+        }
+    }
+
+    pub fn file(&self) -> &str {
+        as_str(&self.file, self.file_len)
+    }
+
+    pub fn message(&self) -> &str {
+        as_str(&self.message, self.message_len)
+    }
+
+    /// Summarizes this record into a reply.
+    pub fn as_packet(&self) -> CrashRecordPacket<'_> {
+        CrashRecordPacket {
+            kind: self.kind(),
+            file: self.file(),
+            line: self.line,
+            pc: self.pc,
+            lr: self.lr,
+            cfsr: self.cfsr,
+            hfsr: self.hfsr,
+            mmfar: self.mmfar,
+            bfar: self.bfar,
+            msg: self.message(),
+        }
+    }
+
+    /// Dumps the whole record to the log.
+    pub fn log(&self) {
+        warn!(
+            "recovered {:?} record: {}:{} {:?}",
+            self.kind(),
+            self.file(),
+            self.line,
+            self.message()
+        );
+        warn!(
+            "pc := {:#010x}, lr := {:#010x}, cfsr := {:#010x}, hfsr := {:#010x}, mmfar := {:#010x}, bfar := {:#010x}",
+            self.pc,
+            self.lr,
+            self.cfsr,
+            self.hfsr,
+            self.mmfar,
+            self.bfar
+        );
+    }
+}
+
+/// Interprets the first `len` bytes as UTF-8, discarding anything that isn't.
+fn as_str(buffer: &[u8], len: u32) -> &str {
+    let buffer = &buffer[..(len as usize).min(buffer.len())];
+    match core::str::from_utf8(buffer) {
+        Ok(s) => s,
+        // NOTE(unwrap): everything up to `valid_up_to` was just validated.
+        Err(e) => core::str::from_utf8(&buffer[..e.valid_up_to()]).unwrap(),
+    }
+}
+
+/// Takes the record left by the last crash, if there was one.
+/// The record is cleared, so it is only reported for the reset that immediately followed it.
+pub fn take() -> Option<CrashRecord> {
+    // SAFETY: only called from init, before any crash handler could run concurrently.
+    //   The record is plain old data, so whatever garbage is in RAM is a valid value, and the
+    //   magic tells us whether it was actually written by a crash handler.
+    unsafe {
+        let record = ptr::addr_of_mut!(CRASH_RECORD) as *mut CrashRecord;
+        if ptr::read_volatile(ptr::addr_of!((*record).magic)) != CRASH_MAGIC {
+            return None;
+        }
+        let taken = ptr::read_volatile(record);
+        ptr::write_volatile(ptr::addr_of_mut!((*record).magic), 0);
+        Some(taken)
+    }
+}
+
+/// Writes the record to uninitialized RAM and resets the device.
+fn store_and_reset(record: CrashRecord) -> ! {
+    // SAFETY: interrupts are disabled by the callers and we never return, so nothing else can
+    //   observe the record while it is being written.
+    unsafe { ptr::write_volatile(ptr::addr_of_mut!(CRASH_RECORD) as *mut CrashRecord, record) };
+    SCB::sys_reset()
+}
+
+#[inline(never)]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+
+    let mut record = CrashRecord::empty(KIND_PANIC);
+    if let Some(location) = info.location() {
+        record.line = location.line();
+        // keep the tail of the path, skipping any partial leading character.
+        let file = location.file().as_bytes();
+        let mut start = file.len().saturating_sub(FILE_LEN);
+        while start < file.len() && file[start] & 0xC0 == 0x80 {
+            start += 1;
+        }
+        let file = &file[start..];
+        record.file[..file.len()].copy_from_slice(file);
+        record.file_len = file.len() as u32;
+    }
+    let mut message = Truncating::new(&mut record.message);
+    let _ = write!(message, "{}", info.message());
+    record.message_len = message.len() as u32;
+
+    // best effort, useful when a probe happens to be attached.
+    error!("{}", info);
+    store_and_reset(record)
+}
+
+#[exception]
+unsafe fn HardFault(frame: &ExceptionFrame) -> ! {
+    cortex_m::interrupt::disable();
+
+    let scb = &*SCB::ptr();
+    let mut record = CrashRecord::empty(KIND_HARD_FAULT);
+    record.pc = frame.pc();
+    record.lr = frame.lr();
+    record.cfsr = scb.cfsr.read();
+    record.hfsr = scb.hfsr.read();
+    record.mmfar = scb.mmfar.read();
+    record.bfar = scb.bfar.read();
+    store_and_reset(record)
+}
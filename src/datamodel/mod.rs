@@ -1,3 +1,7 @@
-pub mod request;
+//! The payloads live in the protocol crate, so hosts decode them with the same types.
+pub use turret_protocol::datamodel::{
+    ack, crash_record, error, link_errors, log_level, log_record, node_address, request,
+    reset_cause, serial_config, subscription, telemetry_batch, telemetry_packet,
+};
 pub mod rx_errors;
-pub mod telemetry_packet;
+pub mod tx_errors;
@@ -1,12 +1,2 @@
-#[derive(Debug)]
-pub enum RxError {
-    CobsDecoderNeededMoreBytes,
-    CobsDecoderError(usize),
-    CobsDecoderPushFailed,
-    InvalidSenderCrc,
-    FailedTelemetrySpawn,
-    FailedDeserialize,
-    BufferOverflow,
-    // DmaReconfigFailed,
-    // DmaTransferFailed,
-}
+//! Shared with the simulator, which runs the same receive path.
+pub use turret_device::rx::RxError;
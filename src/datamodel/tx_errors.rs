@@ -0,0 +1,2 @@
+//! Shared with the simulator, which runs the same transmit path.
+pub use turret_device::tx::TxError;
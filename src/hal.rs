@@ -0,0 +1,53 @@
+//! The STM32's peripherals, behind the traits `turret_device`'s task logic is written against.
+
+use core::convert::TryFrom;
+
+use rtic::time::duration::Milliseconds;
+use stm32f4xx_hal::hal::Direction;
+use stm32f4xx_hal::{crc32::Crc32, prelude::*};
+use turret_device::hal::{Clock, Crc, Encoder};
+
+use crate::app::{monotonics, QeiMonitor};
+use crate::datamodel::telemetry_packet::TurretDirection;
+
+/// The CRC32 peripheral.
+pub struct HwCrc<'a>(pub &'a mut Crc32);
+
+impl Crc for HwCrc<'_> {
+    fn reset(&mut self) {
+        self.0.init();
+    }
+
+    fn update(&mut self, word: u32) -> u32 {
+        self.0.update(&[word])
+    }
+}
+
+/// TIM5 in encoder mode, counting the turret's quadrature signals.
+pub struct Quadrature<'a>(pub &'a QeiMonitor);
+
+impl Encoder for Quadrature<'_> {
+    fn count(&self) -> u32 {
+        self.0.count() as u32
+    }
+
+    fn direction(&self) -> TurretDirection {
+        match self.0.direction() {
+            Direction::Downcounting => TurretDirection::Backward,
+            Direction::Upcounting => TurretDirection::Forward,
+        }
+    }
+}
+
+/// RTIC's monotonic.
+pub struct Monotonic;
+
+impl Clock for Monotonic {
+    fn now_ms(&self) -> u32 {
+        // the monotonic reads as zero until init has returned. It counts in 64 bits, truncating
+        // wraps around every ~49.7 days as callers expect, rather than saturating.
+        Milliseconds::<u64>::try_from(monotonics::now().duration_since_epoch())
+            .map(|ms| ms.0 as u32)
+            .unwrap_or(0)
+    }
+}
@@ -0,0 +1,165 @@
+//! Level-filtered logging facade.
+//!
+//! The backend is selected by cargo feature:
+//! - `log-rtt` (default) prints over RTT via `rtt-target`.
+//! - `log-defmt` hands the formatted message to `defmt`.
+//! - with neither enabled, every log statement compiles out.
+//!
+//! Independently, the `log-uart` feature forwards warnings and errors to the host as log frames,
+//! see [`forward_logs`](crate::tasks::forward_logs).
+//!
+//! Statements above the runtime level, see [`set_level`], are skipped before any formatting
+//! happens, so disabled buffer dumps only cost an atomic load.
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU8, Ordering};
+
+pub use crate::datamodel::log_level::LogLevel;
+// the global defmt logger.
+#[cfg(feature = "log-defmt")]
+use defmt_rtt as _;
+
+#[cfg(all(feature = "log-rtt", feature = "log-defmt"))]
+compile_error!("features `log-rtt` and `log-defmt` are mutually exclusive.");
+
+#[cfg(debug_assertions)]
+const DEFAULT_LEVEL: LogLevel = LogLevel::Debug;
+#[cfg(not(debug_assertions))]
+const DEFAULT_LEVEL: LogLevel = LogLevel::Info;
+
+static LEVEL: AtomicU8 = AtomicU8::new(DEFAULT_LEVEL as u8);
+
+/// Sets up the selected backend, must be called before anything is logged.
+pub fn init() {
+    #[cfg(feature = "log-rtt")]
+    rtt_target::rtt_init_print!(NoBlockSkip, 2048);
+}
+
+pub fn level() -> LogLevel {
+    match LEVEL.load(Ordering::Relaxed) {
+        0 => LogLevel::Off,
+        1 => LogLevel::Error,
+        2 => LogLevel::Warn,
+        3 => LogLevel::Info,
+        4 => LogLevel::Debug,
+        _ => LogLevel::Trace,
+    }
+}
+
+pub fn set_level(level: LogLevel) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+#[inline]
+#[cfg(any(feature = "log-rtt", feature = "log-defmt", feature = "log-uart"))]
+pub fn enabled(level: LogLevel) -> bool {
+    level != LogLevel::Off && level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+/// Hands a statement that passed the level filter to every enabled sink.
+#[doc(hidden)]
+#[cfg(any(feature = "log-rtt", feature = "log-defmt", feature = "log-uart"))]
+pub fn write(level: LogLevel, args: fmt::Arguments) {
+    #[cfg(feature = "log-rtt")]
+    {
+        let tag = match level {
+            LogLevel::Off => "",
+            LogLevel::Error => "[ERROR] ",
+            LogLevel::Warn => "[WARNING] ",
+            LogLevel::Info => "[INFO] ",
+            LogLevel::Debug => "[DEBUG] ",
+            LogLevel::Trace => "[TRACE] ",
+        };
+        rtt_target::rprintln!("{}{}", tag, args);
+    }
+
+    #[cfg(feature = "log-defmt")]
+    {
+        // our call sites use `core::fmt` syntax, so format here and hand defmt the finished string.
+        let mut message: heapless::String<128> = heapless::String::new();
+        // a message that doesn't fit is truncated, which is preferable to dropping it.
+        let _ = message.write_fmt(args);
+        match level {
+            LogLevel::Off => {}
+            LogLevel::Error => defmt::error!("{=str}", message.as_str()),
+            LogLevel::Warn => defmt::warn!("{=str}", message.as_str()),
+            LogLevel::Info => defmt::info!("{=str}", message.as_str()),
+            LogLevel::Debug => defmt::debug!("{=str}", message.as_str()),
+            LogLevel::Trace => defmt::trace!("{=str}", message.as_str()),
+        }
+    }
+
+    #[cfg(feature = "log-uart")]
+    crate::tasks::enqueue_log(level, args);
+}
+
+/// `fmt::Write` into a fixed buffer, silently dropping whatever doesn't fit.
+/// Only whole characters are written, so the buffer always holds valid UTF-8.
+pub(crate) struct Truncating<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Truncating<'a> {
+    pub(crate) fn new(buffer: &'a mut [u8]) -> Self {
+        Self { buffer, len: 0 }
+    }
+
+    /// Number of bytes written so far.
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+}
+
+impl Write for Truncating<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let len = c.len_utf8();
+            if self.len + len > self.buffer.len() {
+                break;
+            }
+            c.encode_utf8(&mut self.buffer[self.len..self.len + len]);
+            self.len += len;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(any(feature = "log-rtt", feature = "log-defmt", feature = "log-uart"))]
+macro_rules! log {
+    ($level:expr, $($arg:tt)+) => {
+        if $crate::logging::enabled($level) {
+            $crate::logging::write($level, core::format_args!($($arg)+));
+        }
+    };
+}
+
+#[cfg(not(any(feature = "log-rtt", feature = "log-defmt", feature = "log-uart")))]
+macro_rules! log {
+    ($level:expr, $($arg:tt)+) => {
+        // type-check the statement, but never evaluate it.
+        if false {
+            let _ = ($level, core::format_args!($($arg)+));
+        }
+    };
+}
+
+macro_rules! error {
+    ($($arg:tt)+) => { log!($crate::logging::LogLevel::Error, $($arg)+) };
+}
+
+macro_rules! warn {
+    ($($arg:tt)+) => { log!($crate::logging::LogLevel::Warn, $($arg)+) };
+}
+
+macro_rules! info {
+    ($($arg:tt)+) => { log!($crate::logging::LogLevel::Info, $($arg)+) };
+}
+
+macro_rules! debug {
+    ($($arg:tt)+) => { log!($crate::logging::LogLevel::Debug, $($arg)+) };
+}
+
+macro_rules! trace {
+    ($($arg:tt)+) => { log!($crate::logging::LogLevel::Trace, $($arg)+) };
+}
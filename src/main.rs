@@ -2,8 +2,22 @@
 #![no_std]
 #![allow(unused_imports)]
 
-use panic_rtt_target as _panic_handler;
-
+/// level-filtered logging macros, must come first so the other modules can use them
+#[macro_use]
+mod logging;
+/// payload encodings, selected by cargo feature
+mod codec;
+/// sizes configured when the firmware is built
+mod config;
+/// panic and hard fault handlers, which preserve a crash record across the following reset
+mod crash;
+/// RS-485 transceiver control
+#[cfg(feature = "rs485")]
+mod rs485;
+/// the peripherals behind the task logic's hardware traits
+mod hal;
+/// settings kept across resets
+mod settings;
 mod datamodel;
 /// submodule holding task handlers
 mod tasks;
@@ -27,8 +41,6 @@ mod app {
 
     use cortex_m::singleton;
     use dwt_systick_monotonic::DwtSystick;
-    use rtic::time::duration::Seconds;
-    use rtt_target::{rprintln, rtt_init_print};
     use stm32f4xx_hal::{
         crc32::Crc32,
         dma::{
@@ -45,14 +57,34 @@ mod app {
         pwm_input::PwmInput,
         rcc::Rcc,
         serial,
-        stm32::{DMA2, TIM4, TIM5, TIM8, USART1, TIM1},
-        timer::Timer,
+        stm32::{DMA2, TIM2, TIM4, TIM5, TIM8, USART1, TIM1},
+        timer::{CountDownTimer, Event, Timer},
+        watchdog::IndependentWatchdog,
     };
     use stm32f4xx_hal::qei::Qei;
 
-    use crate::tasks::{on_usart1_idle, on_usart1_rx_dma, on_usart1_txe, write_telemetry};
-    use crate::tasks::TxBufferState;
+    use crate::crash::CrashRecord;
+    use crate::datamodel::ack::AckPacket;
+    use crate::datamodel::error::ErrorCode;
+    use crate::datamodel::link_errors::LinkErrorsPacket;
+    use crate::datamodel::reset_cause::ResetCause;
+    use crate::datamodel::serial_config::SerialConfig;
+    use crate::datamodel::subscription::Subscription;
+    use crate::tasks::{
+        apply_serial_config, feed_watchdog, forward_logs, on_usart1_idle, on_usart1_rx_dma, on_usart1_txe, process_rx,
+        publish_topics, reboot, sample_turret, serial_fallback, tx_timeout, write_ack, write_crash_record, write_error,
+        write_hello, write_link_errors, write_log_level, write_reboot, write_reset_cause, write_serial_config,
+        write_node_address, write_subscription, write_telemetry, write_telemetry_batch,
+    };
+    use crate::tasks::{
+        Liveness, RxRing, RxState, SerialLink, TxBuffer, TxBufferState, TxQueue, Usart1Receiver,
+        WATCHDOG_TICK_MS, WATCHDOG_TIMEOUT_MS,
+    };
+    use rtic::time::duration::Milliseconds;
     use stm32f4xx_hal::gpio::gpioa::PA0;
+    use turret_device::batch::Batcher;
+    use turret_device::schedule::Scheduler;
+    use turret_protocol::hello::Hello;
 
     /*
         Monotonic config
@@ -67,25 +99,46 @@ mod app {
      */
     /// PWM input monitor type
     pub(crate) type QeiMonitor = Qei<TIM5, (PA0<Alternate<2>>, PA1<Alternate<2>>)>;
+    /// Timer pacing the turret's samples
+    pub(crate) type Sampler = CountDownTimer<TIM2>;
     /// Serial connection type
     pub(crate) type Usart1Tx = serial::Tx<USART1>;
     pub(crate) type Usart1Rx = serial::Rx<USART1>;
     /*
     USART DMA definitions
      */
-    /// Size of USART1's DMA buffer
-    pub(crate) const BUF_SIZE: usize = 64;
+    // ANCHOR: buf_size
+    /// Size of USART1's DMA buffer, the largest frame on the wire.
+    /// Set with the `TURRET_BUF_SIZE` environment variable at build time.
+    pub(crate) const BUF_SIZE: usize =
+        crate::config::usize_from_env(option_env!("TURRET_BUF_SIZE"), 64);
     /// Maximum message size for messages on USART1.
     pub(crate) const MESSAGE_SIZE: usize = BUF_SIZE - 1;
+    /// Largest logical message, which is split across as many frames as it takes.
+    /// Set with the `TURRET_MAX_MESSAGE_SIZE` environment variable at build time.
+    pub(crate) const MAX_MESSAGE_SIZE: usize =
+        crate::config::usize_from_env(option_env!("TURRET_MAX_MESSAGE_SIZE"), 256);
+    // ANCHOR_END: buf_size
+    /// This board's address on the bus until a host moves it, see `turret_protocol::address`.
+    /// Set with the `TURRET_NODE_ADDRESS` environment variable at build time.
+    pub(crate) const NODE_ADDRESS: u8 = NODE_ADDRESS_RAW as u8;
+    const NODE_ADDRESS_RAW: usize =
+        crate::config::usize_from_env(option_env!("TURRET_NODE_ADDRESS"), 1);
+    const _: () = assert!(
+        NODE_ADDRESS_RAW < turret_protocol::address::BROADCAST as usize,
+        "TURRET_NODE_ADDRESS must be below the broadcast address, 127."
+    );
 
     /// USART1's DMA buffer type
     pub(crate) type Usart1Buf = &'static mut [u8; BUF_SIZE];
+    /// USART1's TX DMA buffer type, only the encoded frame at its front is sent.
+    pub(crate) type Usart1TxBuf = &'static mut TxBuffer;
 
     /// Serial TX DMA type
     pub(crate) type Usart1TransferTx =
-    Transfer<Stream7<DMA2>, Usart1Tx, MemoryToPeripheral, Usart1Buf, 4>;
+    Transfer<Stream7<DMA2>, Usart1Tx, MemoryToPeripheral, Usart1TxBuf, 4>;
 
-    /// Serial RX DMA type
+    /// Serial RX DMA type, runs in double-buffer mode so it never has to stop.
     pub(crate) type Usart1TransferRx =
     Transfer<Stream2<DMA2>, Usart1Rx, PeripheralToMemory, Usart1Buf, 4>;
 
@@ -97,14 +150,35 @@ mod app {
 
         #[lock_free]
         send: Option<TxBufferState>,
+        /// encoded frames waiting for the TX DMA
+        #[lock_free]
+        tx_queue: TxQueue,
         crc: Crc32,
         recv: Usart1TransferRx,
+        rx_state: RxState,
+        /// bytes received over USART1 waiting to be processed
+        rx_ring: RxRing,
+        /// critical task check-ins since the watchdog was last fed
+        liveness: Liveness,
+        /// USART1 and DMA errors seen since boot
+        link_errors: LinkErrorsPacket,
+        /// USART1's line settings
+        serial: SerialLink,
+        /// the topics the host subscribed to, and when each is due
+        subscriptions: Scheduler,
+        /// the turret's encoder, read on request and by the sampler
+        monitor: QeiMonitor,
+        /// samples waiting to be sent in a batch
+        batcher: Batcher,
     }
 
     /* resources local to specific RTIC tasks */
     #[local]
     struct Local {
-        monitor: QeiMonitor,
+        sampler: Sampler,
+        watchdog: IndependentWatchdog,
+        reset_cause: ResetCause,
+        crash_record: Option<CrashRecord>,
     }
 
     /*
@@ -114,8 +188,9 @@ mod app {
 
     #[init(
     local = [
-    tx_buf: [u8; BUF_SIZE] = [0; BUF_SIZE],
+    tx_buf: TxBuffer = TxBuffer::new(),
     rx_buf: [u8; BUF_SIZE] = [0; BUF_SIZE],
+    rx_double_buf: [u8; BUF_SIZE] = [0; BUF_SIZE],
     ]
     )]
     fn init(ctx: init::Context) -> (Shared, Local, init::Monotonics) {
@@ -132,9 +207,31 @@ mod app {
             w.dbg_standby().set_bit();
             w.dbg_stop().set_bit()
         });
-        // Enable RTT logging;
-        rtt_init_print!(NoBlockSkip, 2048);
-        rprintln!("hello, world!");
+        // Enable logging;
+        crate::logging::init();
+        info!("hello, world!");
+
+        // the settings live in the backup domain, whose clock is enabled through the RCC.
+        crate::settings::init(&ctx.device.RCC, &ctx.device.PWR);
+        // resume at the log level the host last saved.
+        if let Some(level) = crate::settings::load_log_level() {
+            crate::logging::set_level(level);
+            info!("log level := {:?}", level);
+        }
+        // and at the address the host last moved us to.
+        if let Some(address) = crate::settings::load_node_address() {
+            crate::tasks::set_node_address(address);
+            info!("node address := {}", address);
+        }
+
+        // the reset flags live in the RCC, so they have to be read before it is constrained.
+        let reset_cause = crate::tasks::read_reset_cause(&ctx.device.RCC);
+        info!("last reset cause := {:?}", reset_cause);
+        // if the last reset was caused by a crash, hold on to its record for the host.
+        let crash_record = crate::crash::take();
+        if let Some(record) = &crash_record {
+            record.log();
+        }
 
         // retrieve the RCC register, which is needed to obtain a handle to the clocks
         let rcc: Rcc = ctx.device.RCC.constrain();
@@ -166,6 +263,11 @@ mod app {
 
         let monitor = Qei::new(ctx.device.TIM5, (gpioa.pa0.into_alternate(), gpioa.pa1.into_alternate()));
 
+        // TIM2 paces the turret's samples, its interrupt fires once per sample.
+        let mut sampler = Timer::new(ctx.device.TIM2, &clocks)
+            .start_count_down(crate::tasks::SAMPLE_RATE_HZ.hz());
+        sampler.listen(Event::TimeOut);
+
 
         /*
         begin USART1 config
@@ -174,13 +276,20 @@ mod app {
         // This is the primary interface to this driver.
         let usart1_tx_pin = gpioa.pa9.into_alternate();
         let usart1_rx_pin = gpioa.pa10.into_alternate();
-        let usart1_config = serial::config::Config {
-            baudrate: 115200.bps(),
-            wordlength: serial::config::WordLength::DataBits8,
-            parity: serial::config::Parity::ParityNone,
-            stopbits: serial::config::StopBits::STOP1,
-            dma: serial::config::DmaConfig::TxRx,
-        };
+        // the RS-485 transceiver's driver enable, released until we transmit.
+        #[cfg(feature = "rs485")]
+        {
+            let mut driver_enable = gpioa.pa12.into_push_pull_output();
+            driver_enable.set_low();
+        }
+        // resume at the settings the host last confirmed, if they still work with our clocks.
+        let pclk2 = clocks.pclk2().0;
+        let serial_config = crate::settings::load_serial_config()
+            .filter(|config| crate::tasks::supports(config, pclk2))
+            .unwrap_or(SerialConfig::DEFAULT);
+        let serial = SerialLink::new(serial_config, pclk2);
+        info!("USART1 settings := {:?}", serial_config);
+        let usart1_config = crate::tasks::hal_config(&serial_config);
         let (usart1_tx, usart1_rx) = serial::Serial::new(
             ctx.device.USART1,
             (usart1_tx_pin, usart1_rx_pin),
@@ -198,9 +307,11 @@ mod app {
             .memory_increment(true);
 
         let usart1_dma_rx_config = DmaConfig::default()
-            // these should never fire in a well-formed packet.
-            .transfer_complete_interrupt(false)
+            // fires whenever one of the buffers is full and the DMA switched to the other.
+            .transfer_complete_interrupt(true)
             .half_transfer_interrupt(false)
+            // keep receiving into the other buffer while we drain a full one.
+            .double_buffer(true)
             // enable the interrupt when something goes horribly wrong.
             .fifo_error_interrupt(true)
             .transfer_error_interrupt(true)
@@ -218,15 +329,15 @@ mod app {
             dma2_streams.2,
             usart1_rx,
             ctx.local.rx_buf,
-            None,
+            Some(ctx.local.rx_double_buf),
             usart1_dma_rx_config,
         );
         unsafe {
-            crate::tasks::enable_idle_interrupt();
+            crate::tasks::enable_usart1_interrupts();
         }
 
         usart1_dma_transfer_rx.start(|_rx| {
-            rprintln!("started RX DMA.");
+            info!("started RX DMA.");
         });
         /*
         End USART1 configuration.
@@ -235,17 +346,45 @@ mod app {
         // set up the CRC32 (ethernet) peripheral
         let crc = Crc32::new(ctx.device.CRC);
 
-        // kick off the periodic task.
-        write_telemetry::spawn_after(Seconds(1u32)).expect("failed to kick off periodic task.");
+        /* start the independent watchdog */
+        // don't let the watchdog reset the device while a debugger has it halted.
+        let mut watchdog = IndependentWatchdog::new(ctx.device.IWDG);
+        watchdog.stop_on_debug(&ctx.device.DBGMCU, true);
+        watchdog.start(WATCHDOG_TIMEOUT_MS.ms());
+        feed_watchdog::spawn_after(Milliseconds(WATCHDOG_TICK_MS))
+            .expect("failed to kick off watchdog supervisor.");
+
+        // start forwarding warnings and errors to the host.
+        #[cfg(feature = "log-uart")]
+        forward_logs::spawn_after(Milliseconds(crate::tasks::LOG_FRAME_PERIOD_MS))
+            .expect("failed to kick off log forwarder.");
+
+        // start streaming whatever the host subscribes to.
+        publish_topics::spawn_after(Milliseconds(crate::tasks::PUBLISH_TICK_MS))
+            .expect("failed to kick off topic publisher.");
         // lastly return the shared and local resources, as per RTIC's spec.
         (
             Shared {
                 last_observed_turret_position: 0.0,
                 send: Some(TxBufferState::Idle(usart1_dma_transfer_tx)),
+                tx_queue: TxQueue::new(),
                 crc,
                 recv: usart1_dma_transfer_rx,
+                rx_state: RxState::new(),
+                rx_ring: RxRing::new(),
+                liveness: Liveness::new(),
+                link_errors: LinkErrorsPacket::default(),
+                serial,
+                subscriptions: Scheduler::new(),
+                monitor,
+                batcher: Batcher::new(),
+            },
+            Local {
+                sampler,
+                watchdog,
+                reset_cause,
+                crash_record,
             },
-            Local { monitor },
             init::Monotonics(mono),
         )
     }
@@ -254,31 +393,152 @@ mod app {
     // RTIC docs specify we can modularize the code by using these `extern` blocks.
     // This allows us to specify the tasks in other modules and still work within
     // RTIC's infrastructure.
+    // ANCHOR: extern_tasks
     extern "Rust" {
-        // periodic UART telemetry output task
+        // UART telemetry output task, replying to requests or streaming
         #[task(
-        shared = [last_observed_turret_position, send, crc],
-        local = [monitor]
+        shared = [last_observed_turret_position, send, tx_queue, crc, monitor],
+        capacity = 2
         )]
-        fn write_telemetry(context: write_telemetry::Context);
+        fn write_telemetry(context: write_telemetry::Context, request_id: Option<u8>);
+        // ANCHOR_END: extern_tasks
+
+        // reply to a reset cause request
+        #[task(
+        shared = [send, tx_queue, crc],
+        local = [reset_cause]
+        )]
+        fn write_reset_cause(context: write_reset_cause::Context, request_id: Option<u8>);
+
+        // reply to a crash record request
+        #[task(
+        shared = [send, tx_queue, crc],
+        local = [crash_record]
+        )]
+        fn write_crash_record(context: write_crash_record::Context, request_id: Option<u8>);
+
+        // reply to a reboot request, then reboot
+        #[task(shared = [send, tx_queue, crc])]
+        fn write_reboot(context: write_reboot::Context, request_id: Option<u8>);
+
+        // resets the device once the TX queue drained
+        #[task(shared = [send, tx_queue])]
+        fn reboot(context: reboot::Context);
+
+        // reply to a log level request
+        #[task(shared = [send, tx_queue, crc])]
+        fn write_log_level(context: write_log_level::Context, request_id: Option<u8>);
+
+        // acknowledge a reliable request
+        #[task(shared = [send, tx_queue, crc], capacity = 2)]
+        fn write_ack(context: write_ack::Context, request_id: Option<u8>, ack: AckPacket);
+
+        // reply that a request couldn't be handled
+        #[task(shared = [send, tx_queue, crc], capacity = 2)]
+        fn write_error(context: write_error::Context, request_id: Option<u8>, code: ErrorCode);
+
+        // negotiate the protocol version and capabilities with the host
+        #[task(shared = [send, tx_queue, crc, subscriptions, batcher])]
+        fn write_hello(context: write_hello::Context, request_id: Option<u8>, offer: Hello);
+
+        // reply to a link errors request, or stream the counters
+        #[task(shared = [send, tx_queue, crc, link_errors], capacity = 2)]
+        fn write_link_errors(context: write_link_errors::Context, request_id: Option<u8>);
+
+        // reply to a subscription, starting, changing or stopping a topic
+        #[task(shared = [send, tx_queue, crc, serial, subscriptions, batcher])]
+        fn write_subscription(
+            context: write_subscription::Context,
+            request_id: Option<u8>,
+            requested: Subscription,
+        );
+
+        // reply with this board's address, possibly moving it
+        #[task(shared = [send, tx_queue, crc])]
+        fn write_node_address(
+            context: write_node_address::Context,
+            request_id: Option<u8>,
+            requested: Option<u8>,
+        );
+
+        // periodic subscribed topic output task
+        #[task(shared = [subscriptions])]
+        fn publish_topics(context: publish_topics::Context);
+
+        // samples the turret's position at 1 kHz, above every other task
+        #[task(
+        binds = TIM2,
+        priority = 2,
+        shared = [monitor, batcher],
+        local = [sampler]
+        )]
+        fn sample_turret(context: sample_turret::Context);
+
+        // sends the samples taken since the last batch
+        #[task(shared = [send, tx_queue, crc, batcher])]
+        fn write_telemetry_batch(context: write_telemetry_batch::Context);
+
+        // periodic log frame output task
+        #[task(shared = [send, tx_queue, crc])]
+        fn forward_logs(context: forward_logs::Context);
+
+        // periodic IWDG supervisor
+        #[task(
+        shared = [send, rx_ring, liveness],
+        local = [watchdog]
+        )]
+        fn feed_watchdog(context: feed_watchdog::Context);
 
         // when USART1 is done sending data
         #[task(
         binds = DMA2_STREAM7,
-        shared = [send]
+        shared = [send, tx_queue, liveness]
         )]
         fn on_usart1_txe(context: on_usart1_txe::Context);
 
+        // reply to a serial config request, possibly starting a switch
+        #[task(shared = [send, tx_queue, crc, serial])]
+        fn write_serial_config(
+            context: write_serial_config::Context,
+            request_id: Option<u8>,
+            requested: Option<SerialConfig>,
+        );
+
+        // switches USART1's settings once the TX queue drained
+        #[task(shared = [send, tx_queue, serial, recv, rx_state, rx_ring])]
+        fn apply_serial_config(context: apply_serial_config::Context);
+
+        // keeps or reverts USART1's trial settings
+        #[task(shared = [serial])]
+        fn serial_fallback(context: serial_fallback::Context);
+
+        // aborts a TX transfer whose completion interrupt never came
+        #[task(
+        shared = [send, tx_queue, link_errors],
+        capacity = 16
+        )]
+        fn tx_timeout(context: tx_timeout::Context, transfer: u32);
+
         #[task(
         binds = DMA2_STREAM2,
-        shared = [crc, recv],
+        shared = [recv, rx_state, rx_ring, link_errors],
         )]
-        // when USART1 is done receiving data
+        // when one of USART1's RX buffers is full, or the RX DMA failed
         fn on_usart1_rx_dma(context: on_usart1_rx_dma::Context);
+        // when USART1's RX line falls idle, or a received byte was corrupted
         #[task(
         binds = USART1,
-        shared = [recv, crc]
+        shared = [recv, rx_state, rx_ring, link_errors]
         )]
         fn on_usart1_idle(context: on_usart1_idle::Context);
+
+        // splits the received bytes into frames and handles them
+        #[task(
+        shared = [rx_ring, crc, liveness],
+        local = [
+        receiver: Usart1Receiver = Usart1Receiver::new(),
+        ]
+        )]
+        fn process_rx(context: process_rx::Context);
     }
 }
@@ -0,0 +1,47 @@
+//! RS-485 half-duplex support, for sharing one bus between several boards.
+//!
+//! The transceiver's driver enable (DE) pin is asserted before each transmission, and released
+//! once the USART has shifted out the last stop bit. Releasing it when the DMA completes would cut
+//! off the last byte, since the DMA completes as soon as it hands that byte to the USART.
+
+use stm32f4xx_hal::stm32::{GPIOA, USART1};
+
+/// Asserts DE, before a transfer starts.
+pub fn claim_bus() {
+    // SAFETY: BSRR writes are atomic, and the read/modify/write cycles only touch TX state,
+    //   which belongs to whoever is starting a transfer.
+    unsafe {
+        let usart = &*USART1::ptr();
+        // a transmission is about to start, don't release the bus when the previous one ends.
+        usart.cr1.modify(|_, w| w.tcie().clear_bit());
+        usart.sr.modify(|_, w| w.tc().clear_bit());
+        (*GPIOA::ptr()).bsrr.write(|w| w.bs12().set_bit());
+    }
+}
+
+/// Arranges for DE to be released once the last byte handed to the USART has been sent.
+pub fn release_when_sent() {
+    // SAFETY: read/modify/write cycle, see `claim_bus`.
+    unsafe { (*USART1::ptr()).cr1.modify(|_, w| w.tcie().set_bit()) };
+}
+
+/// Releases DE straight away, cutting off whatever is being sent.
+pub fn release_bus() {
+    // SAFETY: see `claim_bus`.
+    unsafe {
+        (*USART1::ptr()).cr1.modify(|_, w| w.tcie().clear_bit());
+        (*GPIOA::ptr()).bsrr.write(|w| w.br12().set_bit());
+    }
+}
+
+/// Releases DE if the transmission it was held for completed.
+/// Called from the USART1 interrupt, returns whether that was what it fired for.
+pub fn on_transmission_complete() -> bool {
+    // NOTE(safety): atomic reads with no side effects.
+    let armed = unsafe { (*USART1::ptr()).cr1.read().tcie().bit_is_set() };
+    let complete = unsafe { (*USART1::ptr()).sr.read().tc().bit_is_set() };
+    if armed && complete {
+        release_bus();
+    }
+    armed && complete
+}
@@ -0,0 +1,137 @@
+//! Settings kept across resets in the RTC backup registers.
+//!
+//! The backup domain survives every reset but a power cycle, or even that when `VBAT` is
+//! battery-backed. Unlike flash, writing it doesn't stall the CPU.
+
+use stm32f4xx_hal::stm32::{PWR, RCC, RTC};
+
+use crate::datamodel::log_level::LogLevel;
+use crate::datamodel::serial_config::{Parity, SerialConfig, StopBits};
+
+/// Marks the serial settings as written by this firmware, rather than left-over contents.
+const SERIAL_MAGIC: u32 = 0x5E71_A100;
+/// Marks the log level as written by this firmware, in the bits above the level itself.
+const LOG_LEVEL_MAGIC: u32 = 0x5E71_B000;
+/// Marks the node address as written by this firmware, in the bits above the address itself.
+const NODE_ADDRESS_MAGIC: u32 = 0x5E71_C000;
+/// Offset of `RTC_BKP0R` from the RTC's base address.
+const BKP0R_OFFSET: usize = 0x50;
+
+/// Backup register layout.
+#[repr(usize)]
+#[derive(Clone, Copy)]
+enum Register {
+    SerialMagic = 0,
+    SerialBaud = 1,
+    SerialFraming = 2,
+    SerialCheck = 3,
+    LogLevel = 4,
+    NodeAddress = 5,
+}
+
+/// Enables write access to the backup domain. Must be called before the RCC is constrained.
+pub fn init(rcc: &RCC, pwr: &PWR) {
+    rcc.apb1enr.modify(|_, w| w.pwren().set_bit());
+    pwr.cr.modify(|_, w| w.dbp().set_bit());
+}
+
+/// Returns the serial settings last stored, if any survived.
+pub fn load_serial_config() -> Option<SerialConfig> {
+    if read(Register::SerialMagic) != SERIAL_MAGIC {
+        return None;
+    }
+    let baud = read(Register::SerialBaud);
+    let framing = read(Register::SerialFraming);
+    if read(Register::SerialCheck) != check(baud, framing) {
+        return None;
+    }
+    let parity = match framing & 0xFF {
+        0 => Parity::None,
+        1 => Parity::Even,
+        2 => Parity::Odd,
+        _ => return None,
+    };
+    let stop_bits = match framing >> 8 {
+        1 => StopBits::One,
+        2 => StopBits::Two,
+        _ => return None,
+    };
+    Some(SerialConfig {
+        baud,
+        parity,
+        stop_bits,
+    })
+}
+
+pub fn store_serial_config(config: &SerialConfig) {
+    let parity = match config.parity {
+        Parity::None => 0,
+        Parity::Even => 1,
+        Parity::Odd => 2,
+    };
+    let stop_bits = match config.stop_bits {
+        StopBits::One => 1,
+        StopBits::Two => 2,
+    };
+    let framing = parity | stop_bits << 8;
+    write(Register::SerialBaud, config.baud);
+    write(Register::SerialFraming, framing);
+    write(Register::SerialCheck, check(config.baud, framing));
+    write(Register::SerialMagic, SERIAL_MAGIC);
+}
+
+/// Returns the log level last saved by the host, if any survived.
+pub fn load_log_level() -> Option<LogLevel> {
+    let value = read(Register::LogLevel);
+    if value & !0xFF != LOG_LEVEL_MAGIC {
+        return None;
+    }
+    Some(match value & 0xFF {
+        0 => LogLevel::Off,
+        1 => LogLevel::Error,
+        2 => LogLevel::Warn,
+        3 => LogLevel::Info,
+        4 => LogLevel::Debug,
+        5 => LogLevel::Trace,
+        _ => return None,
+    })
+}
+
+pub fn store_log_level(level: LogLevel) {
+    // a single word, so a reset can't land halfway through.
+    write(Register::LogLevel, LOG_LEVEL_MAGIC | level as u32);
+}
+
+/// Returns the node address a host last moved this board to, if any survived.
+pub fn load_node_address() -> Option<u8> {
+    let value = read(Register::NodeAddress);
+    if value & !0xFF != NODE_ADDRESS_MAGIC {
+        return None;
+    }
+    Some(value as u8).filter(|&address| turret_protocol::address::is_node(address))
+}
+
+pub fn store_node_address(address: u8) {
+    // a single word, like the log level.
+    write(Register::NodeAddress, NODE_ADDRESS_MAGIC | address as u32);
+}
+
+/// Guards against a reset landing halfway through a store.
+fn check(baud: u32, framing: u32) -> u32 {
+    !(baud ^ framing.rotate_left(16) ^ SERIAL_MAGIC)
+}
+
+fn read(register: Register) -> u32 {
+    // SAFETY: volatile read of a backup register, which has no side effects.
+    unsafe { core::ptr::read_volatile(address(register)) }
+}
+
+fn write(register: Register, value: u32) {
+    // SAFETY: volatile write of a backup register, which only this module uses.
+    unsafe { core::ptr::write_volatile(address(register), value) }
+}
+
+/// The backup registers are a plain array of words, so address them directly.
+fn address(register: Register) -> *mut u32 {
+    (RTC::ptr() as usize + BKP0R_OFFSET + 4 * register as usize) as *mut u32
+}
@@ -0,0 +1,88 @@
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use heapless::mpmc::Q8;
+use rtic::mutex_prelude::*;
+use rtic::time::duration::Milliseconds;
+use stm32f4xx_hal::crc32::Crc32;
+
+use crate::app::forward_logs;
+use crate::datamodel::log_level::LogLevel;
+use crate::datamodel::log_record::LogRecordPacket;
+use crate::hal::Monotonic;
+use crate::logging::Truncating;
+use crate::tasks::usart1_tx::{transmit, Priority};
+use turret_device::hal::Clock;
+use turret_protocol::envelope::Message;
+
+/// Minimum time between two log frames, bounding the share of the link spent on logs.
+pub(crate) const LOG_FRAME_PERIOD_MS: u32 = 100;
+/// Longest message kept, the rest is truncated.
+const LOG_MESSAGE_LEN: usize = 64;
+
+#[derive(Clone, Copy)]
+struct LogRecord {
+    level: LogLevel,
+    timestamp: u32,
+    len: u8,
+    message: [u8; LOG_MESSAGE_LEN],
+}
+
+/// Records waiting to be forwarded. Lock-free, since statements can be logged from any context.
+static QUEUE: Q8<LogRecord> = Q8::new();
+/// Records lost since the last log frame was sent.
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+/// Queues a warning or error for the host, anything less severe is ignored.
+#[cfg(feature = "log-uart")]
+pub(crate) fn enqueue_log(level: LogLevel, args: fmt::Arguments) {
+    if level == LogLevel::Off || level > LogLevel::Warn {
+        return;
+    }
+    let timestamp = Monotonic.now_ms();
+    let mut message = [0u8; LOG_MESSAGE_LEN];
+    let mut writer = Truncating::new(&mut message);
+    let _ = writer.write_fmt(args);
+    let len = writer.len() as u8;
+
+    let record = LogRecord {
+        level,
+        timestamp,
+        len,
+        message,
+    };
+    if QUEUE.enqueue(record).is_err() {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Periodically sends at most one queued record as a log frame.
+///
+/// Log frames have the lowest TX priority, so they never hold back a reply or telemetry.
+pub(crate) fn forward_logs(mut ctx: forward_logs::Context) {
+    if let Some(record) = QUEUE.dequeue() {
+        // NOTE(unwrap): the message was written one whole character at a time.
+        let message = core::str::from_utf8(&record.message[..record.len as usize]).unwrap();
+        let payload = LogRecordPacket {
+            level: record.level,
+            ts: record.timestamp,
+            dropped: DROPPED.swap(0, Ordering::Relaxed),
+            msg: message,
+        };
+        let send = ctx.shared.send;
+        let queue = ctx.shared.tx_queue;
+        let result = ctx
+            .shared
+            .crc
+            .lock(|crc: &mut Crc32| {
+                transmit(&Message::Log(payload), None, Priority::Log, send, queue, crc)
+            });
+        // deliberately not logged, that would only queue another record.
+        if result.is_err() {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // rescheduling can't fail, this task is the only one that spawns itself.
+    let _ = forward_logs::spawn_after(Milliseconds(LOG_FRAME_PERIOD_MS));
+}
@@ -6,19 +6,80 @@
    private interface
 */
 
+/// Task forwarding warnings and errors to the host as log frames.
+mod forward_logs;
 mod usart1_rx;
 mod usart1_tx;
 
-/// Task handling periodicly emitting current telemetry observations to the UART.
-/// Note: this task requires a monotonic clock with at least 1s resolution.
+/// Tasks rebooting the device at the host's request.
+mod reboot;
+
+/// Tasks sampling the turret at 1 kHz, and sending the samples in batches.
+mod sample_turret;
+
+/// Tasks switching USART1's line settings at the host's request.
+mod serial_config;
+
+/// Tasks streaming the topics the host subscribed to, each at its own rate.
+mod subscriptions;
+
+/// Task supervising critical task liveness and feeding the IWDG.
+mod watchdog;
+
+/// Task acknowledging reliable requests.
+mod write_ack;
+
+/// Task replying that a request couldn't be handled.
+mod write_error;
+
+/// Task replying with the crash record preserved across the last reset.
+mod write_crash_record;
+
+/// Task negotiating the protocol version and capabilities with the host.
+mod write_hello;
+
+/// Task replying with the USART1 and DMA error counters.
+mod write_link_errors;
+
+/// Task replying with the runtime log level.
+mod write_log_level;
+
+/// Task reporting this board's address on the bus, or moving it at the host's request.
+mod write_node_address;
+
+/// Task emitting current telemetry observations to the UART, on request or when subscribed to.
 mod write_telemetry;
 
 /*
     public(crate) interface
 */
+#[cfg(feature = "log-uart")]
+pub(crate) use forward_logs::enqueue_log;
+pub(crate) use forward_logs::{forward_logs, LOG_FRAME_PERIOD_MS};
 pub(crate) use usart1_rx::{
-    clear_idle_interrupt, enable_idle_interrupt, on_usart1_idle, on_usart1_rx_dma,
+    clear_idle_interrupt, enable_usart1_interrupts, on_usart1_idle, on_usart1_rx_dma, process_rx,
+    RxRing, Usart1Receiver,
+};
+pub use usart1_rx::RxState;
+pub(crate) use reboot::{reboot, write_reboot};
+pub(crate) use sample_turret::{sample_turret, write_telemetry_batch, SAMPLE_RATE_HZ};
+pub(crate) use serial_config::{
+    apply_serial_config, hal_config, serial_fallback, supports, write_serial_config,
+};
+pub use serial_config::SerialLink;
+pub(crate) use subscriptions::{publish_topics, write_subscription, PUBLISH_TICK_MS};
+pub(crate) use usart1_tx::{on_usart1_txe, set_node_address, tx_timeout};
+pub use usart1_tx::{Priority, TxBuffer, TxQueue};
+pub(crate) use watchdog::{
+    feed_watchdog, read_reset_cause, write_reset_cause, WATCHDOG_TICK_MS, WATCHDOG_TIMEOUT_MS,
 };
-pub(crate) use usart1_tx::on_usart1_txe;
-pub(crate) use write_telemetry::write_telemetry;
+pub use watchdog::Liveness;
+pub(crate) use write_ack::write_ack;
+pub(crate) use write_crash_record::write_crash_record;
+pub(crate) use write_error::write_error;
+pub(crate) use write_hello::write_hello;
+pub(crate) use write_link_errors::write_link_errors;
+pub(crate) use write_log_level::write_log_level;
+pub(crate) use write_node_address::write_node_address;
+pub(crate) use write_telemetry::{write_telemetry, zero_turret_position};
 pub use write_telemetry::TxBufferState;
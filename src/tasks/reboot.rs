@@ -0,0 +1,51 @@
+use rtic::mutex_prelude::*;
+use rtic::time::duration::Milliseconds;
+use stm32f4xx_hal::stm32::USART1;
+use stm32f4xx_hal::crc32::Crc32;
+
+use crate::app::{reboot, write_reboot};
+use crate::datamodel::reset_cause::{ResetCause, ResetCausePacket};
+use crate::tasks::usart1_tx::{transmit, Priority, TxQueue};
+use turret_protocol::envelope::Message;
+use crate::tasks::TxBufferState;
+
+/// How often a pending reboot checks whether its reply has left the wire.
+const DRAIN_POLL_MS: u32 = 1;
+
+/// Replies with the reset cause the host will read back afterwards, then reboots.
+pub(crate) fn write_reboot(mut ctx: write_reboot::Context, request_id: Option<u8>) {
+    let payload = ResetCausePacket {
+        cause: ResetCause::Software,
+    };
+    let send = ctx.shared.send;
+    let queue = ctx.shared.tx_queue;
+    let result = ctx
+        .shared
+        .crc
+        .lock(|crc: &mut Crc32| {
+            transmit(&Message::ResetCause(payload), request_id, Priority::Reply, send, queue, crc)
+        });
+    if let Err(e) = result {
+        // rebooting anyway, the host can still tell from the reset cause.
+        error!("failed to transmit reboot reply {:?}", e);
+    }
+    if let Err(e) = reboot::spawn() {
+        error!("failed to spawn reboot {:?}", e);
+    }
+}
+
+/// Resets the device, once everything queued has left the wire.
+pub(crate) fn reboot(ctx: reboot::Context) {
+    let queue: &mut TxQueue = ctx.shared.tx_queue;
+    // NOTE(safety): atomic read with no side effects.
+    let transmitting = unsafe { (*USART1::ptr()).sr.read().tc().bit_is_clear() };
+    let drained = matches!(ctx.shared.send, Some(TxBufferState::Idle(_)))
+        && queue.is_empty()
+        && !transmitting;
+    if !drained {
+        let _ = reboot::spawn_after(Milliseconds(DRAIN_POLL_MS));
+        return;
+    }
+    warn!("rebooting at the host's request.");
+    cortex_m::peripheral::SCB::sys_reset();
+}
@@ -0,0 +1,55 @@
+use rtic::mutex_prelude::*;
+use stm32f4xx_hal::crc32::Crc32;
+use stm32f4xx_hal::timer::Event;
+
+use crate::app::{sample_turret, write_telemetry_batch, QeiMonitor};
+use crate::hal::{Monotonic, Quadrature};
+use crate::tasks::usart1_tx::{transmit, Priority};
+use turret_device::batch::{Batcher, SAMPLE_PERIOD_US};
+use turret_device::hal::{Clock, Encoder};
+use turret_protocol::envelope::Message;
+
+/// How often TIM2 fires, one sample each time.
+pub(crate) const SAMPLE_RATE_HZ: u32 = 1_000_000 / SAMPLE_PERIOD_US;
+
+/// Samples the turret's position, on every tick of TIM2.
+///
+/// Runs above every other task, so samples are taken on time. The batcher ignores them unless
+/// the host subscribed to batched telemetry.
+pub(crate) fn sample_turret(mut ctx: sample_turret::Context) {
+    ctx.local.sampler.clear_interrupt(Event::TimeOut);
+    let now = Monotonic.now_ms();
+    let pos = ctx
+        .shared
+        .monitor
+        .lock(|monitor: &mut QeiMonitor| Quadrature(monitor).count());
+    ctx.shared
+        .batcher
+        .lock(|batcher: &mut Batcher| batcher.push(now, pos));
+}
+
+/// Sends the samples taken since the last batch, if there are any.
+///
+/// Batches are sent at telemetry priority. Samples the batcher had no room for are counted in
+/// the next batch rather than logged, which would happen on every batch on a slow link.
+pub(crate) fn write_telemetry_batch(mut ctx: write_telemetry_batch::Context) {
+    let batch = match ctx
+        .shared
+        .batcher
+        .lock(|batcher: &mut Batcher| batcher.take())
+    {
+        Some(batch) => batch,
+        None => return,
+    };
+
+    let send = ctx.shared.send;
+    let queue = ctx.shared.tx_queue;
+    let message = Message::TelemetryBatch(batch);
+    let result = ctx
+        .shared
+        .crc
+        .lock(|crc: &mut Crc32| transmit(&message, None, Priority::Telemetry, send, queue, crc));
+    if let Err(e) = result {
+        error!("failed to transmit telemetry batch {:?}", e);
+    }
+}
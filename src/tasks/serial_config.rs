@@ -0,0 +1,246 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use rtic::mutex_prelude::*;
+use rtic::time::duration::Milliseconds;
+use stm32f4xx_hal::serial;
+use stm32f4xx_hal::stm32::USART1;
+use stm32f4xx_hal::{crc32::Crc32, prelude::*};
+
+use crate::app::{apply_serial_config, serial_fallback, write_serial_config, Usart1TransferRx};
+use crate::datamodel::serial_config::{Parity, SerialConfig, SerialConfigPacket, StopBits};
+use crate::tasks::usart1_rx::{discard_received, RxRing, RxState};
+use crate::tasks::usart1_tx::{transmit, Priority, TxQueue};
+use turret_protocol::envelope::Message;
+use crate::tasks::TxBufferState;
+
+/// How long the host has to send a valid frame at the new settings before the device falls back.
+pub(crate) const SERIAL_FALLBACK_MS: u32 = 2_000;
+/// How often a pending switch checks whether the acknowledgement has left the wire.
+const DRAIN_POLL_MS: u32 = 1;
+/// Baud rates the host may pick from.
+const SUPPORTED_BAUDS: [u32; 7] = [9_600, 19_200, 38_400, 57_600, 115_200, 230_400, 460_800];
+
+/// Set once a frame passes its CRC check, so the fallback knows the new settings work.
+static VALID_FRAME: AtomicBool = AtomicBool::new(false);
+
+/// A change of settings waiting for the TX queue to drain.
+#[derive(Debug, Clone, Copy)]
+enum Switch {
+    /// Switch to new settings, falling back unless the host confirms them.
+    Trial(SerialConfig),
+    /// Go back to the settings that worked.
+    Revert(SerialConfig),
+}
+
+/// USART1's line settings, and the state of a switch between them.
+pub struct SerialLink {
+    active: SerialConfig,
+    pending: Option<Switch>,
+    /// the settings to fall back to, while the active ones are on trial.
+    fallback: Option<SerialConfig>,
+    /// USART1's kernel clock, needed to compute the baud rate divisor.
+    pclk2: u32,
+}
+
+impl SerialLink {
+    pub const fn new(active: SerialConfig, pclk2: u32) -> Self {
+        Self {
+            active,
+            pending: None,
+            fallback: None,
+            pclk2,
+        }
+    }
+
+    /// The settings in use.
+    pub fn active(&self) -> SerialConfig {
+        self.active
+    }
+}
+
+/// Whether USART1 can run at `config`, given its kernel clock.
+pub(crate) fn supports(config: &SerialConfig, pclk2: u32) -> bool {
+    // oversampling by 16 needs a divisor of at least 16.
+    SUPPORTED_BAUDS.contains(&config.baud) && pclk2 / config.baud >= 16
+}
+
+/// Notes that a frame passed its CRC check.
+pub(crate) fn note_valid_frame() {
+    VALID_FRAME.store(true, Ordering::Relaxed);
+}
+
+/// Forgets the frames that passed their CRC check so far, they were received at other settings.
+pub(crate) fn forget_valid_frame() {
+    VALID_FRAME.store(false, Ordering::Relaxed);
+}
+
+/// The HAL's configuration for USART1 at `config`.
+pub(crate) fn hal_config(config: &SerialConfig) -> serial::config::Config {
+    serial::config::Config {
+        baudrate: config.baud.bps(),
+        // the parity bit takes the place of a ninth data bit.
+        wordlength: match config.parity {
+            Parity::None => serial::config::WordLength::DataBits8,
+            _ => serial::config::WordLength::DataBits9,
+        },
+        parity: match config.parity {
+            Parity::None => serial::config::Parity::ParityNone,
+            Parity::Even => serial::config::Parity::ParityEven,
+            Parity::Odd => serial::config::Parity::ParityOdd,
+        },
+        stopbits: match config.stop_bits {
+            StopBits::One => serial::config::StopBits::STOP1,
+            StopBits::Two => serial::config::StopBits::STOP2,
+        },
+        dma: serial::config::DmaConfig::TxRx,
+    }
+}
+
+/// Replies with USART1's settings, after accepting `requested` settings if given.
+///
+/// Accepted settings are acknowledged at the current settings, and only switched to once the
+/// acknowledgement has been sent.
+pub(crate) fn write_serial_config(
+    mut ctx: write_serial_config::Context,
+    request_id: Option<u8>,
+    requested: Option<SerialConfig>,
+) {
+    let payload = ctx.shared.serial.lock(|link: &mut SerialLink| {
+        let idle = link.pending.is_none() && link.fallback.is_none();
+        match requested {
+            Some(config) if idle && supports(&config, link.pclk2) => {
+                info!("switching USART1 to {:?}", config);
+                link.pending = Some(Switch::Trial(config));
+                SerialConfigPacket {
+                    config,
+                    switching: true,
+                }
+            }
+            Some(config) => {
+                warn!("rejected USART1 settings {:?}", config);
+                SerialConfigPacket {
+                    config: link.active,
+                    switching: false,
+                }
+            }
+            None => SerialConfigPacket {
+                config: link.active,
+                switching: false,
+            },
+        }
+    });
+
+    let switching = payload.switching;
+    let send = ctx.shared.send;
+    let queue = ctx.shared.tx_queue;
+    let result = ctx
+        .shared
+        .crc
+        .lock(|crc: &mut Crc32| {
+            transmit(&Message::SerialConfig(payload), request_id, Priority::Reply, send, queue, crc)
+        });
+    if let Err(e) = result {
+        error!("failed to transmit serial config {:?}", e);
+        // the host never heard of the switch, so don't make it.
+        ctx.shared.serial.lock(|link: &mut SerialLink| link.pending = None);
+        return;
+    }
+    if switching {
+        if let Err(e) = apply_serial_config::spawn() {
+            error!("failed to spawn serial config switch {:?}", e);
+        }
+    }
+}
+
+/// Switches USART1 to the pending settings, once everything queued has left the wire.
+pub(crate) fn apply_serial_config(mut ctx: apply_serial_config::Context) {
+    let queue: &mut TxQueue = ctx.shared.tx_queue;
+    // NOTE(safety): atomic read with no side effects.
+    let transmitting = unsafe { (*USART1::ptr()).sr.read().tc().bit_is_clear() };
+    let drained = matches!(ctx.shared.send, Some(TxBufferState::Idle(_)))
+        && queue.is_empty()
+        && !transmitting;
+    if !drained {
+        let _ = apply_serial_config::spawn_after(Milliseconds(DRAIN_POLL_MS));
+        return;
+    }
+
+    let switched = ctx.shared.serial.lock(|link: &mut SerialLink| {
+        let config = match link.pending.take() {
+            Some(Switch::Trial(config)) => {
+                link.fallback = Some(link.active);
+                forget_valid_frame();
+                if let Err(e) = serial_fallback::spawn_after(Milliseconds(SERIAL_FALLBACK_MS)) {
+                    error!("failed to schedule serial fallback {:?}", e);
+                }
+                config
+            }
+            Some(Switch::Revert(config)) => config,
+            None => return false,
+        };
+        link.active = config;
+        // SAFETY: nothing is being transmitted, and a byte being received is lost either way.
+        unsafe { configure_usart1(&config, link.pclk2) };
+        true
+    });
+    if switched {
+        // stale bytes could otherwise complete a frame that passes as received at the new settings.
+        (ctx.shared.recv, ctx.shared.rx_state, ctx.shared.rx_ring).lock(
+            |transfer: &mut Usart1TransferRx, state: &mut RxState, ring: &mut RxRing| {
+                discard_received(transfer, state, ring)
+            },
+        );
+    }
+}
+
+/// Keeps the trial settings if a valid frame arrived at them, otherwise falls back.
+/// Kept settings are persisted, so they also apply after the next reset.
+pub(crate) fn serial_fallback(mut ctx: serial_fallback::Context) {
+    let confirmed = VALID_FRAME.load(Ordering::Relaxed);
+    let reverting = ctx.shared.serial.lock(|link: &mut SerialLink| match link.fallback.take() {
+        None => false,
+        Some(_) if confirmed => {
+            info!("USART1 settings {:?} confirmed.", link.active);
+            crate::settings::store_serial_config(&link.active);
+            false
+        }
+        Some(fallback) => {
+            warn!("no valid frame at {:?}, falling back to {:?}", link.active, fallback);
+            link.pending = Some(Switch::Revert(fallback));
+            true
+        }
+    });
+    if reverting {
+        if let Err(e) = apply_serial_config::spawn() {
+            error!("failed to spawn serial config fallback {:?}", e);
+        }
+    }
+}
+
+/// Reconfigures USART1's line settings in place, leaving its DMA configuration alone.
+/// SAFETY:
+/// read/modify/write cycles. The USART is briefly disabled, so nothing may be in flight.
+unsafe fn configure_usart1(config: &SerialConfig, pclk2: u32) {
+    let usart = &*USART1::ptr();
+    usart.cr1.modify(|_, w| w.ue().clear_bit());
+    // oversampling by 16, so the divisor's fraction is its lowest nibble.
+    let divisor = (pclk2 + config.baud / 2) / config.baud;
+    usart.brr.write(|w| w.bits(divisor));
+    let parity = config.parity != Parity::None;
+    usart.cr1.modify(|_, w| {
+        // the parity bit takes the place of a ninth data bit.
+        w.m()
+            .bit(parity)
+            .pce()
+            .bit(parity)
+            .ps()
+            .bit(config.parity == Parity::Odd)
+    });
+    usart.cr2.modify(|_, w| {
+        w.stop().bits(match config.stop_bits {
+            StopBits::One => 0b00,
+            StopBits::Two => 0b10,
+        })
+    });
+    usart.cr1.modify(|_, w| w.ue().set_bit());
+}
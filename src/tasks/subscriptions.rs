@@ -0,0 +1,101 @@
+use rtic::mutex_prelude::*;
+use rtic::time::duration::Milliseconds;
+use stm32f4xx_hal::crc32::Crc32;
+
+use crate::app::{
+    publish_topics, write_link_errors, write_subscription, write_telemetry, write_telemetry_batch,
+    MAX_MESSAGE_SIZE,
+};
+use crate::datamodel::subscription::{Subscription, Topic};
+use crate::hal::Monotonic;
+use crate::tasks::serial_config::SerialLink;
+use crate::tasks::usart1_tx::{link, transmit, Priority};
+use turret_device::batch::Batcher;
+use turret_device::hal::Clock;
+use turret_device::schedule::{self, Scheduler};
+use turret_protocol::envelope::Message;
+
+/// How often the publisher checks which topics are due, the shortest period a topic is sent at.
+pub(crate) const PUBLISH_TICK_MS: u32 = schedule::MIN_PERIOD_MS;
+
+/// Starts, changes or stops streaming a topic, and replies with the period granted.
+///
+/// The period is fitted into the bandwidth of USART1's current settings and frame size, which
+/// later switches don't revisit.
+pub(crate) fn write_subscription(
+    mut ctx: write_subscription::Context,
+    request_id: Option<u8>,
+    requested: Subscription,
+) {
+    let line_rate = ctx
+        .shared
+        .serial
+        .lock(|serial: &mut SerialLink| schedule::line_rate(&serial.active()));
+    let mut buffer = [0u8; MAX_MESSAGE_SIZE];
+    let codec = crate::codec::active();
+    let granted = match schedule::wire_bytes(&codec, requested.topic, &link(), &mut buffer) {
+        Some(wire_bytes) => {
+            let now = Monotonic.now_ms();
+            ctx.shared.subscriptions.lock(|subscriptions: &mut Scheduler| {
+                subscriptions.subscribe(requested, wire_bytes, line_rate, now)
+            })
+        }
+        None => {
+            error!("can't size {:?}'s packets, not streaming it", requested.topic);
+            Subscription {
+                topic: requested.topic,
+                period_ms: 0,
+            }
+        }
+    };
+    match (requested.period_ms, granted.period_ms) {
+        (0, _) => info!("stopped streaming {:?}", granted.topic),
+        (asked, given) if asked == given => info!("streaming {:?} every {}ms", granted.topic, given),
+        _ => warn!("asked for {:?}, granted {:?}", requested, granted),
+    }
+    // the sampler only buffers samples while someone is listening.
+    if granted.topic == Topic::TelemetryBatch {
+        ctx.shared.batcher.lock(|batcher: &mut Batcher| match granted.period_ms {
+            0 => batcher.stop(),
+            _ => batcher.start(),
+        });
+    }
+
+    let send = ctx.shared.send;
+    let queue = ctx.shared.tx_queue;
+    let result = ctx
+        .shared
+        .crc
+        .lock(|crc: &mut Crc32| {
+            transmit(&Message::Subscription(granted), request_id, Priority::Reply, send, queue, crc)
+        });
+    if let Err(e) = result {
+        error!("failed to transmit subscription {:?}", e);
+    }
+}
+
+/// Periodically spawns the writers of the topics that are due, most overdue first.
+///
+/// Topics are queued at telemetry priority, behind any pending reply, and when that queue is
+/// full their oldest frames are evicted while replies are kept. A topic whose previous message
+/// is still being written skips this period.
+pub(crate) fn publish_topics(mut ctx: publish_topics::Context) {
+    let now = Monotonic.now_ms();
+    while let Some(topic) = ctx
+        .shared
+        .subscriptions
+        .lock(|subscriptions: &mut Scheduler| subscriptions.next_due(now))
+    {
+        let spawned = match topic {
+            Topic::Telemetry => write_telemetry::spawn(None).is_ok(),
+            Topic::LinkErrors => write_link_errors::spawn(None).is_ok(),
+            Topic::TelemetryBatch => write_telemetry_batch::spawn().is_ok(),
+        };
+        if !spawned {
+            warn!("{:?} is still being written, skipping it", topic);
+        }
+    }
+
+    // rescheduling can't fail, this task is the only one that spawns itself.
+    let _ = publish_topics::spawn_after(Milliseconds(PUBLISH_TICK_MS));
+}
@@ -1,98 +1,292 @@
+use heapless::spsc::Queue;
 use rtic::mutex_prelude::*;
-use rtt_target::rprintln;
-use stm32f4xx_hal::dma::{traits::*, Stream2};
+use stm32f4xx_hal::dma::{traits::*, CurrentBuffer, Stream2};
 use stm32f4xx_hal::stm32::{DMA2, USART1};
 
 use crate::app::{
-    on_usart1_idle, on_usart1_rx_dma, Usart1Buf, Usart1TransferRx, {BUF_SIZE, MESSAGE_SIZE},
+    on_usart1_idle, on_usart1_rx_dma, process_rx, Usart1Buf, Usart1TransferRx,
+    {BUF_SIZE, MAX_MESSAGE_SIZE, MESSAGE_SIZE},
 };
-use crate::datamodel::{request::Request, rx_errors::RxError};
+use crate::datamodel::{
+    error::ErrorCode, link_errors::LinkErrorsPacket, request::{Request, RequestKind},
+    rx_errors::RxError,
+};
+use crate::hal::HwCrc;
+use crate::tasks::usart1_tx::link;
+use crate::tasks::watchdog::{Checkin, Liveness};
 use crate::tasks::TxBufferState;
-use core::convert::TryInto;
+use turret_device::rx::{Received, Receiver};
+use core::sync::atomic::{AtomicBool, Ordering};
 use core::ops::Index;
 use stm32f4xx_hal::crc32::Crc32;
 
-/// Handles the DMA transfer complete Interrupt
-pub(crate) fn on_usart1_rx_dma(_ctx: on_usart1_rx_dma::Context) {
-    rprintln!("DMA error occured!");
+/// Capacity of the ring between the RX DMA and the packet processor, must hold a few frames.
+pub(crate) const RX_RING_SIZE: usize = 4 * BUF_SIZE;
+
+/// Bytes received over USART1, waiting to be processed.
+pub(crate) type RxRing = Queue<u8, RX_RING_SIZE>;
+
+/// Splits the bytes received over USART1 into frames of at most `MESSAGE_SIZE` bytes, and joins
+/// their fragments into messages of at most `MAX_MESSAGE_SIZE` bytes.
+pub(crate) type Usart1Receiver = Receiver<MESSAGE_SIZE, MAX_MESSAGE_SIZE>;
+
+/// Set when USART1 switched settings, until `process_rx` dropped the partial frame it held.
+static DISCARD_PARTIAL: AtomicBool = AtomicBool::new(false);
+
+/// Bookkeeping for the never-stopping RX DMA.
+pub struct RxState {
+    /// how many bytes of the buffer the DMA is currently filling have already been drained.
+    consumed: usize,
 }
 
-/// handles USART1 IDLE interrupt
-/// This fires when the host starts sending data but then stops
-/// before the transfer completes (e.g. sends 12 bytes when BUF_SIZE > 12).
-pub(crate) fn on_usart1_idle(ctx: on_usart1_idle::Context) {
-    rprintln!("RX line fell idle, packet recv'ed.");
-    // acquire lock to shared resources, then call the actual handler.
-    (ctx.shared.recv, ctx.shared.crc).lock(|transfer: &mut Usart1TransferRx, crc: &mut Crc32| {
-        handle_rx(transfer, crc);
-    });
+impl RxState {
+    pub const fn new() -> Self {
+        Self { consumed: 0 }
+    }
 }
 
-/// Actually handles the received packet, regardless of its source.
-fn handle_rx(transfer: &mut Usart1TransferRx, crc: &mut Crc32) {
-    let remaining_transfers = Stream2::<DMA2>::get_number_of_transfers() as usize;
-    let bytes_transfered = BUF_SIZE - remaining_transfers;
+/// Handles the DMA2 stream 2 interrupt.
+/// In double-buffer mode this fires whenever one buffer is full and the DMA switched to the other.
+/// It also fires on DMA errors, which are counted and recovered from.
+pub(crate) fn on_usart1_rx_dma(mut ctx: on_usart1_rx_dma::Context) {
+    /*
+    Fetch any DMA errors that might have occured.
+    For some reason these arn't exposed on the transfer interface so we need to fetch them
+    directly from the source.
 
-    rprintln!(
-        "RX dma remaining transfers := {},bytes transfered:={}",
-        remaining_transfers,
-        bytes_transfered
-    );
+    NOTE(safety): atomic reads with no side effects.
+     */
+    let direct_mode_error = Stream2::<DMA2>::get_direct_mode_error_flag();
+    let transfer_error = Stream2::<DMA2>::get_transfer_error_flag();
+    let fifo_error = Stream2::<DMA2>::get_fifo_error_flag();
+    if direct_mode_error || transfer_error || fifo_error {
+        error!(
+            "DMA transfer error occured! direct mode:={},transfer:={},fifo:={}",
+            direct_mode_error,
+            transfer_error,
+            fifo_error
+        );
+        ctx.shared.link_errors.lock(|errors: &mut LinkErrorsPacket| {
+            count(&mut errors.dma_direct_mode, direct_mode_error);
+            count(&mut errors.dma_transfer, transfer_error);
+            count(&mut errors.dma_fifo, fifo_error);
+        });
+        // SAFETY: only clears stream 2's error flags, leaving transfer complete to the drain.
+        unsafe { clear_rx_dma_error_flags() };
+    }
 
-    let mut packet = [0u8; BUF_SIZE];
-    // NOTE(unsafe): only unsafe in the event of a overrun in double-buffer mode.
-    if let Err(e) = unsafe {
-        // set up the next transfer, and copy the previous transfer to a different buffer for
-        // further processing
-        transfer.next_transfer_with(|buf, _current_buffer| {
-            rprintln!("Buf current reads := {:?}", buf);
-            let len = buf.len();
-            /*
-            Fetch any DMA errors that might have occured.
-            For some reason these arn't exposed on the transfer interface so we need to fetch them
-            directly from the source.
-
-            NOTE(safety): atomic reads with no side effects.
-             */
-            let direct_mode_error = Stream2::<DMA2>::get_direct_mode_error_flag();
-            let transfer_error = Stream2::<DMA2>::get_transfer_error_flag();
-            let fifo_error = Stream2::<DMA2>::get_fifo_error_flag();
-            if direct_mode_error || transfer_error || fifo_error {
-                rprintln!(
-                    "DMA transfer error occured! direct mode:={},transfer:={},fifo:={}",
-                    direct_mode_error,
-                    transfer_error,
-                    fifo_error
-                );
+    let restarted = (ctx.shared.recv, ctx.shared.rx_state, ctx.shared.rx_ring).lock(
+        |transfer: &mut Usart1TransferRx, state: &mut RxState, ring: &mut RxRing| {
+            drain_rx_dma(transfer, state, ring);
+            // a transfer error disables the stream, nothing else restarts it.
+            if Stream2::<DMA2>::is_enabled() {
+                return false;
             }
-            // Copy DMA buffer to a different location so DMA can be restarted while
-            // we process the packet.
-            packet.copy_from_slice(buf);
-            (buf, len)
-        })
-    } {
-        rprintln!("something went horribly wrong in DMA reconfig! {:?}", e);
-        transfer.clear_interrupts();
-        unsafe { clear_idle_interrupt() };
-        return;
+            warn!("RX DMA stream stopped, restarting it.");
+            // SAFETY: the stream is disabled, so the DMA no longer touches the buffers.
+            unsafe {
+                restart_rx_dma();
+                recover_usart1();
+            }
+            state.consumed = 0;
+            true
+        },
+    );
+    if restarted {
+        ctx.shared
+            .link_errors
+            .lock(|errors: &mut LinkErrorsPacket| count(&mut errors.rx_restarts, true));
     }
-    // Now that the RXed buffer is copied into our local buffer, and the DMA is reconfigured
-    //
-    let result = if bytes_transfered > MESSAGE_SIZE {
-        rprintln!("Someone sent a bigger message frame than allowed.");
-        Err(RxError::BufferOverflow)
-    } else {
-        process_mabie_packet(&packet, crc)
-    };
-    if let Err(e) = result {
-        rprintln!(
-            "[ERROR] Something went horribly wrong processing packet {:?}!",
-            e
+    // a burst longer than a buffer may hold complete frames, don't wait for the line to idle.
+    let _ = process_rx::spawn();
+}
+
+/// handles USART1 IDLE interrupt
+/// This fires when the host starts sending data but then stops.
+/// Packets are delimited by their sentinel rather than by the line falling idle, but this is when
+/// the host most likely finished one.
+///
+/// It also fires on overrun, framing, noise and parity errors, which are counted and cleared.
+/// The corrupted frame then fails its CRC, and the deframer resynchronises on the next sentinel.
+///
+/// In RS-485 mode it also fires when a transmission completes, to release the bus.
+pub(crate) fn on_usart1_idle(mut ctx: on_usart1_idle::Context) {
+    // NOTE(safety): atomic read, the flags are only cleared by the DR read further down.
+    let status = unsafe { (*USART1::ptr()).sr.read() };
+    let overrun = status.ore().bit_is_set();
+    let framing = status.fe().bit_is_set();
+    let noise = status.nf().bit_is_set();
+    let parity = status.pe().bit_is_set();
+
+    #[cfg(feature = "rs485")]
+    {
+        let released = crate::rs485::on_transmission_complete();
+        // reading DR below would steal a byte from the RX DMA, only do so when it's needed.
+        if released && !(status.idle().bit_is_set() || overrun || framing || noise || parity) {
+            return;
+        }
+    }
+
+    if overrun || framing || noise || parity {
+        warn!(
+            "USART1 RX error! overrun:={},framing:={},noise:={},parity:={}",
+            overrun,
+            framing,
+            noise,
+            parity
         );
+        ctx.shared.link_errors.lock(|errors: &mut LinkErrorsPacket| {
+            count(&mut errors.overrun, overrun);
+            count(&mut errors.framing, framing);
+            count(&mut errors.noise, noise);
+            count(&mut errors.parity, parity);
+        });
+    } else {
+        debug!("RX line fell idle, packet recv'ed.");
     }
 
-    transfer.clear_interrupts();
+    // acquire lock to shared resources, then drain whatever the DMA wrote since the last time.
+    (ctx.shared.recv, ctx.shared.rx_state, ctx.shared.rx_ring).lock(
+        |transfer: &mut Usart1TransferRx, state: &mut RxState, ring: &mut RxRing| {
+            drain_rx_dma(transfer, state, ring);
+        },
+    );
+    // the same read sequence clears the error flags along with IDLE.
     unsafe { clear_idle_interrupt() };
+    if overrun || framing || noise || parity {
+        // SAFETY: only re-asserts the receiver's enable bits, the configuration is untouched.
+        unsafe { recover_usart1() };
+    }
+
+    // hand the bytes to the processor.
+    // If a processor is already pending it will pick up these bytes too.
+    let _ = process_rx::spawn();
+}
+
+/// Drops the bytes received at USART1's previous settings, which are garbage at the new ones:
+/// those in the DMA buffer and the ring right away, the partial frame `process_rx` holds before
+/// it handles another byte. Call once USART1 runs at its new settings.
+pub(crate) fn discard_received(
+    transfer: &mut Usart1TransferRx,
+    state: &mut RxState,
+    ring: &mut RxRing,
+) {
+    drain_rx_dma(transfer, state, ring);
+    while ring.dequeue().is_some() {}
+    DISCARD_PARTIAL.store(true, Ordering::Relaxed);
+    let _ = process_rx::spawn();
+}
+
+/// Moves every byte the DMA wrote since the last drain into the ring, without stopping the DMA.
+///
+/// The DMA runs in double-buffer mode: it fills one buffer, switches to the other and raises the
+/// transfer complete flag, then carries on. We drain the completed buffer when we see that flag,
+/// and otherwise drain the front of the buffer it is currently filling.
+fn drain_rx_dma(transfer: &mut Usart1TransferRx, state: &mut RxState, ring: &mut RxRing) {
+    loop {
+        if Stream2::<DMA2>::get_transfer_complete_flag() {
+            let consumed = state.consumed;
+            // SAFETY: in double-buffer mode the closure is handed the buffer the DMA just
+            //   completed, while the DMA fills the other one. Memory corruption only occurs if
+            //   the DMA also completes the other buffer and switches back before we return,
+            //   which takes BUF_SIZE byte times (~5.5ms at 115200 baud). Copying at most
+            //   BUF_SIZE bytes into the ring takes microseconds, and this handler is never
+            //   preempted by anything that runs that long.
+            //   The HAL also checks for that overrun after the closure, and reports it as an error.
+            let result = unsafe {
+                transfer.next_transfer_with(|buf, _| {
+                    push_bytes(ring, &buf[consumed..]);
+                    (buf, ())
+                })
+            };
+            if let Err(e) = result {
+                error!("RX DMA overran while draining a completed buffer! {:?}", e);
+            }
+            state.consumed = 0;
+            // the DMA may have completed the next buffer meanwhile, check again.
+            continue;
+        }
+
+        let current = Stream2::<DMA2>::current_buffer();
+        // NDTR never exceeds the buffer, but a bogus read mustn't take the board down.
+        let written = BUF_SIZE.saturating_sub(Stream2::<DMA2>::get_number_of_transfers() as usize);
+        if Stream2::<DMA2>::get_transfer_complete_flag() {
+            // the DMA switched buffers under us, so `written` might describe either buffer.
+            continue;
+        }
+
+        // SAFETY: the HAL owns the buffers but doesn't let us peek at the one being filled, so
+        //   fetch its address from the stream. The DMA only writes at and after offset
+        //   `written`, so the bytes before it are stable until the buffer is completed and
+        //   drained above.
+        let active = unsafe {
+            let stream = &(*DMA2::ptr()).st[2];
+            let address = match current {
+                CurrentBuffer::FirstBuffer => stream.m0ar.read().bits(),
+                CurrentBuffer::DoubleBuffer => stream.m1ar.read().bits(),
+            };
+            core::slice::from_raw_parts(address as *const u8, written)
+        };
+        if written > state.consumed {
+            push_bytes(ring, &active[state.consumed..]);
+            state.consumed = written;
+        }
+        return;
+    }
+}
+
+/// Pushes bytes into the ring, dropping whatever doesn't fit.
+fn push_bytes(ring: &mut RxRing, bytes: &[u8]) {
+    for (i, byte) in bytes.iter().enumerate() {
+        if ring.enqueue(*byte).is_err() {
+            warn!(
+                "RX ring is full, dropped {} bytes. Packets are arriving faster than they are processed.",
+                bytes.len() - i
+            );
+            return;
+        }
+    }
+}
+
+/// Feeds the received bytes through the receiver, and handles every frame they complete.
+/// Partial frames are kept by the receiver until the rest arrives.
+pub(crate) fn process_rx(mut ctx: process_rx::Context) {
+    let receiver: &mut Usart1Receiver = ctx.local.receiver;
+    loop {
+        if DISCARD_PARTIAL.swap(false, Ordering::Relaxed) {
+            receiver.discard_partial();
+            // a frame completed by bytes from before the switch doesn't confirm it.
+            crate::tasks::serial_config::forget_valid_frame();
+        }
+        let byte = (&mut ctx.shared.rx_ring, &mut ctx.shared.liveness).lock(
+            |ring: &mut RxRing, liveness: &mut Liveness| {
+                let byte = ring.dequeue();
+                if byte.is_none() {
+                    // the received bytes were consumed, let the watchdog supervisor know.
+                    liveness.check_in(Checkin::Rx);
+                }
+                byte
+            },
+        );
+        let byte = match byte {
+            Some(byte) => byte,
+            None => return,
+        };
+
+        let codec = crate::codec::active();
+        let link = link();
+        let received = match ctx
+            .shared
+            .crc
+            .lock(|crc: &mut Crc32| receiver.push(byte, &codec, &link, &mut HwCrc(crc)))
+        {
+            None => continue,
+            Some(received) => received,
+        };
+        if let Err(e) = handle_frame(received, receiver) {
+            error!("Something went horribly wrong processing packet {:?}!", e);
+        }
+    }
 }
 
 /*
@@ -109,118 +303,250 @@ pub(crate) unsafe fn clear_idle_interrupt() {
 }
 
 #[inline]
-/// Enables USART1's IDLE interrupt
+/// Enables USART1's IDLE interrupt, and its overrun, framing, noise and parity error interrupts.
 /// SAFETY:
 /// read/modify/write cycle
-pub(crate) unsafe fn enable_idle_interrupt() {
-    (*USART1::ptr()).cr1.modify(|_, w| w.idleie().set_bit());
+pub(crate) unsafe fn enable_usart1_interrupts() {
+    (*USART1::ptr())
+        .cr1
+        .modify(|_, w| w.idleie().set_bit().peie().set_bit());
+    // in DMA mode, overrun, framing and noise errors only interrupt with EIE set.
+    (*USART1::ptr()).cr3.modify(|_, w| w.eie().set_bit());
 }
 
-fn process_mabie_packet(input_buffer: &[u8], crc: &mut Crc32) -> Result<(), RxError> {
-    let mut buffer: [u8; BUF_SIZE] = [0; BUF_SIZE];
+/// Makes sure USART1 is still receiving into the DMA after an error.
+/// SAFETY:
+/// read/modify/write cycles, only ever setting bits init already set.
+unsafe fn recover_usart1() {
+    let usart = &*USART1::ptr();
+    usart.cr3.modify(|_, w| w.dmar().set_bit().eie().set_bit());
+    usart
+        .cr1
+        .modify(|_, w| w.ue().set_bit().re().set_bit().idleie().set_bit().peie().set_bit());
+}
 
-    let mut decoder = postcard_cobs::CobsDecoder::new(&mut buffer);
+/// Clears DMA2 stream 2's error flags.
+/// SAFETY:
+/// LIFCR is write-one-to-clear, so this doesn't disturb the other streams' flags.
+unsafe fn clear_rx_dma_error_flags() {
+    (*DMA2::ptr()).lifcr.write(|w| {
+        w.cteif2()
+            .set_bit()
+            .cdmeif2()
+            .set_bit()
+            .cfeif2()
+            .set_bit()
+    });
+}
 
-    // decode the COBS frame into the buffer
-    if let Ok(n) = match decoder.push(input_buffer) {
-        Ok(None) => {
-            rprintln!("[ERROR] Decoder demanded more bytes than we can feed it.");
-            Err(RxError::CobsDecoderNeededMoreBytes)
+/// Restarts the RX DMA stream from the start of its first buffer.
+/// The HAL keeps owning the buffers, they are only handed back to the hardware.
+/// SAFETY:
+/// The stream must be disabled, and nothing may be borrowing the DMA buffers.
+unsafe fn restart_rx_dma() {
+    let stream = &(*DMA2::ptr()).st[2];
+    // in case it was still winding down.
+    while stream.cr.read().en().bit_is_set() {}
+    (*DMA2::ptr()).lifcr.write(|w| {
+        w.ctcif2()
+            .set_bit()
+            .chtif2()
+            .set_bit()
+            .cteif2()
+            .set_bit()
+            .cdmeif2()
+            .set_bit()
+            .cfeif2()
+            .set_bit()
+    });
+    stream.ndtr.write(|w| w.ndt().bits(BUF_SIZE as u16));
+    stream.cr.modify(|_, w| w.ct().clear_bit());
+    stream.cr.modify(|_, w| w.en().set_bit());
+}
+
+/// Bumps an error counter if the error occurred, saturating rather than wrapping.
+fn count(counter: &mut u32, occurred: bool) {
+    if occurred {
+        *counter = counter.saturating_add(1);
+    }
+}
+
+/// Handles what a complete frame held: spawns the task answering a complete request, and
+/// replies with an error to messages that can't be handled.
+fn handle_frame(
+    received: Result<Received, RxError>,
+    receiver: &mut Usart1Receiver,
+) -> Result<(), RxError> {
+    match received {
+        // meant for another board on the bus, or a reply.
+        Ok(Received::Ignored) => return Ok(()),
+        // a frame for us that passed its CRC, whatever it holds.
+        Ok(_) | Err(RxError::Fragment(_)) => crate::tasks::serial_config::note_valid_frame(),
+        Err(_) => {}
+    }
+    match received? {
+        Received::Ignored | Received::Fragment => Ok(()),
+        // understood at any version, it's how the host finds out which versions we speak.
+        Received::Hello { request_id, offer } => {
+            crate::app::write_hello::spawn(request_id, offer).map_err(|e| {
+                error!("failed to spawn hello writer with err {:?}", e);
+                reply_error(request_id, ErrorCode::Busy);
+                RxError::FailedReplySpawn
+            })
         }
-        Ok(Some((message_length, _))) => {
-            rprintln!("Decode successful, decoded {} bytes.", message_length);
-            rprintln!("un-COBS'ed := {:?}", buffer);
-            Ok(message_length)
+        Received::Subscription {
+            request_id,
+            requested,
+        } => crate::app::write_subscription::spawn(request_id, requested).map_err(|e| {
+            error!("failed to spawn subscription writer with err {:?}", e);
+            reply_error(request_id, ErrorCode::Busy);
+            RxError::FailedReplySpawn
+        }),
+        Received::NodeAddress {
+            request_id,
+            requested,
+        } => crate::app::write_node_address::spawn(request_id, requested.address).map_err(|e| {
+            error!("failed to spawn node address writer with err {:?}", e);
+            reply_error(request_id, ErrorCode::Busy);
+            RxError::FailedReplySpawn
+        }),
+        Received::Request {
+            request_id,
+            request,
+            fresh,
+        } => {
+            debug!("successfully deserialized request {:?}", request);
+            if !fresh {
+                debug!("request {:?} is a retransmission, not executing it again", request.seq);
+            }
+            dispatch_request(request_id, request, fresh, receiver)
         }
-        Err(j) => {
-            rprintln!("[ERROR] Decoder errored after {} bytes.", j);
-            Err(RxError::CobsDecoderError(j))
+        Received::Rejected {
+            request_id,
+            code,
+            error,
+        } => {
+            error!("can't handle message, replying {:?}", code);
+            reply_error(request_id, code);
+            Err(error)
         }
-    } {
-        if n<5{
-            return Err(RxError::CobsDecoderNeededMoreBytes)
-        };
-        // NOTE: this somehow solves an off-by-one error.
-        let n = n - 1;
-        // If decoding succeeded, then fetch the sender CRC.
-        let crc_bytes = &buffer[n - 4..n];
-        rprintln!("crc buffer := {:?}", crc_bytes);
-        let sender_crc = u32::from_be_bytes(
-            crc_bytes
-                .try_into()
-                .expect("failed to interpret sender CRC as a u32!"),
-        );
-        // Then compute the device CRC.
-        let data = &mut buffer[..n - 4];
-        rprintln!("computing sender CRC with data length {}", data.len());
-        let device_crc = compute_crc(data, crc);
-
-        // Ensure the two match..
-        if sender_crc != device_crc {
-            rprintln!(
-                "[ERROR] Sender CRC {} != Device CRC {}",
-                sender_crc,
-                device_crc
-            );
-            Err(RxError::InvalidSenderCrc)
-        } else {
-            rprintln!("RX checksum passed.");
-            // Deserialize internal CBOR packet.
-            // Note: the data buffer needs to be mutable as an implementation detail of CBOR.
-            let request_result: serde_cbor::Result<Request> = serde_cbor::de::from_mut_slice(data);
-
-            // Check that the deserialization was successful.
-            if let Ok(request) = request_result {
-                rprintln!("successfully deserialized request {:?}", request);
-                // Spawn the telemetry worker
-                // Note: we remap the error here to our internal enum for consistancy.
-                crate::app::write_telemetry::spawn().map_err(|e| {
-                    rprintln!("[error] failed to spawn telemetry writer with err {:?}", e);
-                    RxError::FailedTelemetrySpawn
-                })?;
-                Ok(())
-            } else {
-                rprintln!("[error] failed to deserialize well-formed packet!");
-                Err(RxError::FailedDeserialize)
+    }
+}
+
+/// Spawns the task answering `request`.
+/// Requests that can't be handled get an error reply instead.
+/// Reliable requests are acknowledged, and their side effects skipped if they aren't `fresh`.
+fn dispatch_request(
+    request_id: Option<u8>,
+    request: Request,
+    fresh: bool,
+    receiver: &mut Usart1Receiver,
+) -> Result<(), RxError> {
+    // replies are sent for duplicates too, only the side effects are skipped.
+    // Spawn the worker that answers this kind of request.
+    // Note: we remap the error here to our internal enum for consistancy.
+    let spawned = match request.kind {
+        RequestKind::Default | RequestKind::Telemetry => {
+            crate::app::write_telemetry::spawn(request_id).map_err(|e| {
+                error!("failed to spawn telemetry writer with err {:?}", e);
+                RxError::FailedTelemetrySpawn
+            })
+        }
+        RequestKind::ResetCause => {
+            crate::app::write_reset_cause::spawn(request_id).map_err(|e| {
+                error!("failed to spawn reset cause writer with err {:?}", e);
+                RxError::FailedReplySpawn
+            })
+        }
+        RequestKind::CrashRecord => {
+            crate::app::write_crash_record::spawn(request_id).map_err(|e| {
+                error!("failed to spawn crash record writer with err {:?}", e);
+                RxError::FailedReplySpawn
+            })
+        }
+        RequestKind::LogLevel => {
+            if let Some(level) = request.log_level.filter(|_| fresh) {
+                info!("log level set to {:?}", level);
+                crate::logging::set_level(level);
             }
+            crate::app::write_log_level::spawn(request_id).map_err(|e| {
+                error!("failed to spawn log level writer with err {:?}", e);
+                RxError::FailedReplySpawn
+            })
         }
-    } else {
-        Err(RxError::CobsDecoderPushFailed)
+        RequestKind::LinkErrors => {
+            crate::app::write_link_errors::spawn(request_id).map_err(|e| {
+                error!("failed to spawn link errors writer with err {:?}", e);
+                RxError::FailedReplySpawn
+            })
+        }
+        RequestKind::SerialConfig => {
+            let requested = request.serial.filter(|_| fresh);
+            crate::app::write_serial_config::spawn(request_id, requested).map_err(|e| {
+                error!("failed to spawn serial config writer with err {:?}", e);
+                RxError::FailedReplySpawn
+            })
+        }
+        RequestKind::Zero => {
+            if fresh {
+                info!("zeroing the turret position.");
+                crate::tasks::zero_turret_position();
+            }
+            crate::app::write_telemetry::spawn(request_id).map_err(|e| {
+                error!("failed to spawn telemetry writer with err {:?}", e);
+                RxError::FailedTelemetrySpawn
+            })
+        }
+        RequestKind::Reboot => {
+            // a retransmission arriving after the reset is new again, so hosts don't retransmit.
+            crate::app::write_reboot::spawn(request_id).map_err(|e| {
+                error!("failed to spawn reboot writer with err {:?}", e);
+                RxError::FailedReplySpawn
+            })
+        }
+        RequestKind::SaveConfig => {
+            if fresh {
+                let level = crate::logging::level();
+                info!("saving log level {:?}", level);
+                crate::settings::store_log_level(level);
+            }
+            crate::app::write_log_level::spawn(request_id).map_err(|e| {
+                error!("failed to spawn log level writer with err {:?}", e);
+                RxError::FailedReplySpawn
+            })
+        }
+    };
+    if let Err(e) = spawned {
+        reply_error(request_id, ErrorCode::Busy);
+        return Err(e);
+    }
+
+    // only acknowledge requests that were handled, so failed ones get retransmitted.
+    if let Some(ack) = receiver.acknowledge(&request, fresh) {
+        crate::app::write_ack::spawn(request_id, ack).map_err(|e| {
+            error!("failed to spawn ack writer with err {:?}", e);
+            RxError::FailedReplySpawn
+        })?
     }
+    Ok(())
 }
 
-/// computes the CRC-32(ethernet) of the provided data buffer.
-/// Note: this uses the CRC32 peripheral, which only operates on u32 words.
-///     For the sake of simplicity, the input buffer is truncated to the nearest word boundry,
-///     and the resulting smaller buffer is then fed to the peripheral.
-pub(crate) fn compute_crc(buffer: &[u8], crc: &mut Crc32) -> u32 {
-    // Reset the peripheral.
-    crc.init();
-    let payload_size = buffer.len();
-    let remainder = payload_size % 4;
-    let total_words = payload_size / 4;
-    if remainder != 0 {
-        rprintln!("input data (length {}) was not word-aligned, truncating to {} bytes for calculation...", buffer.len(), total_words*4)
+/// Tells the host its request couldn't be handled, as far as that's possible.
+fn reply_error(request_id: Option<u8>, code: ErrorCode) {
+    if let Err(e) = crate::app::write_error::spawn(request_id, code) {
+        error!("failed to spawn error writer with err {:?}", e);
     }
-    // truncate to the word boundry
-    let buffer = &buffer[0..total_words * 4];
-    let chunks = buffer.chunks_exact(4);
-
-    rprintln!(
-        "checksumming {} bytes of a {} byte payload across {} words.",
-        buffer.len(),
-        payload_size,
-        chunks.len()
-    );
-    rprintln!("buffer := {:?}", buffer);
-    let mut result: u32 = 0;
-    chunks.for_each(|chunk| {
-        let word = u32::from_be_bytes(chunk.try_into().expect("unexpected misalligned word."));
-        rprintln!("feeding word {:x}", word);
-        result = crc.update(&[word])
-    });
+}
 
-    rprintln!("computed CRC := {}", result);
+/// Set once a host agreed on CRCs covering the whole frame, see `turret_protocol::hello`.
+static FULL_CRC: AtomicBool = AtomicBool::new(false);
+
+/// Switches between CRCs covering the whole frame, and only its whole words.
+pub(crate) fn set_full_crc(full: bool) {
+    FULL_CRC.store(full, Ordering::Relaxed);
+}
 
-    result
+/// Whether CRCs cover the whole frame, rather than only its whole words.
+pub(crate) fn full_crc() -> bool {
+    FULL_CRC.load(Ordering::Relaxed)
 }
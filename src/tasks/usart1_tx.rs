@@ -1,8 +1,95 @@
-use crate::app::on_usart1_txe;
+use crate::app::{
+    on_usart1_txe, tx_timeout, Usart1TransferTx, BUF_SIZE, MAX_MESSAGE_SIZE, NODE_ADDRESS,
+};
+use crate::datamodel::link_errors::LinkErrorsPacket;
+use crate::datamodel::tx_errors::TxError;
+use crate::hal::HwCrc;
+use crate::tasks::usart1_rx::full_crc;
+use crate::tasks::watchdog::{Checkin, Liveness};
 use crate::tasks::TxBufferState;
-use rtt_target::rprintln;
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use embedded_dma::ReadTarget;
+use rtic::mutex_prelude::*;
+use rtic::time::duration::Milliseconds;
+use stm32f4xx_hal::crc32::Crc32;
+use stm32f4xx_hal::stm32::DMA2;
+use turret_device::hal::SerialTx;
+use turret_device::tx::encode;
+pub use turret_device::tx::Priority;
+use turret_device::LinkConfig;
+use turret_protocol::envelope::Message;
+use turret_protocol::fragment::max_chunk_len;
+use turret_protocol::hello;
 
-pub(crate) fn on_usart1_txe(ctx: on_usart1_txe::Context) {
+/// Smallest frame size a host may agree on, see `turret_protocol::hello`.
+const MIN_FRAME_SIZE: usize = if BUF_SIZE < hello::MIN_FRAME_SIZE {
+    BUF_SIZE
+} else {
+    hello::MIN_FRAME_SIZE
+};
+/// Most frames a single message can take, at the smallest frame size.
+const MAX_FRAGMENTS: usize = (MAX_MESSAGE_SIZE - 1) / max_chunk_len(MIN_FRAME_SIZE) + 1;
+/// Capacity of each priority's queue, which holds up to `TX_QUEUE_SIZE - 1` frames.
+/// Enough for one whole message of the largest size, and then some.
+pub(crate) const TX_QUEUE_SIZE: usize = MAX_FRAGMENTS + 2;
+/// Size of the frames sent, `BUF_SIZE` unless a host agreed on smaller ones.
+static FRAME_SIZE: AtomicUsize = AtomicUsize::new(BUF_SIZE);
+/// This board's address on the bus, `NODE_ADDRESS` unless a host moved it.
+static NODE: AtomicU8 = AtomicU8::new(NODE_ADDRESS);
+
+/// How long a transfer may run before it is considered stalled.
+/// A whole frame at 9600 baud, the slowest rate we run at, plus some margin.
+const TX_TIMEOUT_MS: u32 = 20 + (BUF_SIZE as u32 * 10 * 1_000) / 9_600;
+
+/// USART1's TX DMA buffer, holding a copy of the frame being sent.
+///
+/// The DMA sends only the first `len` bytes, so no padding follows the frame's sentinel on the
+/// wire.
+pub struct TxBuffer {
+    bytes: [u8; BUF_SIZE],
+    len: usize,
+}
+
+impl TxBuffer {
+    pub const fn new() -> Self {
+        Self {
+            bytes: [0; BUF_SIZE],
+            len: 0,
+        }
+    }
+}
+
+// SAFETY: the returned slice lies within `bytes`, and lives as long as the buffer does.
+unsafe impl ReadTarget for TxBuffer {
+    type Word = u8;
+
+    fn as_read_buffer(&self) -> (*const u8, usize) {
+        (self.bytes.as_ptr(), self.len)
+    }
+}
+
+/// Frames waiting for the TX DMA, one queue per [`Priority`].
+pub type TxQueue = turret_device::tx::TxQueue<BUF_SIZE, TX_QUEUE_SIZE>;
+
+/// The TX DMA, as the serial port the queued frames are sent on.
+struct Usart1Dma<'a>(&'a mut Option<TxBufferState>);
+
+impl SerialTx for Usart1Dma<'_> {
+    fn is_busy(&self) -> bool {
+        matches!(self.0, Some(TxBufferState::Running(_)))
+    }
+
+    fn start(&mut self, frame: &[u8]) {
+        let dma_state: TxBufferState = self.0.take().expect("failed to aquire buffer state");
+        *self.0 = Some(match dma_state {
+            TxBufferState::Idle(tx) => TxBufferState::Running(start_transfer(tx, frame)),
+            // NOTE(unreachable): the queue only starts transfers while the DMA is idle.
+            running => running,
+        });
+    }
+}
+
+pub(crate) fn on_usart1_txe(mut ctx: on_usart1_txe::Context) {
     let dma_state: TxBufferState = ctx
         .shared
         .send
@@ -10,19 +97,170 @@ pub(crate) fn on_usart1_txe(ctx: on_usart1_txe::Context) {
         .expect("failed to aquire buffer state");
     match dma_state {
         TxBufferState::Running(mut tx) => {
-            rprintln!("Expected interrupt. In-flight USART1_TX completed.");
+            debug!("Expected interrupt. In-flight USART1_TX completed.");
 
             // turns out DMA doesn't clean up its own interrupts, so we have to do so ourselves.
             tx.clear_transfer_complete_interrupt();
-            // nothing left to transfer, pause DMA.
+            // pause DMA until we know whether anything else is queued.
             tx.pause(|_| {});
             *ctx.shared.send = Some(TxBufferState::Idle(tx));
+            // the TX state machine made progress, let the watchdog supervisor know.
+            ctx.shared
+                .liveness
+                .lock(|liveness: &mut Liveness| liveness.check_in(Checkin::Tx));
         }
         TxBufferState::Idle(mut tx) => {
             // this shouldn't happen.
-            rprintln!("[ERROR] DMA shouldn't be firing interrupts while we are idle.");
+            error!("DMA shouldn't be firing interrupts while we are idle.");
             tx.pause(|_| {});
             *ctx.shared.send = Some(TxBufferState::Idle(tx))
         }
     }
+    // send whatever queued up while the last transfer was in flight.
+    start_next(ctx.shared.send, ctx.shared.tx_queue);
+    // otherwise hand the bus back once the last byte is out.
+    #[cfg(feature = "rs485")]
+    if matches!(ctx.shared.send, Some(TxBufferState::Idle(_))) {
+        crate::rs485::release_when_sent();
+    }
+}
+
+/// Wraps `message` in an envelope, echoing `request_id` if it replies to a request, and splits
+/// it into fragments, then queues a frame per fragment at `priority`.
+/// Each frame holds the fragment's header and chunk, followed by their CRC, COBS encoded.
+///
+/// The first frame goes out immediately if the DMA is idle, otherwise `on_usart1_txe` sends the
+/// frames once every more important frame has been sent.
+pub(crate) fn transmit(
+    message: &Message,
+    request_id: Option<u8>,
+    priority: Priority,
+    send: &mut Option<TxBufferState>,
+    queue: &mut TxQueue,
+    crc: &mut Crc32,
+) -> Result<(), TxError> {
+    // declare a buffer to fit the response in
+    let mut payload_buffer: [u8; MAX_MESSAGE_SIZE] = [0x00; MAX_MESSAGE_SIZE];
+    // serialize payload, anything bigger than MAX_MESSAGE_SIZE fails here.
+    let payload = encode(&crate::codec::active(), message, request_id, &mut payload_buffer)?;
+    trace!("payload := {:?}", payload);
+    queue.push(payload, priority, &link(), &mut HwCrc(crc))?;
+
+    start_next(send, queue);
+    Ok(())
+}
+
+/// How frames are addressed, sized and checksummed, as agreed with the host.
+pub(crate) fn link() -> LinkConfig {
+    LinkConfig {
+        address: node_address(),
+        frame_size: FRAME_SIZE.load(Ordering::Relaxed),
+        full_crc: full_crc(),
+    }
+}
+
+/// This board's address on the bus, which frames are sent from and received at.
+pub(crate) fn node_address() -> u8 {
+    NODE.load(Ordering::Relaxed)
+}
+
+/// Moves this board to `address`, for the frames sent and received from now on.
+pub(crate) fn set_node_address(address: u8) {
+    NODE.store(address, Ordering::Relaxed);
+}
+
+/// Switches the size of the frames sent, within what the TX queues are sized for.
+pub(crate) fn set_frame_size(size: usize) {
+    FRAME_SIZE.store(size.max(MIN_FRAME_SIZE).min(BUF_SIZE), Ordering::Relaxed);
+}
+
+/// Starts sending the most important queued frame, unless a transfer is already in flight.
+/// Every transfer started is supervised by a [`tx_timeout`].
+fn start_next(send: &mut Option<TxBufferState>, queue: &mut TxQueue) {
+    if let Some(transfer) = queue.start_next(&mut Usart1Dma(send)) {
+        if let Err(e) = tx_timeout::spawn_after(Milliseconds(TX_TIMEOUT_MS), transfer) {
+            warn!("failed to schedule TX timeout for transfer {:?}", e);
+        }
+    }
+}
+
+/// Copies `frame` into the DMA buffer and starts the transfer, which is sized to the frame.
+fn start_transfer(mut tx: Usart1TransferTx, frame: &[u8]) -> Usart1TransferTx {
+    debug!("DMA was idle, setting up next transfer...");
+    #[cfg(feature = "rs485")]
+    crate::rs485::claim_bus();
+    // SAFETY: memory corruption can occur in double-buffer mode in the event of an overrun.
+    //   - we are in single-buffer mode so this is safe.
+    unsafe {
+        // We re-use the existing DMA buffer, since the buffer has to live for 'static
+        // in order to be safe. This was ensured during creation of the Transfer object,
+        // so this is safe.
+        tx.next_transfer_with(|buf, _| {
+            // populate the DMA buffer with the frame, the transfer length is read back from it.
+            buf.bytes[..frame.len()].copy_from_slice(frame);
+            buf.len = frame.len();
+            // report the frame's length, if only to satisfy the closure's contract.
+            let buf_len = buf.len;
+            (buf, buf_len) // Don't know what the second argument is, but it seems to be ignored.
+        })
+        .expect("Something went horribly wrong setting up the transfer.");
+    }
+    debug!("TX scheduled.");
+    tx
+}
+
+/// Fires `TX_TIMEOUT_MS` after `transfer` started. If that transfer still hasn't completed, its
+/// completion interrupt was missed: abort it, return the DMA to idle and carry on with the queue.
+///
+/// Timeouts for transfers that completed in time find the DMA idle, or a later transfer running,
+/// and do nothing.
+pub(crate) fn tx_timeout(mut ctx: tx_timeout::Context, transfer: u32) {
+    let queue: &mut TxQueue = ctx.shared.tx_queue;
+    if !queue.stalled(&Usart1Dma(ctx.shared.send), transfer) {
+        return;
+    }
+
+    error!("USART1 TX transfer {} stalled, aborting it.", transfer);
+    let dma_state: TxBufferState = ctx
+        .shared
+        .send
+        .take()
+        .expect("failed to aquire buffer state");
+    if let TxBufferState::Running(mut tx) = dma_state {
+        // disable the stream, cutting the stalled frame short.
+        tx.pause(|_| {});
+        // SAFETY: the stream is disabled, and this only clears stream 7's flags.
+        unsafe { clear_tx_dma_flags() };
+        *ctx.shared.send = Some(TxBufferState::Idle(tx));
+        // the USART may never finish the frame, don't wait for it.
+        #[cfg(feature = "rs485")]
+        crate::rs485::release_bus();
+    } else {
+        *ctx.shared.send = Some(dma_state);
+    }
+    ctx.shared.link_errors.lock(|errors: &mut LinkErrorsPacket| {
+        errors.tx_timeouts = errors.tx_timeouts.saturating_add(1);
+    });
+
+    // the receiver discards the cut-short frame, send the next one.
+    start_next(ctx.shared.send, queue);
+}
+
+/// Clears every DMA2 stream 7 interrupt flag, so the aborted transfer can't raise a late
+/// completion interrupt.
+/// SAFETY:
+/// HIFCR is write-one-to-clear, so this doesn't disturb the other streams' flags.
+unsafe fn clear_tx_dma_flags() {
+    (*DMA2::ptr()).hifcr.write(|w| {
+        w.ctcif7()
+            .set_bit()
+            .chtif7()
+            .set_bit()
+            .cteif7()
+            .set_bit()
+            .cdmeif7()
+            .set_bit()
+            .cfeif7()
+            .set_bit()
+    });
 }
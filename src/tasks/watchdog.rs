@@ -0,0 +1,133 @@
+use rtic::mutex_prelude::*;
+use rtic::time::duration::Milliseconds;
+use stm32f4xx_hal::dma::{traits::*, Stream2};
+use stm32f4xx_hal::stm32::{DMA2, RCC};
+use stm32f4xx_hal::{crc32::Crc32, prelude::*};
+
+use crate::app::{feed_watchdog, write_reset_cause};
+use crate::datamodel::reset_cause::{ResetCause, ResetCausePacket};
+use crate::tasks::usart1_rx::RxRing;
+use crate::tasks::usart1_tx::{transmit, Priority};
+use turret_protocol::envelope::Message;
+use crate::tasks::TxBufferState;
+
+/// How often the supervisor checks whether every critical task has reported in.
+pub(crate) const WATCHDOG_TICK_MS: u32 = 250;
+/// IWDG expiry, must span several supervisor ticks so one slow tick doesn't reset the device.
+pub(crate) const WATCHDOG_TIMEOUT_MS: u32 = 1_000;
+
+/// Critical tasks that must check in before the watchdog may be fed.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy)]
+pub enum Checkin {
+    /// The USART1 TX DMA state machine returned to idle.
+    Tx = 1 << 0,
+    /// Bytes received over USART1 were processed.
+    Rx = 1 << 1,
+}
+
+/// Record of which critical tasks have checked in since the watchdog was last fed.
+pub struct Liveness {
+    reported: u8,
+}
+
+impl Liveness {
+    const ALL: u8 = Checkin::Tx as u8 | Checkin::Rx as u8;
+
+    pub const fn new() -> Self {
+        Self { reported: 0 }
+    }
+
+    pub fn check_in(&mut self, task: Checkin) {
+        self.reported |= task as u8;
+    }
+
+    /// Returns whether every critical task reported, and starts a new reporting period.
+    fn take_all_reported(&mut self) -> bool {
+        let all_reported = self.reported & Self::ALL == Self::ALL;
+        if all_reported {
+            self.reported = 0;
+        }
+        all_reported
+    }
+}
+
+/// Periodic supervisor, only feeds the IWDG once every critical task has checked in.
+///
+/// Tasks that have nothing outstanding are checked in on their behalf, so an idle link doesn't
+/// reset the device:
+/// - TX reports if its DMA is idle, otherwise the transfer has to complete.
+/// - RX reports if its DMA is still running and nothing is waiting in the ring,
+///   otherwise the received bytes have to be processed.
+pub(crate) fn feed_watchdog(mut ctx: feed_watchdog::Context) {
+    let tx_idle = matches!(ctx.shared.send, Some(TxBufferState::Idle(_)));
+    let rx_idle = Stream2::<DMA2>::is_enabled()
+        && ctx.shared.rx_ring.lock(|ring: &mut RxRing| ring.is_empty());
+
+    let all_reported = ctx.shared.liveness.lock(|liveness| {
+        if tx_idle {
+            liveness.check_in(Checkin::Tx);
+        }
+        if rx_idle {
+            liveness.check_in(Checkin::Rx);
+        }
+        liveness.take_all_reported()
+    });
+
+    if all_reported {
+        ctx.local.watchdog.feed();
+    } else {
+        warn!("a critical task has not checked in, withholding watchdog feed.");
+    }
+
+    if let Err(e) = feed_watchdog::spawn_after(Milliseconds(WATCHDOG_TICK_MS)) {
+        error!("failed to reschedule watchdog supervisor {:?}", e);
+    }
+}
+
+/// Replies with the cause of the last reset.
+pub(crate) fn write_reset_cause(mut ctx: write_reset_cause::Context, request_id: Option<u8>) {
+    let payload = ResetCausePacket {
+        cause: *ctx.local.reset_cause,
+    };
+    let send = ctx.shared.send;
+    let queue = ctx.shared.tx_queue;
+    let result = ctx
+        .shared
+        .crc
+        .lock(|crc: &mut Crc32| {
+            transmit(&Message::ResetCause(payload), request_id, Priority::Reply, send, queue, crc)
+        });
+    if let Err(e) = result {
+        error!("failed to transmit reset cause {:?}", e);
+    }
+}
+
+/// Reads the latched reset flags, then clears them so the next reset reports afresh.
+/// Must be called before the RCC is constrained.
+pub(crate) fn read_reset_cause(rcc: &RCC) -> ResetCause {
+    let csr = rcc.csr.read();
+    // every reset also latches the pin flag, and a power-on reset the brown-out flag too, so the
+    // flags only set by one cause are checked first: the watchdogs, low-power and software resets,
+    // whose flags can't be stale since they're cleared below at every boot. Power-on goes before
+    // brown-out and pin, which it implies.
+    let cause = if csr.wdgrstf().bit_is_set() {
+        ResetCause::IndependentWatchdog
+    } else if csr.wwdgrstf().bit_is_set() {
+        ResetCause::WindowWatchdog
+    } else if csr.lpwrrstf().bit_is_set() {
+        ResetCause::LowPower
+    } else if csr.sftrstf().bit_is_set() {
+        ResetCause::Software
+    } else if csr.porrstf().bit_is_set() {
+        ResetCause::PowerOn
+    } else if csr.borrstf().bit_is_set() {
+        ResetCause::BrownOut
+    } else if csr.padrstf().bit_is_set() {
+        ResetCause::Pin
+    } else {
+        ResetCause::Unknown
+    };
+    rcc.csr.modify(|_, w| w.rmvf().set_bit());
+    cause
+}
@@ -0,0 +1,22 @@
+use rtic::mutex_prelude::*;
+use stm32f4xx_hal::crc32::Crc32;
+
+use crate::app::write_ack;
+use crate::datamodel::ack::AckPacket;
+use crate::tasks::usart1_tx::{transmit, Priority};
+use turret_protocol::envelope::Message;
+
+/// Acknowledges a reliable request.
+pub(crate) fn write_ack(mut ctx: write_ack::Context, request_id: Option<u8>, ack: AckPacket) {
+    let send = ctx.shared.send;
+    let queue = ctx.shared.tx_queue;
+    let result = ctx
+        .shared
+        .crc
+        .lock(|crc: &mut Crc32| {
+            transmit(&Message::Ack(ack), request_id, Priority::Reply, send, queue, crc)
+        });
+    if let Err(e) = result {
+        error!("failed to transmit ack {:?}", e);
+    }
+}
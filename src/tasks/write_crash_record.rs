@@ -0,0 +1,41 @@
+use rtic::mutex_prelude::*;
+use stm32f4xx_hal::crc32::Crc32;
+
+use crate::app::{write_crash_record, MAX_MESSAGE_SIZE};
+use crate::datamodel::crash_record::{CrashKind, CrashRecordPacket};
+use crate::tasks::usart1_tx::{transmit, Priority};
+use turret_device::crash;
+use turret_protocol::envelope::Message;
+
+/// Replies with the crash record preserved across the last reset, if any.
+pub(crate) fn write_crash_record(mut ctx: write_crash_record::Context, request_id: Option<u8>) {
+    let payload = match ctx.local.crash_record {
+        Some(record) => record.as_packet(),
+        None => CrashRecordPacket {
+            kind: CrashKind::None,
+            file: "",
+            line: 0,
+            pc: 0,
+            lr: 0,
+            cfsr: 0,
+            hfsr: 0,
+            mmfar: 0,
+            bfar: 0,
+            msg: "",
+        },
+    };
+    // a long panic message could otherwise make the whole reply too large to send.
+    let mut buffer = [0u8; MAX_MESSAGE_SIZE];
+    let payload = crash::fit(&crate::codec::active(), payload, request_id, &mut buffer);
+    let send = ctx.shared.send;
+    let queue = ctx.shared.tx_queue;
+    let result = ctx
+        .shared
+        .crc
+        .lock(|crc: &mut Crc32| {
+            transmit(&Message::CrashRecord(payload), request_id, Priority::Reply, send, queue, crc)
+        });
+    if let Err(e) = result {
+        error!("failed to transmit crash record {:?}", e);
+    }
+}
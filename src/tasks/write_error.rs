@@ -0,0 +1,20 @@
+use rtic::mutex_prelude::*;
+use stm32f4xx_hal::crc32::Crc32;
+
+use crate::app::write_error;
+use crate::datamodel::error::{ErrorCode, ErrorPacket};
+use crate::tasks::usart1_tx::{transmit, Priority};
+use turret_protocol::envelope::Message;
+
+/// Replies that a request couldn't be handled.
+pub(crate) fn write_error(mut ctx: write_error::Context, request_id: Option<u8>, code: ErrorCode) {
+    let payload = ErrorPacket { code };
+    let send = ctx.shared.send;
+    let queue = ctx.shared.tx_queue;
+    let result = ctx.shared.crc.lock(|crc: &mut Crc32| {
+        transmit(&Message::Error(payload), request_id, Priority::Reply, send, queue, crc)
+    });
+    if let Err(e) = result {
+        error!("failed to transmit error reply {:?}", e);
+    }
+}
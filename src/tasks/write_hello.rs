@@ -0,0 +1,68 @@
+use rtic::mutex_prelude::*;
+use stm32f4xx_hal::crc32::Crc32;
+
+use crate::app::{write_hello, BUF_SIZE};
+use crate::codec::{self, Codec};
+use crate::tasks::usart1_rx::set_full_crc;
+use crate::tasks::usart1_tx::{set_frame_size, transmit, Priority, TxQueue};
+use turret_device::batch::Batcher;
+use turret_device::schedule::Scheduler;
+use turret_protocol::envelope::{Message, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use turret_protocol::hello::{Hello, FULL_CRC};
+
+/// What this firmware supports.
+fn supported() -> Hello {
+    Hello {
+        min_version: MIN_PROTOCOL_VERSION,
+        max_version: PROTOCOL_VERSION,
+        codecs: codec::PREFERENCE.iter().fold(0, |codecs, codec| codecs | codec),
+        flags: FULL_CRC,
+        max_frame: BUF_SIZE as u16,
+    }
+}
+
+/// Agrees on a protocol version and capabilities with the host's `offer`.
+///
+/// The agreement is sent at the current settings, and only switched to once it is queued.
+/// Without an agreement, the reply holds what this firmware supports, and nothing changes.
+/// An agreement starts a new session, which ends every subscription.
+pub(crate) fn write_hello(mut ctx: write_hello::Context, request_id: Option<u8>, offer: Hello) {
+    let supported = supported();
+    let agreed = supported.negotiate(&offer, codec::PREFERENCE);
+    let reply = match agreed {
+        Some(agreed) => agreed,
+        None => {
+            warn!("nothing in common with the host's hello {:?}", offer);
+            supported
+        }
+    };
+
+    let send = ctx.shared.send;
+    let queue: &mut TxQueue = ctx.shared.tx_queue;
+    let result = ctx.shared.crc.lock(|crc: &mut Crc32| {
+        transmit(&Message::Hello(reply), request_id, Priority::Reply, send, queue, crc)
+    });
+    if let Err(e) = result {
+        error!("failed to transmit hello {:?}", e);
+        // the host never heard of the agreement, so don't switch.
+        return;
+    }
+
+    if let Some(agreed) = agreed {
+        info!("agreed on {:?} with the host", agreed);
+        // NOTE(unwrap): `negotiate` only picks codecs from our preference.
+        codec::set_active(Codec::from_hello_bit(agreed.codecs).unwrap());
+        set_full_crc(agreed.flags & FULL_CRC != 0);
+        set_frame_size(agreed.max_frame as usize);
+        // queued before the reply but sent after it, at settings the host no longer expects.
+        let stale = queue.discard(Priority::Telemetry) + queue.discard(Priority::Log);
+        if stale > 0 {
+            debug!("discarded {} frames encoded before the hello", stale);
+        }
+        // sized for the old frames, and made by a host that no longer expects them.
+        ctx.shared
+            .subscriptions
+            .lock(|subscriptions: &mut Scheduler| subscriptions.clear());
+        ctx.shared.batcher.lock(|batcher: &mut Batcher| batcher.stop());
+    }
+}
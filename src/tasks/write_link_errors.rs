@@ -0,0 +1,31 @@
+use rtic::mutex_prelude::*;
+use stm32f4xx_hal::crc32::Crc32;
+
+use crate::app::write_link_errors;
+use crate::datamodel::link_errors::LinkErrorsPacket;
+use crate::tasks::usart1_tx::{transmit, Priority};
+use turret_protocol::envelope::Message;
+
+/// Replies with the USART1 and DMA error counters, or streams them without a `request_id`.
+pub(crate) fn write_link_errors(mut ctx: write_link_errors::Context, request_id: Option<u8>) {
+    let payload = ctx
+        .shared
+        .link_errors
+        .lock(|errors: &mut LinkErrorsPacket| *errors);
+    // streamed counters are stale once newer ones are queued, like telemetry.
+    let priority = match request_id {
+        Some(_) => Priority::Reply,
+        None => Priority::Telemetry,
+    };
+    let send = ctx.shared.send;
+    let queue = ctx.shared.tx_queue;
+    let result = ctx
+        .shared
+        .crc
+        .lock(|crc: &mut Crc32| {
+            transmit(&Message::LinkErrors(payload), request_id, priority, send, queue, crc)
+        });
+    if let Err(e) = result {
+        error!("failed to transmit link errors {:?}", e);
+    }
+}
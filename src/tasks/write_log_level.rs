@@ -0,0 +1,25 @@
+use rtic::mutex_prelude::*;
+use stm32f4xx_hal::crc32::Crc32;
+
+use crate::app::write_log_level;
+use crate::datamodel::log_level::LogLevelPacket;
+use crate::tasks::usart1_tx::{transmit, Priority};
+use turret_protocol::envelope::Message;
+
+/// Replies with the current runtime log level.
+pub(crate) fn write_log_level(mut ctx: write_log_level::Context, request_id: Option<u8>) {
+    let payload = LogLevelPacket {
+        level: crate::logging::level(),
+    };
+    let send = ctx.shared.send;
+    let queue = ctx.shared.tx_queue;
+    let result = ctx
+        .shared
+        .crc
+        .lock(|crc: &mut Crc32| {
+            transmit(&Message::LogLevel(payload), request_id, Priority::Reply, send, queue, crc)
+        });
+    if let Err(e) = result {
+        error!("failed to transmit log level {:?}", e);
+    }
+}
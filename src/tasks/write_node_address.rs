@@ -0,0 +1,40 @@
+use rtic::mutex_prelude::*;
+use stm32f4xx_hal::crc32::Crc32;
+
+use crate::app::write_node_address;
+use crate::datamodel::node_address::NodeAddress;
+use crate::tasks::usart1_tx::{node_address, set_node_address, transmit, Priority};
+use turret_protocol::envelope::Message;
+
+/// Replies with this board's address on the bus, after moving it to `requested` if given.
+///
+/// The reply's frames are built before the move, so they still carry the old address and the
+/// host hears them. The new address is stored straight away, a board that forgot it on its next
+/// reset would be lost to the host.
+pub(crate) fn write_node_address(
+    mut ctx: write_node_address::Context,
+    request_id: Option<u8>,
+    requested: Option<u8>,
+) {
+    let payload = NodeAddress {
+        address: Some(requested.unwrap_or_else(node_address)),
+    };
+    let send = ctx.shared.send;
+    let queue = ctx.shared.tx_queue;
+    let result = ctx
+        .shared
+        .crc
+        .lock(|crc: &mut Crc32| {
+            transmit(&Message::NodeAddress(payload), request_id, Priority::Reply, send, queue, crc)
+        });
+    if let Err(e) = result {
+        // the host didn't hear about the move, so don't make it.
+        error!("failed to transmit node address {:?}", e);
+        return;
+    }
+    if let Some(address) = requested {
+        crate::settings::store_node_address(address);
+        set_node_address(address);
+        info!("node address := {}", address);
+    }
+}